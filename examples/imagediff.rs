@@ -0,0 +1,63 @@
+extern crate raytracer;
+
+use std::env;
+use std::process;
+use raytracer::bmp::*;
+
+// Compares two images of the same size (BMP or PNG), printing how many pixels differ and by how much, and optionally
+// writing an image of the differences, scaled up so that they can be seen:
+//
+//	cargo run --release --example imagediff -- a.bmp b.bmp [diff.bmp]
+
+fn main() {
+	let args: Vec<String> = env::args().skip(1).collect();
+	if args.len() < 2 || args.len() > 3 {
+		println!("Usage: imagediff <image> <image> [difference image]");
+		process::exit(2);
+	}
+	let read = |path: &String| BmpImage::read_from_file(path).unwrap_or_else(|err| {
+		println!("{}: {}", path, err);
+		process::exit(2);
+	});
+	let (a, b) = (read(&args[0]), read(&args[1]));
+	if a.width != b.width || a.height != b.height {
+		println!("The images have different sizes ({}x{} and {}x{})!", a.width, a.height, b.width, b.height);
+		process::exit(2);
+	}
+
+	// differences of each pixel, in levels of its channels (0 to 255)
+	let to_byte = |value| BmpImage::float_to_byte(value + 0.5 / 255.);
+	let mut differences = Vec::with_capacity((a.width * a.height) as usize);
+	for row in 0..a.height {
+		for col in 0..a.width {
+			let (ar, ag, ab) = a.get_pixel(row, col);
+			let (br, bg, bb) = b.get_pixel(row, col);
+			let difference = |x, y| (to_byte(x) as i32 - to_byte(y) as i32).unsigned_abs();
+			differences.push((difference(ar, br), difference(ag, bg), difference(ab, bb)));
+		}
+	}
+
+	let max_channel = |&(r, g, b): &(u32, u32, u32)| r.max(g).max(b);
+	let n_pixels = differences.len();
+	let n_different = differences.iter().filter(|difference| max_channel(difference) > 0).count();
+	let n_above_2 = differences.iter().filter(|difference| max_channel(difference) > 2).count();
+	let max_difference = differences.iter().map(max_channel).max().unwrap_or(0);
+	let mean_difference = differences.iter().map(|&(r, g, b)| (r + g + b) as f64).sum::<f64>() / (3 * n_pixels.max(1)) as f64;
+	let percent = |count: usize| 100. * count as f64 / n_pixels.max(1) as f64;
+	println!("Pixels: {}", n_pixels);
+	println!("Different: {} ({:.3}%), by more than 2 levels: {} ({:.3}%)", n_different, percent(n_different), n_above_2, percent(n_above_2));
+	println!("Largest difference: {} levels, mean difference: {:.4} levels", max_difference, mean_difference);
+
+	if let Some(path) = args.get(2) {
+		let mut image = BmpImage::new(a.width, a.height);
+		let scale = |level: u32| (level * 16).min(255) as u8;
+		for row in 0..a.height {
+			for col in 0..a.width {
+				let (r, g, b) = differences[(row * a.width + col) as usize];
+				image.set_pixel_bytes(row, col, scale(r), scale(g), scale(b));
+			}
+		}
+		image.write_to_file(path.clone());
+		println!("Differences (16 times brighter) written to {}", path);
+	}
+}
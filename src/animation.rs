@@ -0,0 +1,307 @@
+use std::rc::Rc;
+
+use float::*;
+use vec3::*;
+use mat4::*;
+use bvh::*;
+
+// Keyframe animation of the camera and of object transforms. Times are frame numbers, and values are interpolated
+// linearly between keys, except rotations, which are interpolated along the shortest arc (slerp).
+
+// ---------- Keyframes ----------
+
+#[derive(Debug,Clone)]
+pub struct Keyframes<T> {
+	pub keys: Vec<(Float, T)>,	// sorted by time
+}
+
+impl<T: Copy> Default for Keyframes<T> {
+	fn default() -> Keyframes<T> {
+		Keyframes { keys: Vec::new() }
+	}
+}
+
+impl<T: Copy> Keyframes<T> {
+	// replaces the key at the same time, if any
+	pub fn insert(&mut self, time: Float, value: T) {
+		match self.keys.iter().position(|key| key.0 >= time) {
+			Some(i) if self.keys[i].0 == time => { self.keys[i].1 = value; }
+			Some(i) => { self.keys.insert(i, (time, value)); }
+			None => { self.keys.push((time, value)); }
+		}
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.keys.is_empty()
+	}
+
+	// value at the time, interpolated between the keys around it (the value of the first or last key outside them)
+	pub fn sample<F>(&self, time: Float, interpolate: F) -> Option<T> where F: Fn(T, T, Float) -> T {
+		let next = match self.keys.iter().position(|key| key.0 > time) {
+			Some(0) => { return Some(self.keys[0].1); }
+			Some(next) => next,
+			None => { return self.keys.last().map(|key| key.1); }
+		};
+		let (time0, value0) = self.keys[next - 1];
+		let (time1, value1) = self.keys[next];
+		Some(interpolate(value0, value1, (time - time0) / (time1 - time0)))
+	}
+}
+
+fn lerp_vector(a: Vector3, b: Vector3, s: Float) -> Vector3 {
+	a + s * (b - a)
+}
+
+fn lerp_point(a: Point3, b: Point3, s: Float) -> Point3 {
+	a + s * (b - a)
+}
+
+fn lerp_float(a: Float, b: Float, s: Float) -> Float {
+	a + s * (b - a)
+}
+
+// ---------- Quaternion ----------
+
+// Rotation xi + yj + zk + w, normalized.
+#[derive(Debug,Copy,Clone)]
+pub struct Quaternion {
+	pub x: Float,
+	pub y: Float,
+	pub z: Float,
+	pub w: Float,
+}
+
+impl Quaternion {
+	pub fn identity() -> Quaternion {
+		Quaternion { x: 0., y: 0., z: 0., w: 1. }
+	}
+
+	// None if it is zero
+	pub fn new(x: Float, y: Float, z: Float, w: Float) -> Option<Quaternion> {
+		let length = Float::sqrt(x*x + y*y + z*z + w*w);
+		if length == 0. {
+			return None;
+		}
+		Some(Quaternion { x: x / length, y: y / length, z: z / length, w: w / length })
+	}
+
+	// the same rotation as Mat4::create_rotation
+	pub fn from_axis_angle(degrees: Float, axis: Vector3) -> Quaternion {
+		let axis = axis.normalize();
+		let half_angle = degrees.to_radians() / 2.;
+		let s = half_angle.sin();
+		Quaternion { x: axis.x * s, y: axis.y * s, z: axis.z * s, w: half_angle.cos() }
+	}
+
+	fn dot(&self, other: &Quaternion) -> Float {
+		self.x * other.x + self.y * other.y + self.z * other.z + self.w * other.w
+	}
+
+	// spherical interpolation along the shortest arc, so rotations between keys must be less than half a turn
+	pub fn slerp(self, other: Quaternion, s: Float) -> Quaternion {
+		let mut dot = self.dot(&other);
+		let mut other = other;
+		if dot < 0. {
+			dot = -dot;
+			other = Quaternion { x: -other.x, y: -other.y, z: -other.z, w: -other.w };
+		}
+		// nearly equal rotations are interpolated linearly, where the angle is too small to divide by its sine
+		let (s0, s1) = if dot > 0.9995 {
+			(1. - s, s)
+		} else {
+			let angle = dot.acos();
+			(((1. - s) * angle).sin() / angle.sin(), (s * angle).sin() / angle.sin())
+		};
+		Quaternion::new(
+			s0 * self.x + s1 * other.x,
+			s0 * self.y + s1 * other.y,
+			s0 * self.z + s1 * other.z,
+			s0 * self.w + s1 * other.w,
+		).unwrap_or(self)
+	}
+
+	pub fn to_matrix(&self) -> Mat4 {
+		Mat4::create_quaternion_rotation(self.x, self.y, self.z, self.w).unwrap()
+	}
+}
+
+// ---------- TransformTrack ----------
+
+// Keyframed translation, rotation and scale, applied to objects as translation * rotation * scale.
+#[derive(Debug,Clone,Default)]
+pub struct TransformTrack {
+	pub translation: Keyframes<Vector3>,
+	pub rotation: Keyframes<Quaternion>,
+	pub scale: Keyframes<Vector3>,
+}
+
+impl TransformTrack {
+	pub fn get_transform_at(&self, time: Float) -> Mat4 {
+		let mut transform = Mat4::id(1.);
+		if let Some(t) = self.translation.sample(time, lerp_vector) {
+			transform = transform * Mat4::create_translation(t.x, t.y, t.z);
+		}
+		if let Some(rotation) = self.rotation.sample(time, Quaternion::slerp) {
+			transform = transform * rotation.to_matrix();
+		}
+		if let Some(s) = self.scale.sample(time, lerp_vector) {
+			transform = transform * Mat4::create_scale(s.x, s.y, s.z);
+		}
+		transform
+	}
+}
+
+// ---------- AnimatedTransform ----------
+
+#[derive(Debug,Clone)]
+pub enum TransformFactor {
+	Fixed(Mat4),
+	Track(Rc<TransformTrack>),
+}
+
+// Transform made of fixed matrices and animation tracks, multiplied in order, as the transform stack composes them.
+#[derive(Debug,Clone)]
+pub struct AnimatedTransform {
+	pub factors: Vec<TransformFactor>,
+}
+
+impl AnimatedTransform {
+	pub fn fixed(transform: Mat4) -> AnimatedTransform {
+		AnimatedTransform { factors: vec![TransformFactor::Fixed(transform)] }
+	}
+
+	pub fn is_animated(&self) -> bool {
+		self.factors.iter().any(|factor| match *factor { TransformFactor::Track(_) => true, TransformFactor::Fixed(_) => false })
+	}
+
+	// the matrix is applied to the objects before the transform, like the transform commands do
+	pub fn apply(&mut self, matrix: Mat4) {
+		if let Some(&mut TransformFactor::Fixed(ref mut last)) = self.factors.last_mut() {
+			*last = *last * matrix;
+			return;
+		}
+		self.factors.push(TransformFactor::Fixed(matrix));
+	}
+
+	pub fn animate(&mut self, track: Rc<TransformTrack>) {
+		self.factors.push(TransformFactor::Track(track));
+	}
+
+	pub fn get_transform_at(&self, time: Float) -> Mat4 {
+		let mut factors = self.factors.iter().map(|factor| match *factor {
+			TransformFactor::Fixed(matrix) => matrix,
+			TransformFactor::Track(ref track) => track.get_transform_at(time),
+		});
+		let first = factors.next().unwrap_or_else(|| Mat4::id(1.));
+		factors.fold(first, |transform, factor| transform * factor)
+	}
+}
+
+// ---------- Motion ----------
+
+// number of steps in which the bounds of a moving graphic are swept over the shutter interval
+const SWEEP_STEPS: usize = 16;
+
+// Movement of a graphic while the shutter is open, for motion blur. The graphic stays where it was put for the frame,
+// and rays at other times are moved to it instead, as if space moved the other way.
+#[derive(Debug,Clone)]
+pub struct Motion {
+	pub transform: AnimatedTransform,
+	pub frame_transform: Mat4,	// at the time of the frame
+	pub frame_inverse_transform: Mat4,
+}
+
+impl Motion {
+	// None if the transform is singular at the time of the frame
+	pub fn new(transform: AnimatedTransform, frame_time: Float) -> Option<Motion> {
+		let frame_transform = transform.get_transform_at(frame_time);
+		let frame_inverse_transform = frame_transform.invert()?;
+		Some(Motion {
+			transform: transform,
+			frame_transform: frame_transform,
+			frame_inverse_transform: frame_inverse_transform,
+		})
+	}
+
+	// matrices taking world space from where the graphic is for the frame to where it is at the time, and back
+	// (None if its transform is singular at the time, when it cannot be hit)
+	pub fn get_correction_at(&self, time: Float) -> Option<(Mat4, Mat4)> {
+		let transform = self.transform.get_transform_at(time);
+		let inverse_transform = transform.invert()?;
+		Some((transform * self.frame_inverse_transform, self.frame_transform * inverse_transform))
+	}
+
+	// world box around the places the graphic goes through between the times, given its box for the frame: the boxes
+	// at each step are grown by half the longest move of their corners, which covers the arcs the corners make in between
+	pub fn calc_swept_bounds(&self, bounds: &Bounds, start: Float, end: Float) -> Bounds {
+		let corners = bounds.get_corners();
+		let mut swept: Option<Bounds> = None;
+		let mut previous_corners: Option<Vec<Point3>> = None;
+		let mut longest_move: Float = 0.;
+		for step in 0..SWEEP_STEPS + 1 {
+			let time = start + (end - start) * step as Float / SWEEP_STEPS as Float;
+			let to_world = match self.get_correction_at(time) {
+				Some((to_world, _)) => to_world,
+				None => { continue; }
+			};
+			let moved_corners: Vec<Point3> = corners.iter().map(|&corner| to_world.transform_point(corner)).collect();
+			if let Some(previous_corners) = previous_corners {
+				for (&corner, &previous_corner) in moved_corners.iter().zip(&previous_corners) {
+					longest_move = longest_move.max((corner - previous_corner).length());
+				}
+			}
+			previous_corners = Some(moved_corners);
+
+			let moved = bounds.transformed(&to_world);
+			swept = Some(swept.map_or(moved, |swept| swept.union(&moved)));
+		}
+		match swept {
+			Some(swept) => {
+				let margin = Vector3::new(1., 1., 1.) * (longest_move / 2.);
+				Bounds::new(swept.min - margin, swept.max + margin)
+			}
+			None => *bounds,
+		}
+	}
+}
+
+// ---------- CameraAnimation ----------
+
+// Keyframes of the camera, which keeps the values it was created with where they have none.
+#[derive(Debug,Clone,Default)]
+pub struct CameraAnimation {
+	pub position: Keyframes<Point3>,
+	pub look_at: Keyframes<Point3>,
+	pub fov_y: Keyframes<Float>,	// degrees
+}
+
+impl CameraAnimation {
+	pub fn is_empty(&self) -> bool {
+		self.position.is_empty() && self.look_at.is_empty() && self.fov_y.is_empty()
+	}
+
+	// position, look at and vertical field of view (degrees) at the time
+	pub fn sample(&self, time: Float, pos: Point3, look_at: Point3, fov_y: Float) -> (Point3, Point3, Float) {
+		(
+			self.position.sample(time, lerp_point).unwrap_or(pos),
+			self.look_at.sample(time, lerp_point).unwrap_or(look_at),
+			self.fov_y.sample(time, lerp_float).unwrap_or(fov_y),
+		)
+	}
+}
+
+// ---------- Animation ----------
+
+#[derive(Debug,Clone,Default)]
+pub struct Animation {
+	pub frames: Option<(u32, u32)>,	// first and last frame to render, if the scene is rendered as a sequence
+	pub camera: CameraAnimation,
+	pub camera_setup: (Point3, Point3, Vector3, Float),	// position, look at, up and fov_y (degrees) of the camera command
+	pub graphics: Vec<(Vec<usize>, AnimatedTransform)>,	// path to each animated graphic (CSG children included)
+}
+
+impl Animation {
+	pub fn is_animated(&self) -> bool {
+		!self.camera.is_empty() || !self.graphics.is_empty()
+	}
+}
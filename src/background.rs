@@ -0,0 +1,92 @@
+use std::mem;
+use float::*;
+use float::consts::PI;
+use structs::*;
+use vec3::*;
+use mat4::*;
+use textures::*;
+use scene_writer::*;
+
+// ---------- Background ----------
+
+// Color seen by rays that miss every object, including reflected ones.
+pub trait Background {
+	fn get_color(&self, direction: Vector3) -> Color;
+	// the scene file command that sets it
+	fn get_scene_command(&self) -> Result<String, String> {
+		Err(String::from("the background cannot be written to a scene file"))
+	}
+	// bytes taken by the background, including what it allocates
+	fn estimate_memory(&self) -> usize {
+		mem::size_of_val(self)
+	}
+}
+
+// ---------- ColorBackground ----------
+
+#[derive(Debug)]
+pub struct ColorBackground {
+	pub color: Color,
+}
+
+impl Background for ColorBackground {
+	fn get_color(&self, _direction: Vector3) -> Color {
+		self.color
+	}
+
+	fn get_scene_command(&self) -> Result<String, String> {
+		Ok(format!("background {}", format_color(self.color)))
+	}
+}
+
+// ---------- GradientBackground ----------
+
+#[derive(Debug)]
+pub struct GradientBackground {
+	pub horizon: Color,
+	pub zenith: Color,
+	pub up: Vector3,
+}
+
+impl Background for GradientBackground {
+	fn get_color(&self, direction: Vector3) -> Color {
+		// below the horizon the horizon color is kept
+		let t = direction.dot(self.up).clamp(0., 1.);
+		(1. - t) * self.horizon + t * self.zenith
+	}
+
+	fn get_scene_command(&self) -> Result<String, String> {
+		let up = to_exact_direction(self.up);
+		Ok(format!("skygradient {} {} {} {} {}", format_color(self.horizon), format_color(self.zenith), up.x, up.y, up.z))
+	}
+}
+
+// ---------- EnvironmentMap ----------
+
+// Equirectangular (latitude-longitude) map, with +y as the up direction in its own space.
+#[derive(Debug)]
+pub struct EnvironmentMap {
+	pub texture: ImageTexture,
+	pub intensity: Float,
+	pub inverse_transform: Mat4,	// from world space to the map's space
+}
+
+impl Background for EnvironmentMap {
+	fn get_color(&self, direction: Vector3) -> Color {
+		let d = self.inverse_transform.transform_vector(direction).normalize();
+
+		let uv = UV {
+			u: 0.5 + Float::atan2(d.x, -d.z) / (2. * PI),
+			v: 0.5 + Float::asin(d.y.clamp(-1., 1.)) / PI,
+		};
+		self.intensity * self.texture.get_color_at(uv, Point3::origin() + d)
+	}
+
+	fn get_scene_command(&self) -> Result<String, String> {
+		Err(String::from("environment maps cannot be written to scene files, as their file is not kept"))
+	}
+
+	fn estimate_memory(&self) -> usize {
+		mem::size_of_val(self) + self.texture.estimate_memory() - mem::size_of_val(&self.texture)
+	}
+}
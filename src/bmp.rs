@@ -1,5 +1,8 @@
 use std::fs;
-use std::io::Write;
+use std::io;
+use std::io::prelude::*;
+use float::*;
+use png::*;
 
 struct BmpPixel {
 	pub r: u8,
@@ -8,8 +11,8 @@ struct BmpPixel {
 }
 
 pub struct BmpImage {
-	width: u32,
-	height: u32,
+	pub width: u32,
+	pub height: u32,
 	data: Vec<BmpPixel>
 }
 
@@ -27,6 +30,74 @@ impl BmpImage {
 		}
 	}
 
+	/// Loads an image from a file, detecting its format (BMP or PNG) from the file contents.
+	pub fn read_from_file(file_path: &str) -> Result<BmpImage, io::Error> {
+		let mut bytes = Vec::new();
+		fs::File::open(file_path)?.read_to_end(&mut bytes)?;
+
+		if bytes.starts_with(b"BM") {
+			BmpImage::decode_bmp(&bytes)
+		} else if bytes.starts_with(&PNG_SIGNATURE) {
+			decode_png(&bytes)
+		} else {
+			Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}: unsupported image format", file_path)))
+		}
+	}
+
+	fn decode_bmp(bytes: &[u8]) -> Result<BmpImage, io::Error> {
+		let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("invalid BMP: {}", msg));
+		if bytes.len() < 54 {
+			return Err(invalid("file too short"));
+		}
+
+		let read_u16 = |at: usize| (bytes[at] as u32) | (bytes[at+1] as u32) << 8;
+		let read_u32 = |at: usize| read_u16(at) | read_u16(at+2) << 16;
+
+		let data_offset = read_u32(10) as usize;
+		let width = read_u32(18) as i32;
+		let height = read_u32(22) as i32;
+		let bits_per_pixel = read_u16(28);
+		let compression = read_u32(30);
+
+		// 32 bit images are usually stored with bitfields, which we assume to be the standard BGRA layout
+		if bits_per_pixel != 24 && bits_per_pixel != 32 {
+			return Err(invalid("only 24 and 32 bits per pixel are supported"));
+		}
+		if compression != 0 && !(compression == 3 && bits_per_pixel == 32) {
+			return Err(invalid("compressed images are not supported"));
+		}
+		if width <= 0 || height == 0 {
+			return Err(invalid("bad dimensions"));
+		}
+
+		// a negative height means rows are stored top to bottom
+		let top_down = height < 0;
+		let width = width as u32;
+		let height = height.unsigned_abs();
+		let bytes_per_pixel = (bits_per_pixel / 8) as usize;
+		let row_bits = bits_per_pixel.checked_mul(width).ok_or_else(|| invalid("image too large"))?;
+		let row_size = (row_bits.div_ceil(32) * 4) as usize;
+		let data_end = row_size.checked_mul(height as usize).and_then(|size| size.checked_add(data_offset)).ok_or_else(|| invalid("image too large"))?;
+
+		if bytes.len() < data_end {
+			return Err(invalid("pixel data is truncated"));
+		}
+
+		let mut image = BmpImage::new(width, height);
+		for file_row in 0..height {
+			let row = if top_down { file_row } else { height - 1 - file_row };
+			for col in 0..width {
+				let at = data_offset + file_row as usize * row_size + col as usize * bytes_per_pixel;
+				image.data[((height-1-row)*width+col) as usize] = BmpPixel {
+					r: bytes[at+2],
+					g: bytes[at+1],
+					b: bytes[at],
+				};
+			}
+		}
+		Ok(image)
+	}
+
 	pub fn write_to_file(&self, file_path: String) {
 		let size_of_file_header: u32 = 14;
 		let size_of_bitmap_header: u32 = 40;
@@ -37,8 +108,8 @@ impl BmpImage {
 
    		let mut stream = Vec::with_capacity(filesize as usize);
 
-		stream.push('B' as u8);
-		stream.push('M' as u8);
+		stream.push(b'B');
+		stream.push(b'M');
 		stream.push(((filesize >>  0) & 0xFF) as u8);
 		stream.push(((filesize >>  8) & 0xFF) as u8);
 		stream.push(((filesize >> 16) & 0xFF) as u8);
@@ -81,16 +152,15 @@ impl BmpImage {
 				stream.push(p.g);
 				stream.push(p.r);
 			}
-			for _ in 0..size_of_padding {
-				stream.push(0);
-			}
+			let padded_len = stream.len() + size_of_padding as usize;
+			stream.resize(padded_len, 0);
 		}
 
 		let mut file = fs::File::create(file_path).unwrap();
-		file.write(&stream).unwrap();
+		file.write_all(&stream).unwrap();
 	}
 
-	pub fn set_pixel(&mut self, row:u32, col:u32, r:f64, g:f64, b:f64) {
+	pub fn set_pixel(&mut self, row:u32, col:u32, r:Float, g:Float, b:Float) {
 		// row is inverted
 		self.data[((self.height-1-row)*self.width+col) as usize] = BmpPixel{
 			r: BmpImage::float_to_byte(r),
@@ -99,13 +169,93 @@ impl BmpImage {
 		};
 	}
 
-	pub fn float_to_byte(value: f64) -> u8 {
+	pub fn set_pixel_bytes(&mut self, row:u32, col:u32, r:u8, g:u8, b:u8) {
+		// row is inverted
+		self.data[((self.height-1-row)*self.width+col) as usize] = BmpPixel{ r: r, g: g, b: b };
+	}
+
+	pub fn get_pixel(&self, row:u32, col:u32) -> (Float, Float, Float) {
+		// row is inverted
+		let p = &self.data[((self.height-1-row)*self.width+col) as usize];
+		(p.r as Float / 255., p.g as Float / 255., p.b as Float / 255.)
+	}
+
+	pub fn float_to_byte(value: Float) -> u8 {
 		if value >= 1.0 {
 			return 255 as u8;
 		} else if value <= 0.0 {
 			return 0 as u8;
 		} else {
-			return (value * 255 as f64) as u8;
+			return (value * 255 as Float) as u8;
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::env;
+	use std::process;
+
+	fn get_bytes(image: &BmpImage, row: u32, col: u32) -> (u8, u8, u8) {
+		let (r, g, b) = image.get_pixel(row, col);
+		let to_byte = |value: Float| BmpImage::float_to_byte(value + 0.5 / 255.);
+		(to_byte(r), to_byte(g), to_byte(b))
+	}
+
+	#[test]
+	fn reads_back_written_images() {
+		// 3 pixels per row need padding
+		let mut image = BmpImage::new(3, 2);
+		for row in 0..2 {
+			for col in 0..3 {
+				image.set_pixel_bytes(row, col, (10 * row + col) as u8, 100 + col as u8, 200 + row as u8);
+			}
+		}
+		let path = env::temp_dir().join(format!("raytracer-test-{}.bmp", process::id())).to_string_lossy().into_owned();
+		image.write_to_file(path.clone());
+		let read = BmpImage::read_from_file(&path);
+		fs::remove_file(&path).unwrap();
+
+		let read = read.unwrap();
+		assert_eq!((read.width, read.height), (3, 2));
+		for row in 0..2 {
+			for col in 0..3 {
+				assert_eq!(get_bytes(&read, row, col), ((10 * row + col) as u8, 100 + col as u8, 200 + row as u8));
+			}
+		}
+	}
+
+	#[test]
+	fn decodes_top_down_32_bit_images() {
+		let mut bytes = vec![0u8; 54];
+		bytes[0] = b'B';
+		bytes[1] = b'M';
+		bytes[10] = 54;	// data offset
+		bytes[18] = 1;	// width
+		bytes[22..26].copy_from_slice(&(-2i32).to_le_bytes());	// height, rows top to bottom
+		bytes[28] = 32;
+		bytes.extend_from_slice(&[1, 2, 3, 255, 4, 5, 6, 255]);	// BGRA
+		let image = BmpImage::decode_bmp(&bytes).unwrap();
+		assert_eq!((image.width, image.height), (1, 2));
+		assert_eq!(get_bytes(&image, 0, 0), (3, 2, 1));
+		assert_eq!(get_bytes(&image, 1, 0), (6, 5, 4));
+	}
+
+	#[test]
+	fn rejects_unsupported_images() {
+		let mut bytes = vec![0u8; 54];
+		bytes[0] = b'B';
+		bytes[1] = b'M';
+		bytes[10] = 54;
+		bytes[18] = 1;
+		bytes[22] = 1;
+		bytes[28] = 8;	// palette images are not supported
+		assert!(BmpImage::decode_bmp(&bytes).is_err());
+		bytes[28] = 24;	// the pixel is missing
+		assert!(BmpImage::decode_bmp(&bytes).is_err());
+		bytes[18..22].copy_from_slice(&i32::MAX.to_le_bytes());	// the size of a row overflows
+		assert_eq!(BmpImage::decode_bmp(&bytes).err().unwrap().to_string(), "invalid BMP: image too large");
+		assert!(BmpImage::decode_bmp(&bytes[..20]).is_err());
+	}
+}
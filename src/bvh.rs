@@ -0,0 +1,381 @@
+use std::mem;
+use float::*;
+use structs::*;
+use vec3::*;
+use mat4::*;
+
+static MAX_LEAF_SIZE: usize = 4;
+// the tree is balanced, so this is enough for any number of graphics
+const MAX_DEPTH: usize = 64;
+
+// ---------- Bounds ----------
+
+// Axis-aligned bounding box.
+#[derive(Debug,Copy,Clone)]
+pub struct Bounds {
+	pub min: Point3,
+	pub max: Point3,
+}
+
+impl Bounds {
+	pub fn new(min: Point3, max: Point3) -> Bounds {
+		Bounds { min: min, max: max }
+	}
+
+	pub fn from_points(points: &[Point3]) -> Bounds {
+		let mut bounds = Bounds::new(points[0], points[0]);
+		for &point in &points[1..] {
+			bounds = bounds.union(&Bounds::new(point, point));
+		}
+		bounds
+	}
+
+	pub fn union(&self, other: &Bounds) -> Bounds {
+		Bounds {
+			min: Point3::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y), self.min.z.min(other.min.z)),
+			max: Point3::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y), self.max.z.max(other.max.z)),
+		}
+	}
+
+	pub fn intersection(&self, other: &Bounds) -> Bounds {
+		Bounds {
+			min: Point3::new(self.min.x.max(other.min.x), self.min.y.max(other.min.y), self.min.z.max(other.min.z)),
+			max: Point3::new(self.max.x.min(other.max.x), self.max.y.min(other.max.y), self.max.z.min(other.max.z)),
+		}
+	}
+
+	pub fn get_corners(&self) -> [Point3; 8] {
+		let (min, max) = (self.min, self.max);
+		[
+			Point3::new(min.x, min.y, min.z), Point3::new(max.x, min.y, min.z),
+			Point3::new(min.x, max.y, min.z), Point3::new(max.x, max.y, min.z),
+			Point3::new(min.x, min.y, max.z), Point3::new(max.x, min.y, max.z),
+			Point3::new(min.x, max.y, max.z), Point3::new(max.x, max.y, max.z),
+		]
+	}
+
+	// box containing the transformed corners, grown by their rounding error so that no hit on the surface falls outside
+	pub fn transformed(&self, transform: &Mat4) -> Bounds {
+		let corners: Vec<Point3> = self.get_corners().iter().map(|&corner| transform.transform_point(corner)).collect();
+		let bounds = Bounds::from_points(&corners);
+
+		let magnitude = [bounds.min.x, bounds.min.y, bounds.min.z, bounds.max.x, bounds.max.y, bounds.max.z].iter()
+			.fold(0., |magnitude: Float, coord| magnitude.max(coord.abs()));
+		let margin = Vector3::new(1., 1., 1.) * (16. * Float::EPSILON * (1. + magnitude));
+		Bounds::new(bounds.min - margin, bounds.max + margin)
+	}
+
+	pub fn contains(&self, point: Point3) -> bool {
+		point.x >= self.min.x && point.x <= self.max.x && point.y >= self.min.y && point.y <= self.max.y &&
+			point.z >= self.min.z && point.z <= self.max.z
+	}
+
+	pub fn calc_center(&self) -> Point3 {
+		self.min + 0.5 * (self.max - self.min)
+	}
+
+	// whether the ray passes through the box within its range; inv_direction holds the inverses of the ray's direction
+	// components, which are infinite for axis-parallel rays (the NaNs of rays lying on a slab's plane are ignored by min/max)
+	pub fn test_ray(&self, ray: &Ray, inv_direction: Vector3) -> bool {
+		let (tx0, tx1) = ((self.min.x - ray.origin.x) * inv_direction.x, (self.max.x - ray.origin.x) * inv_direction.x);
+		let (ty0, ty1) = ((self.min.y - ray.origin.y) * inv_direction.y, (self.max.y - ray.origin.y) * inv_direction.y);
+		let (tz0, tz1) = ((self.min.z - ray.origin.z) * inv_direction.z, (self.max.z - ray.origin.z) * inv_direction.z);
+
+		let mut t_near = ray.t_min;
+		let mut t_far = ray.t_max;
+		for &(t0, t1) in &[(tx0, tx1), (ty0, ty1), (tz0, tz1)] {
+			t_near = t_near.max(t0.min(t1));
+			t_far = t_far.min(t0.max(t1));
+		}
+		// the distances to the slabs are rounded, which must not make a grazing ray miss (Ize, 2013)
+		t_near <= t_far * (1. + 4. * Float::EPSILON)
+	}
+}
+
+fn get_axis(point: Point3, axis: usize) -> Float {
+	match axis {
+		0 => point.x,
+		1 => point.y,
+		_ => point.z,
+	}
+}
+
+fn calc_inv_direction(ray: &Ray) -> Vector3 {
+	Vector3::new(1. / ray.direction.x, 1. / ray.direction.y, 1. / ray.direction.z)
+}
+
+// ---------- Bvh ----------
+
+#[derive(Debug)]
+struct BvhNode {
+	bounds: Bounds,
+	first: usize,	// leaves: first entry of their graphics in the index list
+	count: usize,	// leaves: number of graphics, 0 for inner nodes
+	second_child: usize,	// inner nodes: the first child follows the node, the second one is here
+	axis: usize,	// inner nodes: axis along which the children were split
+}
+
+// Bounding volume hierarchy over the graphics of a scene, referred to by their index, and built from their world
+// bounds (see Scene::calc_graphic_bounds). Graphics without bounds, such as planes, are kept apart and tested by every ray.
+#[derive(Debug,Default)]
+pub struct Bvh {
+	nodes: Vec<BvhNode>,
+	indices: Vec<usize>,
+	unbounded: Vec<usize>,
+}
+
+impl Bvh {
+	pub fn build(graphic_bounds: &[Option<Bounds>]) -> Bvh {
+		let mut bvh: Bvh = Default::default();
+		// bounds and their centers
+		let mut entries: Vec<(usize, Bounds, Point3)> = Vec::new();
+		for (index, bounds) in graphic_bounds.iter().enumerate() {
+			match *bounds {
+				Some(bounds) => { entries.push((index, bounds, bounds.calc_center())); }
+				None => { bvh.unbounded.push(index); }
+			}
+		}
+		if !entries.is_empty() {
+			bvh.build_node(&mut entries);
+		}
+		bvh
+	}
+
+	// splits the entries at the median of their centers along the axis where the centers are most spread out
+	fn build_node(&mut self, entries: &mut [(usize, Bounds, Point3)]) {
+		let bounds = entries[1..].iter().fold(entries[0].1, |bounds, entry| bounds.union(&entry.1));
+		let node_index = self.nodes.len();
+		self.nodes.push(BvhNode { bounds: bounds, first: self.indices.len(), count: 0, second_child: 0, axis: 0 });
+
+		if entries.len() <= MAX_LEAF_SIZE {
+			self.indices.extend(entries.iter().map(|entry| entry.0));
+			self.nodes[node_index].count = entries.len();
+			return;
+		}
+
+		let centers: Vec<Point3> = entries.iter().map(|entry| entry.2).collect();
+		let spread = Bounds::from_points(&centers);
+		let size = spread.max - spread.min;
+		let axis = if size.x >= size.y && size.x >= size.z { 0 } else if size.y >= size.z { 1 } else { 2 };
+
+		let middle = entries.len() / 2;
+		entries.select_nth_unstable_by(middle, |a, b| get_axis(a.2, axis).total_cmp(&get_axis(b.2, axis)));
+		let (first_half, second_half) = entries.split_at_mut(middle);
+		self.build_node(first_half);
+		let second_child = self.nodes.len();
+		self.build_node(second_half);
+
+		let node = &mut self.nodes[node_index];
+		node.second_child = second_child;
+		node.axis = axis;
+	}
+
+	/// Updates the bounds of the nodes after graphics moved, keeping the tree as it was built, which suits small
+	/// movements (e.g. between frames of an animation). Graphics must keep being bounded, or unbounded.
+	pub fn refit(&mut self, graphic_bounds: &[Option<Bounds>]) {
+		// children come after their parent
+		for node_index in (0..self.nodes.len()).rev() {
+			let node = &self.nodes[node_index];
+			let bounds = if node.count > 0 {
+				let mut leaf_bounds = self.indices[node.first..node.first + node.count].iter().map(|&index| graphic_bounds[index].unwrap());
+				let first = leaf_bounds.next().unwrap();
+				leaf_bounds.fold(first, |bounds, graphic_bounds| bounds.union(&graphic_bounds))
+			} else {
+				self.nodes[node_index + 1].bounds.union(&self.nodes[node.second_child].bounds)
+			};
+			self.nodes[node_index].bounds = bounds;
+		}
+	}
+
+	// bytes allocated by the hierarchy
+	pub fn estimate_allocated_memory(&self) -> usize {
+		self.nodes.capacity() * mem::size_of::<BvhNode>() + (self.indices.capacity() + self.unbounded.capacity()) * mem::size_of::<usize>()
+	}
+
+	// children of an inner node, the one on the side the rays come from first
+	fn order_children(&self, node_index: usize, direction: Vector3) -> (usize, usize) {
+		let node = &self.nodes[node_index];
+		let goes_backwards = match node.axis {
+			0 => direction.x < 0.,
+			1 => direction.y < 0.,
+			_ => direction.z < 0.,
+		};
+		if goes_backwards { (node.second_child, node_index + 1) } else { (node_index + 1, node.second_child) }
+	}
+
+	/// Calls visit with the index of every graphic the ray may hit, nearest nodes first. visit can shorten the ray
+	/// (e.g. when it finds a hit), which prunes the nodes beyond it. Returns the number of nodes tested.
+	pub fn traverse<F>(&self, ray: &mut Ray, mut visit: F) -> usize where F: FnMut(usize, &mut Ray) {
+		for &index in &self.unbounded {
+			visit(index, ray);
+		}
+		if self.nodes.is_empty() {
+			return 0;
+		}
+
+		let inv_direction = calc_inv_direction(ray);
+		let mut stack = [0; MAX_DEPTH];
+		let mut stack_len = 1;
+		let mut n_visits = 0;
+		while stack_len > 0 {
+			stack_len -= 1;
+			n_visits += 1;
+			let node_index = stack[stack_len];
+			let node = &self.nodes[node_index];
+			if !node.bounds.test_ray(ray, inv_direction) {
+				continue;
+			}
+			if node.count > 0 {
+				for &index in &self.indices[node.first..node.first + node.count] {
+					visit(index, ray);
+				}
+			} else {
+				let (near, far) = self.order_children(node_index, ray.direction);
+				stack[stack_len] = far;
+				stack[stack_len + 1] = near;
+				stack_len += 2;
+			}
+		}
+		n_visits
+	}
+
+	/// Like traverse, for all the rays of a packet at once: a node is entered if any of the rays may hit it, and visit
+	/// then tests the graphic against the whole packet. The rays should be coherent, as they share the order of the nodes.
+	pub fn traverse_packet<F>(&self, packet: &mut RayPacket, mut visit: F) -> usize where F: FnMut(usize, &mut RayPacket) {
+		for &index in &self.unbounded {
+			visit(index, packet);
+		}
+		if self.nodes.is_empty() || packet.rays.is_empty() {
+			return 0;
+		}
+
+		let inv_directions: Vec<Vector3> = packet.rays.iter().map(calc_inv_direction).collect();
+		let direction = packet.rays[0].direction;
+		let mut stack = [0; MAX_DEPTH];
+		let mut stack_len = 1;
+		let mut n_visits = 0;
+		while stack_len > 0 {
+			stack_len -= 1;
+			n_visits += 1;
+			let node_index = stack[stack_len];
+			let node = &self.nodes[node_index];
+			if !packet.rays.iter().zip(&inv_directions).any(|(ray, &inv_direction)| node.bounds.test_ray(ray, inv_direction)) {
+				continue;
+			}
+			if node.count > 0 {
+				for &index in &self.indices[node.first..node.first + node.count] {
+					visit(index, packet);
+				}
+			} else {
+				let (near, far) = self.order_children(node_index, direction);
+				stack[stack_len] = far;
+				stack[stack_len + 1] = near;
+				stack_len += 2;
+			}
+		}
+		n_visits
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// unit cubes centered along the x axis at x = 0, 2, 4...
+	fn make_cube_bounds(count: usize) -> Vec<Option<Bounds>> {
+		(0..count).map(|i| {
+			let x = 2. * i as Float;
+			Some(Bounds::new(Point3::new(x - 0.5, -0.5, -0.5), Point3::new(x + 0.5, 0.5, 0.5)))
+		}).collect()
+	}
+
+	fn get_visited(bvh: &Bvh, ray: &Ray) -> Vec<usize> {
+		let mut visited = Vec::new();
+		bvh.traverse(&mut ray.clone(), |index, _| { visited.push(index); });
+		visited
+	}
+
+	#[test]
+	fn visits_the_graphics_along_the_ray() {
+		let mut graphic_bounds = make_cube_bounds(20);
+		graphic_bounds.push(None);
+		let bvh = Bvh::build(&graphic_bounds);
+
+		// the unbounded graphic first, then the cubes nearest first
+		let along = Ray::new(Point3::new(-10., 0., 0.), Vector3::new(1., 0., 0.));
+		assert_eq!(get_visited(&bvh, &along), (20..21).chain(0..20).collect::<Vec<usize>>());
+		let backwards = Ray::new(Point3::new(100., 0., 0.), Vector3::new(-1., 0., 0.));
+		let visited = get_visited(&bvh, &backwards);
+		assert_eq!(visited.len(), 21);
+		assert!(visited[1] > visited[visited.len() - 1]);
+
+		let across = Ray::new(Point3::new(10., -10., 0.), Vector3::new(0., 1., 0.));
+		let visited = get_visited(&bvh, &across);
+		assert!(visited.contains(&5) && !visited.contains(&0) && !visited.contains(&19));
+		let above = Ray::new(Point3::new(-10., 2., 0.), Vector3::new(1., 0., 0.));
+		assert_eq!(get_visited(&bvh, &above), [20]);
+	}
+
+	#[test]
+	fn prunes_nodes_beyond_shortened_rays() {
+		let bvh = Bvh::build(&make_cube_bounds(64));
+		let mut ray = Ray::new(Point3::new(-10., 0., 0.), Vector3::new(1., 0., 0.));
+		let mut visited = Vec::new();
+		let n_nodes = bvh.traverse(&mut ray, |index, ray| {
+			visited.push(index);
+			// a hit on the first cube
+			ray.t_max = ray.t_max.min(9.5);
+		});
+		assert!(visited.contains(&0));
+		assert!(visited.len() <= MAX_LEAF_SIZE);
+		assert!(n_nodes < 2 * 64 / MAX_LEAF_SIZE);
+	}
+
+	#[test]
+	fn refits_moved_graphics() {
+		let mut graphic_bounds = make_cube_bounds(16);
+		let mut bvh = Bvh::build(&graphic_bounds);
+		let above = Ray::new(Point3::new(-10., 5., 0.), Vector3::new(1., 0., 0.));
+		assert!(get_visited(&bvh, &above).is_empty());
+
+		graphic_bounds[7] = Some(Bounds::new(Point3::new(13.5, 4.5, -0.5), Point3::new(14.5, 5.5, 0.5)));
+		bvh.refit(&graphic_bounds);
+		// with the other graphics of its leaf
+		let visited = get_visited(&bvh, &above);
+		assert!(visited.contains(&7) && visited.len() <= MAX_LEAF_SIZE);
+	}
+
+	#[test]
+	fn handles_empty_scenes() {
+		let bvh = Bvh::build(&[]);
+		let mut ray = Ray::new(Point3::origin(), Vector3::new(0., 0., 1.));
+		assert_eq!(bvh.traverse(&mut ray, |_, _| panic!("nothing to visit")), 0);
+	}
+
+	#[test]
+	fn tests_rays_against_boxes() {
+		let bounds = Bounds::new(Point3::new(-1., -1., -1.), Point3::new(1., 1., 1.));
+		let test = |origin: Point3, direction: Vector3, t_max: Float| {
+			let mut ray = Ray::new(origin, direction);
+			ray.t_max = t_max;
+			bounds.test_ray(&ray, calc_inv_direction(&ray))
+		};
+		assert!(test(Point3::new(0., 0., -5.), Vector3::new(0., 0., 1.), Float::INFINITY));
+		assert!(!test(Point3::new(0., 0., -5.), Vector3::new(0., 0., 1.), 3.));
+		assert!(!test(Point3::new(0., 0., -5.), Vector3::new(0., 0., -1.), Float::INFINITY));
+		// parallel to the slabs of y and z, where the inverse direction is infinite
+		assert!(test(Point3::new(-5., 0.9, 0.), Vector3::new(1., 0., 0.), Float::INFINITY));
+		assert!(!test(Point3::new(-5., 1.5, 0.), Vector3::new(1., 0., 0.), Float::INFINITY));
+	}
+
+	#[test]
+	fn builds_with_nan_bounds() {
+		// e.g. from an object with a NaN size, which must not make the median split panic
+		let mut graphic_bounds = make_cube_bounds(8);
+		let nan = Float::NAN;
+		graphic_bounds.push(Some(Bounds::new(Point3::new(nan, nan, nan), Point3::new(nan, nan, nan))));
+		let bvh = Bvh::build(&graphic_bounds);
+		let along = Ray::new(Point3::new(-10., 0., 0.), Vector3::new(1., 0., 0.));
+		assert!(get_visited(&bvh, &along).len() >= 8);
+	}
+}
@@ -0,0 +1,304 @@
+use std::cmp::Ordering;
+use std::mem;
+use structs::*;
+use vec3::*;
+use mat4::*;
+use graphics::*;
+use bvh::*;
+use scene_writer::*;
+
+// ---------- Csg ----------
+
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum CsgOperation {
+	Union,
+	Intersection,
+	Difference,	// the first child minus all the others
+}
+
+// Constructive solid geometry node, combining its children from left to right.
+// Children keep their own (world) transforms, so the node itself is always in world space.
+// Hits are reported on the children's primitives, which provide the normals, UVs and materials.
+pub struct Csg {
+	pub operation: CsgOperation,
+	pub children: Vec<Box<dyn Graphic>>,
+	pub material_index: usize,	// unused, hits are shaded with the materials of the children
+	pub transform: Mat4,	// identity
+}
+
+impl Csg {
+	pub fn new(operation: CsgOperation, children: Vec<Box<dyn Graphic>>) -> Csg {
+		Csg {
+			operation: operation,
+			children: children,
+			material_index: 0,
+			transform: Mat4::id(1.),
+		}
+	}
+
+	// the first child whose bounds contain the point, or else the first one
+	fn find_child_at(&self, point: Point3) -> Option<&dyn Graphic> {
+		let contains = |child: &dyn Graphic| match child.calc_bounds() {
+			Some(bounds) => bounds.transformed(child.get_transform()).contains(point),
+			None => true,
+		};
+		self.children.iter().map(|child| &**child).find(|&child| contains(child)).or_else(|| self.children.first().map(|child| &**child))
+	}
+
+	// intervals of a child, with the distances converted to this node's ray
+	fn get_child_intervals<'a>(child: &'a dyn Graphic, ray: &Ray) -> Vec<Interval<'a>> {
+		let ray_os = ray.transformed(child.get_inverse_transform());
+		let mut intervals = child.test_intersection_intervals(&ray_os);
+		for interval in &mut intervals {
+			for hit in [&mut interval.enter, &mut interval.exit] {
+				if hit.t.is_finite() {
+					// the primitive's transform takes its points all the way to world space
+					let point = hit.graphic.get_transform().transform_point(hit.point);
+					hit.t = (point - ray.origin).dot(ray.direction);
+				}
+			}
+		}
+		intervals
+	}
+}
+
+// sweeps the boundaries of both lists of intervals in order, keeping the spans where the operation holds
+fn combine_intervals<'a>(a: Vec<Interval<'a>>, b: Vec<Interval<'a>>, operation: CsgOperation) -> Vec<Interval<'a>> {
+	// (hit, belongs to a, is an entry)
+	let mut events: Vec<(Hit<'a>, bool, bool)> = Vec::with_capacity(2 * (a.len() + b.len()));
+	for (intervals, is_a) in [(a, true), (b, false)] {
+		for interval in intervals {
+			events.push((interval.enter, is_a, true));
+			events.push((interval.exit, is_a, false));
+		}
+	}
+	// entries go first on ties, so that empty spans (flat graphics) are not lost
+	events.sort_by(|e1, e2| e1.0.t.partial_cmp(&e2.0.t).unwrap_or(Ordering::Equal).then(e2.2.cmp(&e1.2)));
+
+	let mut result = Vec::new();
+	let (mut inside_a, mut inside_b) = (false, false);
+	let mut enter: Option<Hit<'a>> = None;
+
+	for (mut hit, is_a, is_entry) in events {
+		if is_a { inside_a = is_entry; } else { inside_b = is_entry; }
+
+		// the carved surface of a difference is seen from the inside of the subtracted graphic
+		if operation == CsgOperation::Difference && !is_a {
+			hit.inverted = !hit.inverted;
+		}
+
+		let inside = match operation {
+			CsgOperation::Union => inside_a || inside_b,
+			CsgOperation::Intersection => inside_a && inside_b,
+			CsgOperation::Difference => inside_a && !inside_b,
+		};
+		match enter {
+			None if inside => { enter = Some(hit); }
+			Some(enter_hit) if !inside => {
+				result.push(Interval { enter: enter_hit, exit: hit });
+				enter = None;
+			}
+			_ => {}
+		}
+	}
+	result
+}
+
+impl Graphic for Csg {
+	fn get_material_index(&self) -> usize {
+		self.material_index
+	}
+	fn get_type_name(&self) -> &'static str {
+		"csg"
+	}
+	fn get_transform(&self) -> &Mat4 {
+		&self.transform
+	}
+	fn get_inverse_transform(&self) -> &Mat4 {
+		&self.transform
+	}
+	// the children keep their own transforms
+	fn set_transform(&mut self, _transform: Mat4, _inverse_transform: Mat4) {}
+	fn get_children_mut(&mut self) -> Option<&mut Vec<Box<dyn Graphic>>> {
+		Some(&mut self.children)
+	}
+	fn estimate_memory(&self) -> usize {
+		mem::size_of_val(self) + self.children.capacity() * mem::size_of::<Box<dyn Graphic>>() +
+			self.children.iter().map(|child| child.estimate_memory()).sum::<usize>()
+	}
+
+	fn test_intersection(&self, ray: &Ray) -> Option<Point3> {
+		self.test_closest_hit(ray).map(|hit| hit.graphic.get_transform().transform_point(hit.point))
+	}
+
+	fn test_closest_hit(&self, ray: &Ray) -> Option<Hit<'_>> {
+		for interval in self.test_intersection_intervals(ray) {
+			for hit in [interval.enter, interval.exit] {
+				if ray.contains(hit.t) {
+					return Some(hit);
+				}
+			}
+		}
+		None
+	}
+
+	fn test_intersection_intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+		let mut children = self.children.iter();
+		let mut intervals = match children.next() {
+			Some(child) => Csg::get_child_intervals(&**child, ray),
+			None => { return Vec::new(); }
+		};
+		for child in children {
+			intervals = combine_intervals(intervals, Csg::get_child_intervals(&**child, ray), self.operation);
+		}
+		intervals
+	}
+
+	// hits are resolved to the children's primitives (see test_closest_hit), so these are only used if the node itself
+	// is asked about a point of its surface, which is then that of the child around it
+
+	fn calc_normal_at(&self, point: Point3) -> Normal3 {
+		match self.find_child_at(point) {
+			Some(child) => {
+				let normal_os = child.calc_normal_at(child.get_inverse_transform().transform_point(point));
+				child.get_inverse_transform().transform_normal(normal_os).normalize()
+			}
+			None => Normal3::new(0., 1., 0.),
+		}
+	}
+
+	fn calc_uv_at(&self, point: Point3) -> UV {
+		match self.find_child_at(point) {
+			Some(child) => child.calc_uv_at(child.get_inverse_transform().transform_point(point)),
+			None => UV { u: 0., v: 0. },
+		}
+	}
+
+	fn calc_tangents_at(&self, point: Point3) -> (Vector3, Vector3) {
+		match self.find_child_at(point) {
+			Some(child) => {
+				let (dp_du, dp_dv) = child.calc_tangents_at(child.get_inverse_transform().transform_point(point));
+				(child.get_transform().transform_vector(dp_du), child.get_transform().transform_vector(dp_dv))
+			}
+			None => (Vector3::new(1., 0., 0.), Vector3::new(0., 1., 0.)),
+		}
+	}
+
+	fn calc_bounds(&self) -> Option<Bounds> {
+		// the children's transforms go to world space, which is this node's space too
+		let mut child_bounds = self.children.iter().map(|child| child.calc_bounds().map(|bounds| bounds.transformed(child.get_transform())));
+		match self.operation {
+			CsgOperation::Union => {
+				let first = child_bounds.next()??;
+				child_bounds.try_fold(first, |union, bounds| bounds.map(|bounds| union.union(&bounds)))
+			}
+			// the result is inside every child, so the bounded ones are enough
+			CsgOperation::Intersection => {
+				child_bounds.flatten().fold(None, |intersection: Option<Bounds>, bounds| {
+					Some(intersection.map_or(bounds, |intersection| intersection.intersection(&bounds)))
+				})
+			}
+			CsgOperation::Difference => child_bounds.next()?,
+		}
+	}
+
+	fn write_scene_commands(&self, writer: &mut SceneWriter) -> Result<(), String> {
+		writer.write_line(String::from(match self.operation {
+			CsgOperation::Union => "csg union",
+			CsgOperation::Intersection => "csg intersection",
+			CsgOperation::Difference => "csg difference",
+		}));
+		for child in &self.children {
+			child.write_scene_commands(writer)?;
+		}
+		writer.write_line(String::from("endcsg"));
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use float::*;
+
+	fn make_sphere(x: Float, radius: Float) -> Box<dyn Graphic> {
+		let transform = Mat4::create_translation(x, 0., 0.);
+		let inverse_transform = transform.invert().unwrap();
+		Box::new(Sphere { pos: Point3::origin(), radius: radius, material_index: 0, transform: transform, inverse_transform: inverse_transform })
+	}
+
+	fn make_intervals<'a>(spans: &[(Float, Float)], graphic: &'a dyn Graphic) -> Vec<Interval<'a>> {
+		let ray = Ray::new(Point3::origin(), Vector3::new(1., 0., 0.));
+		spans.iter().map(|&(enter, exit)| Interval { enter: Hit::new(&ray, enter, graphic), exit: Hit::new(&ray, exit, graphic) }).collect()
+	}
+
+	fn get_spans(intervals: &[Interval]) -> Vec<(Float, Float)> {
+		intervals.iter().map(|interval| (interval.enter.t, interval.exit.t)).collect()
+	}
+
+	#[test]
+	fn combines_overlapping_intervals() {
+		let graphic = make_sphere(0., 1.);
+		let a = || make_intervals(&[(1., 3.)], &*graphic);
+		let b = || make_intervals(&[(2., 5.)], &*graphic);
+		assert_eq!(get_spans(&combine_intervals(a(), b(), CsgOperation::Union)), [(1., 5.)]);
+		assert_eq!(get_spans(&combine_intervals(a(), b(), CsgOperation::Intersection)), [(2., 3.)]);
+		assert_eq!(get_spans(&combine_intervals(a(), b(), CsgOperation::Difference)), [(1., 2.)]);
+		assert_eq!(get_spans(&combine_intervals(b(), a(), CsgOperation::Difference)), [(3., 5.)]);
+	}
+
+	#[test]
+	fn combines_disjoint_intervals() {
+		let graphic = make_sphere(0., 1.);
+		let a = || make_intervals(&[(1., 2.), (6., 7.)], &*graphic);
+		let b = || make_intervals(&[(3., 4.)], &*graphic);
+		assert_eq!(get_spans(&combine_intervals(a(), b(), CsgOperation::Union)), [(1., 2.), (3., 4.), (6., 7.)]);
+		assert!(combine_intervals(a(), b(), CsgOperation::Intersection).is_empty());
+		assert_eq!(get_spans(&combine_intervals(a(), b(), CsgOperation::Difference)), [(1., 2.), (6., 7.)]);
+		assert_eq!(get_spans(&combine_intervals(a(), Vec::new(), CsgOperation::Union)), [(1., 2.), (6., 7.)]);
+	}
+
+	#[test]
+	fn inverts_the_carved_surfaces_of_differences() {
+		let graphic = make_sphere(0., 1.);
+		let result = combine_intervals(make_intervals(&[(1., 5.)], &*graphic), make_intervals(&[(2., 3.)], &*graphic), CsgOperation::Difference);
+		assert_eq!(get_spans(&result), [(1., 2.), (3., 5.)]);
+		let inverted: Vec<(bool, bool)> = result.iter().map(|interval| (interval.enter.inverted, interval.exit.inverted)).collect();
+		assert_eq!(inverted, [(false, true), (true, false)]);
+	}
+
+	#[test]
+	fn keeps_empty_spans() {
+		// flat graphics are empty spans, which must survive a union
+		let graphic = make_sphere(0., 1.);
+		let result = combine_intervals(make_intervals(&[(2., 2.)], &*graphic), make_intervals(&[(4., 5.)], &*graphic), CsgOperation::Union);
+		assert_eq!(get_spans(&result), [(2., 2.), (4., 5.)]);
+	}
+
+	#[test]
+	fn resolves_hits_to_the_children() {
+		// two unit spheres at x = 0 and x = 1.5, intersected: the lens between them
+		let csg = Csg::new(CsgOperation::Intersection, vec![make_sphere(0., 1.), make_sphere(1.5, 1.)]);
+		let ray = Ray::new(Point3::new(-5., 0., 0.), Vector3::new(1., 0., 0.));
+		let hit = csg.test_closest_hit(&ray).unwrap();
+		assert!((hit.t - 5.5).abs() < 1e-5);
+		assert!((hit.graphic.get_transform().transform_point(hit.point).x - 0.5).abs() < 1e-5);
+		let miss = Ray::new(Point3::new(-5., 0.9, 0.), Vector3::new(1., 0., 0.));
+		assert!(csg.test_closest_hit(&miss).is_none());
+	}
+
+	#[test]
+	fn answers_surface_queries_from_the_children() {
+		let csg = Csg::new(CsgOperation::Union, vec![make_sphere(0., 1.), make_sphere(5., 1.)]);
+		let normal = csg.calc_normal_at(Point3::new(5., 1., 0.));
+		assert!((normal.x).abs() < 1e-5 && (normal.y - 1.).abs() < 1e-5);
+		let normal = csg.calc_normal_at(Point3::new(-1., 0., 0.));
+		assert!((normal.x + 1.).abs() < 1e-5);
+		// without children, the answers are still defined
+		let empty = Csg::new(CsgOperation::Union, Vec::new());
+		let normal = empty.calc_normal_at(Point3::origin());
+		assert_eq!((normal.x, normal.y, normal.z), (0., 1., 0.));
+		let uv = empty.calc_uv_at(Point3::origin());
+		assert_eq!((uv.u, uv.v), (0., 0.));
+	}
+}
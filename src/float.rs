@@ -0,0 +1,12 @@
+// Scalar type of the geometry and colors: f64 by default, or f32 with the "f32" feature, which halves the size of meshes,
+// matrices and vectors at the cost of precision.
+
+#[cfg(not(feature = "f32"))]
+pub type Float = f64;
+#[cfg(not(feature = "f32"))]
+pub use std::f64::consts;
+
+#[cfg(feature = "f32")]
+pub type Float = f32;
+#[cfg(feature = "f32")]
+pub use std::f32::consts;
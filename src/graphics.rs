@@ -1,48 +1,189 @@
+use std::mem;
+use float::*;
+use float::consts::PI;
 use structs::*;
-use vec4::*;
+use vec3::*;
 use mat4::*;
+use solvers::*;
+use bvh::*;
+use scene_writer::*;
 
 // ---------- Graphic ----------
 
 pub trait Graphic {
-	fn get_material(&self) -> &Material;
+	fn get_material_index(&self) -> usize;
+	// the kind of graphic, for statistics
+	fn get_type_name(&self) -> &'static str;
 	fn get_transform(&self) -> &Mat4;
 	fn get_inverse_transform(&self) -> &Mat4;
-	fn get_transposed_inverse_transform(&self) -> &Mat4;
-	fn test_intersection(&self, ray: &Ray) -> Option<Vec4>;
-	fn calc_normal_at(&self, point: Vec4) -> Vec4;
+	// moves the graphic, e.g. to the next frame of an animation
+	fn set_transform(&mut self, transform: Mat4, inverse_transform: Mat4);
+	// closest hit within the range of the ray
+	fn test_intersection(&self, ray: &Ray) -> Option<Point3>;
+	// like test_intersection, but reporting the primitive that was hit, which differs from self for composite graphics (CSG)
+	fn test_closest_hit(&self, ray: &Ray) -> Option<Hit<'_>>;
+	// spans of the (object-space) ray that are inside the graphic, in order, including those behind the origin
+	fn test_intersection_intervals(&self, ray: &Ray) -> Vec<Interval<'_>>;
+	fn calc_normal_at(&self, point: Point3) -> Normal3;
+	fn calc_uv_at(&self, point: Point3) -> UV;
+	fn calc_tangents_at(&self, point: Point3) -> (Vector3, Vector3);	// (dp/du, dp/dv), not normalized
+	// object-space box around the graphic, or None if it is unbounded (and then tested by every ray)
+	fn calc_bounds(&self) -> Option<Bounds>;
+	// the commands creating the graphic in a scene file (see SceneWriter), if it can be written to one
+	fn write_scene_commands(&self, _writer: &mut SceneWriter) -> Result<(), String> {
+		Err(String::from("a graphic cannot be written to a scene file"))
+	}
+	// children of composite graphics (CSG), which animations may move on their own
+	fn get_children_mut(&mut self) -> Option<&mut Vec<Box<dyn Graphic>>> {
+		None
+	}
+	// flat graphics cannot be hit again by rays leaving them, so those rays can skip them altogether
+	fn is_planar(&self) -> bool {
+		false
+	}
+	// bytes taken by the graphic, including what it allocates
+	fn estimate_memory(&self) -> usize {
+		mem::size_of_val(self)
+	}
+}
+
+// ---------- Hit ----------
+
+#[derive(Copy,Clone)]
+pub struct Hit<'a> {
+	pub t: Float,	// distance along the ray that was tested
+	pub point: Point3,	// in the object space of the graphic
+	pub graphic: &'a dyn Graphic,	// primitive that was hit
+	pub inverted: bool,	// the surface is seen from its inside (e.g. where a CSG difference carved the object), so its normal must be flipped
+	pub uv: Option<UV>,	// if the intersection test already found it (triangles keep their barycentric coordinates)
 }
 
-fn test_plane_intersection(ray: &Ray, pos: Vec4, normal: Vec4) -> Option<Vec4> {
+impl<'a> Hit<'a> {
+	pub fn new(ray: &Ray, t: Float, graphic: &'a dyn Graphic) -> Hit<'a> {
+		Hit { t: t, point: ray.origin + t * ray.direction, graphic: graphic, inverted: false, uv: None }
+	}
+
+	pub fn from_point(ray: &Ray, point: Point3, graphic: &'a dyn Graphic) -> Hit<'a> {
+		Hit { t: (point - ray.origin).dot(ray.direction), point: point, graphic: graphic, inverted: false, uv: None }
+	}
+}
+
+// ---------- Interval ----------
+
+#[derive(Copy,Clone)]
+pub struct Interval<'a> {
+	pub enter: Hit<'a>,
+	pub exit: Hit<'a>,
+}
+
+// pairs up the crossings of a ray with a closed surface (sorted by t) into the spans inside it;
+// a leftover crossing, which only happens with open surfaces, becomes an empty span
+pub fn pair_crossings<'a>(ray: &Ray, crossings: &[Float], graphic: &'a dyn Graphic) -> Vec<Interval<'a>> {
+	crossings.chunks(2)
+		.map(|pair| Interval {
+			enter: Hit::new(ray, pair[0], graphic),
+			exit: Hit::new(ray, pair[pair.len() - 1], graphic),
+		})
+		.collect()
+}
+
+// flat graphics have no inside, so they are a single empty span
+pub fn get_flat_intervals<'a>(ray: &Ray, hit: Option<Point3>, graphic: &'a dyn Graphic) -> Vec<Interval<'a>> {
+	match hit {
+		Some(point) => {
+			let hit = Hit::from_point(ray, point, graphic);
+			vec![Interval { enter: hit, exit: hit }]
+		}
+		None => Vec::new(),
+	}
+}
+
+fn test_plane_intersection(ray: &Ray, pos: Point3, normal: Normal3) -> Option<Point3> {
 	let t = normal.dot(pos - ray.origin) / normal.dot(ray.direction);
-	if t <= 0. {
+	if !ray.contains(t) {
 		None
 	} else {
 		Some(ray.origin + t * ray.direction)
 	}
 }
 
-fn is_inside(point: Vec4, origin: Vec4, p1: Vec4, p2: Vec4) -> bool {
-	let normal = (p2 - origin).cross(p1 - origin).normalize();
-	let d = (-origin).dot(normal);
-	return point.dot(normal) + d >= 0.;
+// Watertight ray-triangle intersection (Woop, Benthin and Wald, 2013), returning the distance and the barycentric
+// coordinates of the hit. The triangle is sheared into a space where the ray goes along +z from the origin, so the edge
+// tests of triangles sharing an edge are computed from the same values and rays cannot slip between them.
+pub fn test_triangle_intersection(ray: &Ray, points: &[Point3; 3]) -> Option<(Float, [Float; 3])> {
+	let direction = [ray.direction.x, ray.direction.y, ray.direction.z];
+
+	// the axis along which the ray goes the most becomes z, keeping the winding of the triangle
+	let kz = (0..3).fold(0, |max_axis, axis| if direction[axis].abs() > direction[max_axis].abs() { axis } else { max_axis });
+	let (mut kx, mut ky) = ((kz + 1) % 3, (kz + 2) % 3);
+	if direction[kz] < 0. {
+		::std::mem::swap(&mut kx, &mut ky);
+	}
+	let shear_x = direction[kx] / direction[kz];
+	let shear_y = direction[ky] / direction[kz];
+	let shear_z = 1. / direction[kz];
+
+	// vertices relative to the ray origin, sheared
+	let mut sheared = [[0.; 3]; 3];
+	for (vertex, point) in sheared.iter_mut().zip(points.iter()) {
+		let p = [point.x - ray.origin.x, point.y - ray.origin.y, point.z - ray.origin.z];
+		*vertex = [p[kx] - shear_x * p[kz], p[ky] - shear_y * p[kz], shear_z * p[kz]];
+	}
+	let [a, b, c] = sheared;
+
+	// scaled barycentric coordinates, as the signed areas of the edges seen from the ray
+	let u = c[0] * b[1] - c[1] * b[0];
+	let v = a[0] * c[1] - a[1] * c[0];
+	let w = b[0] * a[1] - b[1] * a[0];
+	// in single precision, a zero may be a rounded-off sign, which decides on which side of the edge the ray passes,
+	// so the areas are computed again in double precision (as the paper does)
+	#[cfg(feature = "f32")]
+	let (u, v, w) = if u == 0. || v == 0. || w == 0. {
+		let area = |p: [Float; 3], q: [Float; 3]| (f64::from(p[0]) * f64::from(q[1]) - f64::from(p[1]) * f64::from(q[0])) as Float;
+		(area(c, b), area(a, c), area(b, a))
+	} else {
+		(u, v, w)
+	};
+	if (u < 0. || v < 0. || w < 0.) && (u > 0. || v > 0. || w > 0.) {
+		return None;
+	}
+	let det = u + v + w;
+	if det == 0. {
+		return None;
+	}
+
+	let t = (u * a[2] + v * b[2] + w * c[2]) / det;
+	if !ray.contains(t) {
+		return None;
+	}
+	Some((t, [u / det, v / det, w / det]))
+}
+
+// two unit directions that together with the normal form an orthonormal basis
+pub fn calc_plane_axes(normal: Normal3) -> (Vector3, Vector3) {
+	let helper = if normal.x.abs() < 0.9 { Vector3::new(1., 0., 0.) } else { Vector3::new(0., 1., 0.) };
+	let axis_u = helper.cross(normal.to_vector()).normalize();
+	let axis_v = normal.to_vector().cross(axis_u).normalize();
+	(axis_u, axis_v)
 }
 
 // ---------- Sphere ----------
 
 #[derive(Debug)]
 pub struct Sphere {
-	pub pos: Vec4,
-	pub radius: f64,
-	pub material: Material,
+	pub pos: Point3,
+	pub radius: Float,
+	pub material_index: usize,	// in the material table of the scene
 	pub transform: Mat4,
 	pub inverse_transform: Mat4,
-	pub transposed_inverse_transform: Mat4,
 }
 
 impl Graphic for Sphere {
-	fn get_material(&self) -> &Material {
-		&self.material
+	fn get_material_index(&self) -> usize {
+		self.material_index
+	}
+	fn get_type_name(&self) -> &'static str {
+		"sphere"
 	}
 	fn get_transform(&self) -> &Mat4 {
 		&self.transform
@@ -50,11 +191,12 @@ impl Graphic for Sphere {
 	fn get_inverse_transform(&self) -> &Mat4 {
 		&self.inverse_transform
 	}
-	fn get_transposed_inverse_transform(&self) -> &Mat4 {
-		&self.transposed_inverse_transform
+	fn set_transform(&mut self, transform: Mat4, inverse_transform: Mat4) {
+		self.transform = transform;
+		self.inverse_transform = inverse_transform;
 	}
 
-	fn test_intersection(&self, ray: &Ray) -> Option<Vec4> {
+	fn test_intersection(&self, ray: &Ray) -> Option<Point3> {
 		let origin_to_center = self.pos - ray.origin;
 		let b = origin_to_center.dot(ray.direction);
 
@@ -65,21 +207,65 @@ impl Graphic for Sphere {
 
 		let squared_det = det.sqrt();
 		let t0 = b - squared_det;
-		if t0 > 1e-3 {
+		if ray.contains(t0) {
 			return Some(ray.origin + t0 * ray.direction);
 		}
 
 		let t1 = b + squared_det;
-		if t1 > 1e-3 {
+		if ray.contains(t1) {
 			return Some(ray.origin + t1 * ray.direction);
 		}
 
 		return None;
 	}
 
-	fn calc_normal_at(&self, point: Vec4) -> Vec4 {
-		let mut to_point = point - self.pos;
-		to_point.normalize()
+	fn test_closest_hit(&self, ray: &Ray) -> Option<Hit<'_>> {
+		self.test_intersection(ray).map(|point| Hit::from_point(ray, point, self))
+	}
+
+	fn test_intersection_intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+		let origin_to_center = self.pos - ray.origin;
+		let b = origin_to_center.dot(ray.direction);
+		let det = b*b - origin_to_center.length_sq() + self.radius*self.radius;
+		if det < 0. {
+			return Vec::new();
+		}
+		let squared_det = det.sqrt();
+		pair_crossings(ray, &[b - squared_det, b + squared_det], self)
+	}
+
+	fn calc_normal_at(&self, point: Point3) -> Normal3 {
+		(point - self.pos).normalize().to_normal()
+	}
+
+	fn calc_uv_at(&self, point: Point3) -> UV {
+		// spherical mapping: u goes around the y axis, v goes from the bottom pole to the top one
+		let d = (point - self.pos).normalize();
+		UV {
+			u: 0.5 + Float::atan2(d.x, d.z) / (2. * PI),
+			v: 0.5 + Float::asin(d.y.clamp(-1., 1.)) / PI,
+		}
+	}
+
+	fn calc_tangents_at(&self, point: Point3) -> (Vector3, Vector3) {
+		let p = point - self.pos;
+		let dist_to_axis = (p.x * p.x + p.z * p.z).sqrt();
+		if dist_to_axis < 1e-9 {
+			// at the poles u is undefined, so any frame will do
+			return (Vector3::new(2. * PI * self.radius, 0., 0.), Vector3::new(0., 0., -PI * self.radius));
+		}
+		let dp_du = 2. * PI * Vector3::new(p.z, 0., -p.x);
+		let dp_dv = PI * Vector3::new(-p.y * p.x / dist_to_axis, dist_to_axis, -p.y * p.z / dist_to_axis);
+		(dp_du, dp_dv)
+	}
+
+	fn calc_bounds(&self) -> Option<Bounds> {
+		let radius = Vector3::new(self.radius, self.radius, self.radius);
+		Some(Bounds::new(self.pos - radius, self.pos + radius))
+	}
+
+	fn write_scene_commands(&self, writer: &mut SceneWriter) -> Result<(), String> {
+		writer.write_object(self.material_index, &self.transform, format!("sphere {} {} {} {}", self.pos.x, self.pos.y, self.pos.z, self.radius))
 	}
 }
 
@@ -87,17 +273,19 @@ impl Graphic for Sphere {
 
 #[derive(Debug)]
 pub struct Plane {
-	pub pos: Vec4,
-	pub normal: Vec4,
-	pub material: Material,
+	pub pos: Point3,
+	pub normal: Normal3,
+	pub material_index: usize,	// in the material table of the scene
 	pub transform: Mat4,
 	pub inverse_transform: Mat4,
-	pub transposed_inverse_transform: Mat4,
 }
 
 impl Graphic for Plane {
-	fn get_material(&self) -> &Material {
-		&self.material
+	fn get_material_index(&self) -> usize {
+		self.material_index
+	}
+	fn get_type_name(&self) -> &'static str {
+		"plane"
 	}
 	fn get_transform(&self) -> &Mat4 {
 		&self.transform
@@ -105,34 +293,84 @@ impl Graphic for Plane {
 	fn get_inverse_transform(&self) -> &Mat4 {
 		&self.inverse_transform
 	}
-	fn get_transposed_inverse_transform(&self) -> &Mat4 {
-		&self.transposed_inverse_transform
+	fn set_transform(&mut self, transform: Mat4, inverse_transform: Mat4) {
+		self.transform = transform;
+		self.inverse_transform = inverse_transform;
 	}
 
-	fn test_intersection(&self, ray: &Ray) -> Option<Vec4> {
+	fn test_intersection(&self, ray: &Ray) -> Option<Point3> {
 		test_plane_intersection(ray, self.pos, self.normal)
 	}
 
-	fn calc_normal_at(&self, _point: Vec4) -> Vec4 {
+	fn test_closest_hit(&self, ray: &Ray) -> Option<Hit<'_>> {
+		self.test_intersection(ray).map(|point| Hit::from_point(ray, point, self))
+	}
+
+	fn test_intersection_intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+		// the inside is the half-space behind the normal
+		let to_plane = self.normal.dot(self.pos - ray.origin);
+		let cos = self.normal.dot(ray.direction);
+		let t = to_plane / cos;
+		if cos < 0. {
+			vec![Interval { enter: Hit::new(ray, t, self), exit: Hit::new(ray, Float::INFINITY, self) }]
+		} else if cos > 0. {
+			vec![Interval { enter: Hit::new(ray, Float::NEG_INFINITY, self), exit: Hit::new(ray, t, self) }]
+		} else if to_plane > 0. {
+			// parallel to the plane, and behind it
+			vec![Interval { enter: Hit::new(ray, Float::NEG_INFINITY, self), exit: Hit::new(ray, Float::INFINITY, self) }]
+		} else {
+			Vec::new()
+		}
+	}
+
+	fn calc_normal_at(&self, _point: Point3) -> Normal3 {
 		self.normal
 	}
+
+	fn calc_uv_at(&self, point: Point3) -> UV {
+		// planar projection, one texture repetition per unit of distance
+		let (axis_u, axis_v) = calc_plane_axes(self.normal);
+		let to_point = point - self.pos;
+		UV { u: to_point.dot(axis_u), v: to_point.dot(axis_v) }
+	}
+
+	fn calc_tangents_at(&self, _point: Point3) -> (Vector3, Vector3) {
+		calc_plane_axes(self.normal)
+	}
+
+	fn calc_bounds(&self) -> Option<Bounds> {
+		None
+	}
+
+	fn write_scene_commands(&self, writer: &mut SceneWriter) -> Result<(), String> {
+		let normal = to_exact_direction(self.normal.to_vector());
+		writer.write_object(self.material_index, &self.transform, format!("plane {} {} {} {} {} {}",
+			self.pos.x, self.pos.y, self.pos.z, normal.x, normal.y, normal.z))
+	}
+
+	fn is_planar(&self) -> bool {
+		true
+	}
 }
 
 // ---------- Triangle ----------
 
 #[derive(Debug)]
 pub struct Triangle {
-	pub points: [Vec4; 3],
-	pub normal: Vec4,
-	pub material: Material,
+	pub points: [Point3; 3],
+	pub uvs: [UV; 3],
+	pub normal: Normal3,
+	pub material_index: usize,	// in the material table of the scene
 	pub transform: Mat4,
 	pub inverse_transform: Mat4,
-	pub transposed_inverse_transform: Mat4,
 }
 
 impl Graphic for Triangle {
-	fn get_material(&self) -> &Material {
-		&self.material
+	fn get_material_index(&self) -> usize {
+		self.material_index
+	}
+	fn get_type_name(&self) -> &'static str {
+		"triangle"
 	}
 	fn get_transform(&self) -> &Mat4 {
 		&self.transform
@@ -140,23 +378,764 @@ impl Graphic for Triangle {
 	fn get_inverse_transform(&self) -> &Mat4 {
 		&self.inverse_transform
 	}
-	fn get_transposed_inverse_transform(&self) -> &Mat4 {
-		&self.transposed_inverse_transform
+	fn set_transform(&mut self, transform: Mat4, inverse_transform: Mat4) {
+		self.transform = transform;
+		self.inverse_transform = inverse_transform;
+	}
+
+	fn test_intersection(&self, ray: &Ray) -> Option<Point3> {
+		test_triangle_intersection(ray, &self.points).map(|(t, _)| ray.origin + t * ray.direction)
+	}
+
+	// the UV comes from the barycentric coordinates of the watertight test, rather than from the hit point
+	fn test_closest_hit(&self, ray: &Ray) -> Option<Hit<'_>> {
+		test_triangle_intersection(ray, &self.points).map(|(t, barycentrics)| Hit {
+			uv: Some(self.interpolate_uv(barycentrics)),
+			..Hit::from_point(ray, ray.origin + t * ray.direction, self)
+		})
 	}
 
-	fn test_intersection(&self, ray: &Ray) -> Option<Vec4> {
-		if let Some(hit) = test_plane_intersection(ray, self.points[0], self.normal) {
-			// check if intersection is inside triangle; i.e.: if all calculations return the same side (side is either true or false)
-			let inside: bool = is_inside(hit, self.points[0], self.points[1], ray.origin);
-			if inside == is_inside(hit, self.points[1], self.points[2], ray.origin)
-			&& inside == is_inside(hit, self.points[2], self.points[0], ray.origin) {
-				return Some(hit);
-			 }
+	fn test_intersection_intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+		match self.test_closest_hit(ray) {
+			Some(hit) => vec![Interval { enter: hit, exit: hit }],
+			None => Vec::new(),
 		}
-		return None;
 	}
 
-	fn calc_normal_at(&self, _point: Vec4) -> Vec4 {
+	fn calc_normal_at(&self, _point: Point3) -> Normal3 {
 		self.normal
 	}
+
+	fn calc_uv_at(&self, point: Point3) -> UV {
+		self.interpolate_uv(self.calc_barycentric_coords(point))
+	}
+
+	fn calc_tangents_at(&self, _point: Point3) -> (Vector3, Vector3) {
+		let e1 = self.points[1] - self.points[0];
+		let e2 = self.points[2] - self.points[0];
+		let (du1, dv1) = (self.uvs[1].u - self.uvs[0].u, self.uvs[1].v - self.uvs[0].v);
+		let (du2, dv2) = (self.uvs[2].u - self.uvs[0].u, self.uvs[2].v - self.uvs[0].v);
+
+		let det = du1 * dv2 - du2 * dv1;
+		if det.abs() < 1e-12 {
+			// degenerate UVs
+			return calc_plane_axes(self.normal);
+		}
+		let dp_du = (1. / det) * (dv2 * e1 - dv1 * e2);
+		let dp_dv = (1. / det) * (du1 * e2 - du2 * e1);
+		(dp_du, dp_dv)
+	}
+
+	fn calc_bounds(&self) -> Option<Bounds> {
+		Some(Bounds::from_points(&self.points))
+	}
+
+	fn write_scene_commands(&self, writer: &mut SceneWriter) -> Result<(), String> {
+		let default_uvs = Triangle::default_uvs();
+		let command = if (0..3).all(|i| self.uvs[i].u == default_uvs[i].u && self.uvs[i].v == default_uvs[i].v) {
+			let indices: Vec<usize> = self.points.iter().map(|&point| writer.write_vertex(point)).collect();
+			format!("tri {} {} {}", indices[0], indices[1], indices[2])
+		} else {
+			let indices: Vec<usize> = (0..3).map(|i| writer.write_tex_vertex(self.points[i], self.uvs[i])).collect();
+			format!("tritex {} {} {}", indices[0], indices[1], indices[2])
+		};
+		writer.write_object(self.material_index, &self.transform, command)
+	}
+
+	fn is_planar(&self) -> bool {
+		true
+	}
+}
+
+impl Triangle {
+	pub fn default_uvs() -> [UV; 3] {
+		[UV { u: 0., v: 0. }, UV { u: 1., v: 0. }, UV { u: 0., v: 1. }]
+	}
+
+	fn interpolate_uv(&self, [b0, b1, b2]: [Float; 3]) -> UV {
+		UV {
+			u: b0 * self.uvs[0].u + b1 * self.uvs[1].u + b2 * self.uvs[2].u,
+			v: b0 * self.uvs[0].v + b1 * self.uvs[1].v + b2 * self.uvs[2].v,
+		}
+	}
+
+	fn calc_barycentric_coords(&self, point: Point3) -> [Float; 3] {
+		let e1 = self.points[1] - self.points[0];
+		let e2 = self.points[2] - self.points[0];
+		let to_point = point - self.points[0];
+
+		let d11 = e1.dot(e1);
+		let d12 = e1.dot(e2);
+		let d22 = e2.dot(e2);
+		let dp1 = to_point.dot(e1);
+		let dp2 = to_point.dot(e2);
+		let denominator = d11 * d22 - d12 * d12;
+
+		let b1 = (d22 * dp1 - d12 * dp2) / denominator;
+		let b2 = (d11 * dp2 - d12 * dp1) / denominator;
+		[1. - b1 - b2, b1, b2]
+	}
+}
+
+// ---------- Cylinder ----------
+
+// Cylinder around the y axis, between two heights, optionally closed with caps.
+#[derive(Debug)]
+pub struct Cylinder {
+	pub radius: Float,
+	pub y_min: Float,
+	pub y_max: Float,
+	pub capped: bool,
+	pub material_index: usize,	// in the material table of the scene
+	pub transform: Mat4,
+	pub inverse_transform: Mat4,
+}
+
+impl Graphic for Cylinder {
+	fn get_material_index(&self) -> usize {
+		self.material_index
+	}
+	fn get_type_name(&self) -> &'static str {
+		"cylinder"
+	}
+	fn get_transform(&self) -> &Mat4 {
+		&self.transform
+	}
+	fn get_inverse_transform(&self) -> &Mat4 {
+		&self.inverse_transform
+	}
+	fn set_transform(&mut self, transform: Mat4, inverse_transform: Mat4) {
+		self.transform = transform;
+		self.inverse_transform = inverse_transform;
+	}
+
+	fn test_intersection(&self, ray: &Ray) -> Option<Point3> {
+		self.calc_crossings(ray).into_iter()
+			.find(|&t| ray.contains(t))
+			.map(|t| ray.origin + t * ray.direction)
+	}
+
+	fn test_closest_hit(&self, ray: &Ray) -> Option<Hit<'_>> {
+		self.test_intersection(ray).map(|point| Hit::from_point(ray, point, self))
+	}
+
+	fn test_intersection_intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+		pair_crossings(ray, &self.calc_crossings(ray), self)
+	}
+
+	fn calc_normal_at(&self, point: Point3) -> Normal3 {
+		match self.get_cap_at(point) {
+			Some(cap_normal_y) => Normal3::new(0., cap_normal_y, 0.),
+			None => Normal3::new(point.x, 0., point.z).normalize(),
+		}
+	}
+
+	fn calc_uv_at(&self, point: Point3) -> UV {
+		match self.get_cap_at(point) {
+			// caps are mapped to the unit square, oriented so that they have the same handedness as the side
+			Some(cap_normal_y) => UV {
+				u: 0.5 + 0.5 * point.x / self.radius,
+				v: 0.5 - 0.5 * cap_normal_y * point.z / self.radius,
+			},
+			None => UV {
+				u: 0.5 + Float::atan2(point.x, point.z) / (2. * PI),
+				v: (point.y - self.y_min) / (self.y_max - self.y_min),
+			},
+		}
+	}
+
+	fn calc_tangents_at(&self, point: Point3) -> (Vector3, Vector3) {
+		match self.get_cap_at(point) {
+			Some(cap_normal_y) => (
+				Vector3::new(2. * self.radius, 0., 0.),
+				Vector3::new(0., 0., -cap_normal_y * 2. * self.radius),
+			),
+			None => (
+				Vector3::new(2. * PI * point.z, 0., -2. * PI * point.x),
+				Vector3::new(0., self.y_max - self.y_min, 0.),
+			),
+		}
+	}
+
+	fn calc_bounds(&self) -> Option<Bounds> {
+		Some(Bounds::new(Point3::new(-self.radius, self.y_min, -self.radius), Point3::new(self.radius, self.y_max, self.radius)))
+	}
+
+	fn write_scene_commands(&self, writer: &mut SceneWriter) -> Result<(), String> {
+		writer.write_object(self.material_index, &self.transform, format!("cylinder {} {} {} {}",
+			self.radius, self.y_min, self.y_max, if self.capped { "capped" } else { "open" }))
+	}
+}
+
+impl Cylinder {
+	// all the distances along the ray where it crosses the surface, sorted
+	fn calc_crossings(&self, ray: &Ray) -> Vec<Float> {
+		let Ray { origin: o, direction: d, .. } = *ray;
+
+		let a = d.x * d.x + d.z * d.z;
+		let b = 2. * (o.x * d.x + o.z * d.z);
+		let c = o.x * o.x + o.z * o.z - self.radius * self.radius;
+		let mut crossings: Vec<Float> = solve_quadratic(a as f64, b as f64, c as f64).into_iter()
+			.map(|t| t as Float)
+			.filter(|&t| {
+				let y = o.y + t * d.y;
+				y >= self.y_min && y <= self.y_max
+			})
+			.collect();
+
+		if self.capped && d.y != 0. {
+			for &cap_y in &[self.y_min, self.y_max] {
+				let t = (cap_y - o.y) / d.y;
+				let (x, z) = (o.x + t * d.x, o.z + t * d.z);
+				if x * x + z * z <= self.radius * self.radius {
+					crossings.push(t);
+				}
+			}
+		}
+
+		crossings.sort_by(|a, b| a.total_cmp(b));
+		crossings
+	}
+
+	// y of the normal of the cap containing the point, if any
+	fn get_cap_at(&self, point: Point3) -> Option<Float> {
+		let on_side = (point.x * point.x + point.z * point.z).sqrt() > self.radius * (1. - 1e-6);
+		if !self.capped || on_side {
+			None
+		} else if (point.y - self.y_max).abs() < (point.y - self.y_min).abs() {
+			Some(1.)
+		} else {
+			Some(-1.)
+		}
+	}
+}
+
+// ---------- Cone ----------
+
+// Cone around the y axis, with its base at y = 0 and its apex at y = height, optionally closed at the base.
+#[derive(Debug)]
+pub struct Cone {
+	pub radius: Float,
+	pub height: Float,
+	pub capped: bool,
+	pub material_index: usize,	// in the material table of the scene
+	pub transform: Mat4,
+	pub inverse_transform: Mat4,
+}
+
+impl Graphic for Cone {
+	fn get_material_index(&self) -> usize {
+		self.material_index
+	}
+	fn get_type_name(&self) -> &'static str {
+		"cone"
+	}
+	fn get_transform(&self) -> &Mat4 {
+		&self.transform
+	}
+	fn get_inverse_transform(&self) -> &Mat4 {
+		&self.inverse_transform
+	}
+	fn set_transform(&mut self, transform: Mat4, inverse_transform: Mat4) {
+		self.transform = transform;
+		self.inverse_transform = inverse_transform;
+	}
+
+	fn test_intersection(&self, ray: &Ray) -> Option<Point3> {
+		self.calc_crossings(ray).into_iter()
+			.find(|&t| ray.contains(t))
+			.map(|t| ray.origin + t * ray.direction)
+	}
+
+	fn test_closest_hit(&self, ray: &Ray) -> Option<Hit<'_>> {
+		self.test_intersection(ray).map(|point| Hit::from_point(ray, point, self))
+	}
+
+	fn test_intersection_intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+		pair_crossings(ray, &self.calc_crossings(ray), self)
+	}
+
+	fn calc_normal_at(&self, point: Point3) -> Normal3 {
+		if self.is_on_base(point) {
+			return Normal3::new(0., -1., 0.);
+		}
+		let dist_to_axis = (point.x * point.x + point.z * point.z).sqrt();
+		if dist_to_axis < 1e-9 {
+			return Normal3::new(0., 1., 0.);	// apex
+		}
+		// the radius shrinks by radius / height per unit of height, so the normal leans up by that slope
+		let slope = self.radius / self.height;
+		Normal3::new(point.x / dist_to_axis, slope, point.z / dist_to_axis).normalize()
+	}
+
+	fn calc_uv_at(&self, point: Point3) -> UV {
+		if self.is_on_base(point) {
+			UV { u: 0.5 + 0.5 * point.x / self.radius, v: 0.5 + 0.5 * point.z / self.radius }
+		} else {
+			UV { u: 0.5 + Float::atan2(point.x, point.z) / (2. * PI), v: point.y / self.height }
+		}
+	}
+
+	fn calc_tangents_at(&self, point: Point3) -> (Vector3, Vector3) {
+		if self.is_on_base(point) {
+			return (Vector3::new(2. * self.radius, 0., 0.), Vector3::new(0., 0., 2. * self.radius));
+		}
+		let dist_to_axis = (point.x * point.x + point.z * point.z).sqrt();
+		if dist_to_axis < 1e-9 {
+			return (Vector3::new(1., 0., 0.), Vector3::new(0., 0., -1.));	// apex
+		}
+		let (sin, cos) = (point.x / dist_to_axis, point.z / dist_to_axis);
+		(
+			Vector3::new(2. * PI * point.z, 0., -2. * PI * point.x),
+			Vector3::new(-self.radius * sin, self.height, -self.radius * cos),
+		)
+	}
+
+	fn calc_bounds(&self) -> Option<Bounds> {
+		Some(Bounds::new(Point3::new(-self.radius, 0., -self.radius), Point3::new(self.radius, self.height, self.radius)))
+	}
+
+	fn write_scene_commands(&self, writer: &mut SceneWriter) -> Result<(), String> {
+		writer.write_object(self.material_index, &self.transform, format!("cone {} {} {}",
+			self.radius, self.height, if self.capped { "capped" } else { "open" }))
+	}
+}
+
+impl Cone {
+	// all the distances along the ray where it crosses the surface, sorted
+	fn calc_crossings(&self, ray: &Ray) -> Vec<Float> {
+		let Ray { origin: o, direction: d, .. } = *ray;
+
+		// x^2 + z^2 = k^2 * (height - y)^2
+		let k_sq = (self.radius / self.height) * (self.radius / self.height);
+		let h = self.height - o.y;
+		let a = d.x * d.x + d.z * d.z - k_sq * d.y * d.y;
+		let b = 2. * (o.x * d.x + o.z * d.z + k_sq * h * d.y);
+		let c = o.x * o.x + o.z * o.z - k_sq * h * h;
+		let mut crossings: Vec<Float> = solve_quadratic(a as f64, b as f64, c as f64).into_iter()
+			.map(|t| t as Float)
+			.filter(|&t| {
+				// the equation also describes the mirrored cone above the apex
+				let y = o.y + t * d.y;
+				y >= 0. && y <= self.height
+			})
+			.collect();
+
+		if self.capped && d.y != 0. {
+			let t = -o.y / d.y;
+			let (x, z) = (o.x + t * d.x, o.z + t * d.z);
+			if x * x + z * z <= self.radius * self.radius {
+				crossings.push(t);
+			}
+		}
+
+		crossings.sort_by(|a, b| a.total_cmp(b));
+		crossings
+	}
+
+	fn is_on_base(&self, point: Point3) -> bool {
+		self.capped && point.y.abs() < 1e-6 * self.height.max(1.)
+	}
+}
+
+// ---------- Disk ----------
+
+#[derive(Debug)]
+pub struct Disk {
+	pub pos: Point3,
+	pub normal: Normal3,
+	pub radius: Float,
+	pub material_index: usize,	// in the material table of the scene
+	pub transform: Mat4,
+	pub inverse_transform: Mat4,
+}
+
+impl Graphic for Disk {
+	fn get_material_index(&self) -> usize {
+		self.material_index
+	}
+	fn get_type_name(&self) -> &'static str {
+		"disk"
+	}
+	fn get_transform(&self) -> &Mat4 {
+		&self.transform
+	}
+	fn get_inverse_transform(&self) -> &Mat4 {
+		&self.inverse_transform
+	}
+	fn set_transform(&mut self, transform: Mat4, inverse_transform: Mat4) {
+		self.transform = transform;
+		self.inverse_transform = inverse_transform;
+	}
+
+	fn test_intersection(&self, ray: &Ray) -> Option<Point3> {
+		match test_plane_intersection(ray, self.pos, self.normal) {
+			Some(hit) if (hit - self.pos).length_sq() <= self.radius * self.radius => Some(hit),
+			_ => None,
+		}
+	}
+
+	fn test_closest_hit(&self, ray: &Ray) -> Option<Hit<'_>> {
+		self.test_intersection(ray).map(|point| Hit::from_point(ray, point, self))
+	}
+
+	fn test_intersection_intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+		get_flat_intervals(ray, self.test_intersection(ray), self)
+	}
+
+	fn calc_normal_at(&self, _point: Point3) -> Normal3 {
+		self.normal
+	}
+
+	fn calc_uv_at(&self, point: Point3) -> UV {
+		// the disk is mapped to the unit square
+		let (axis_u, axis_v) = calc_plane_axes(self.normal);
+		let to_point = point - self.pos;
+		UV {
+			u: 0.5 + 0.5 * to_point.dot(axis_u) / self.radius,
+			v: 0.5 + 0.5 * to_point.dot(axis_v) / self.radius,
+		}
+	}
+
+	fn calc_tangents_at(&self, _point: Point3) -> (Vector3, Vector3) {
+		let (axis_u, axis_v) = calc_plane_axes(self.normal);
+		(2. * self.radius * axis_u, 2. * self.radius * axis_v)
+	}
+
+	fn calc_bounds(&self) -> Option<Bounds> {
+		let radius = Vector3::new(self.radius, self.radius, self.radius);
+		Some(Bounds::new(self.pos - radius, self.pos + radius))
+	}
+
+	fn write_scene_commands(&self, writer: &mut SceneWriter) -> Result<(), String> {
+		let normal = to_exact_direction(self.normal.to_vector());
+		writer.write_object(self.material_index, &self.transform, format!("disk {} {} {} {} {} {} {}",
+			self.pos.x, self.pos.y, self.pos.z, normal.x, normal.y, normal.z, self.radius))
+	}
+
+	fn is_planar(&self) -> bool {
+		true
+	}
+}
+
+// ---------- Torus ----------
+
+// Torus around the y axis, centered at the origin.
+#[derive(Debug)]
+pub struct Torus {
+	pub major_radius: Float,	// from the center to the middle of the tube
+	pub minor_radius: Float,	// of the tube
+	pub material_index: usize,	// in the material table of the scene
+	pub transform: Mat4,
+	pub inverse_transform: Mat4,
+}
+
+impl Graphic for Torus {
+	fn get_material_index(&self) -> usize {
+		self.material_index
+	}
+	fn get_type_name(&self) -> &'static str {
+		"torus"
+	}
+	fn get_transform(&self) -> &Mat4 {
+		&self.transform
+	}
+	fn get_inverse_transform(&self) -> &Mat4 {
+		&self.inverse_transform
+	}
+	fn set_transform(&mut self, transform: Mat4, inverse_transform: Mat4) {
+		self.transform = transform;
+		self.inverse_transform = inverse_transform;
+	}
+
+	fn test_intersection(&self, ray: &Ray) -> Option<Point3> {
+		self.calc_crossings(ray).into_iter()
+			.find(|&t| ray.contains(t))
+			.map(|t| ray.origin + t * ray.direction)
+	}
+
+	fn test_closest_hit(&self, ray: &Ray) -> Option<Hit<'_>> {
+		self.test_intersection(ray).map(|point| Hit::from_point(ray, point, self))
+	}
+
+	fn test_intersection_intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+		pair_crossings(ray, &self.calc_crossings(ray), self)
+	}
+
+	fn calc_normal_at(&self, point: Point3) -> Normal3 {
+		// from the closest point on the circle in the middle of the tube
+		let tube_center = Point3::origin() + self.major_radius * Vector3::new(point.x, 0., point.z).normalize();
+		(point - tube_center).normalize().to_normal()
+	}
+
+	fn calc_uv_at(&self, point: Point3) -> UV {
+		// u goes around the y axis, v around the tube
+		let dist_to_axis = (point.x * point.x + point.z * point.z).sqrt();
+		UV {
+			u: 0.5 + Float::atan2(point.x, point.z) / (2. * PI),
+			v: 0.5 + Float::atan2(point.y, dist_to_axis - self.major_radius) / (2. * PI),
+		}
+	}
+
+	fn calc_tangents_at(&self, point: Point3) -> (Vector3, Vector3) {
+		let dist_to_axis = (point.x * point.x + point.z * point.z).sqrt().max(1e-9);
+		(
+			Vector3::new(2. * PI * point.z, 0., -2. * PI * point.x),
+			Vector3::new(
+				-2. * PI * point.y * point.x / dist_to_axis,
+				2. * PI * (dist_to_axis - self.major_radius),
+				-2. * PI * point.y * point.z / dist_to_axis,
+			),
+		)
+	}
+
+	fn calc_bounds(&self) -> Option<Bounds> {
+		let extent = self.major_radius + self.minor_radius;
+		Some(Bounds::new(Point3::new(-extent, -self.minor_radius, -extent), Point3::new(extent, self.minor_radius, extent)))
+	}
+
+	fn write_scene_commands(&self, writer: &mut SceneWriter) -> Result<(), String> {
+		writer.write_object(self.material_index, &self.transform, format!("torus {} {}", self.major_radius, self.minor_radius))
+	}
+}
+
+impl Torus {
+	// all the distances along the ray where it crosses the surface, sorted
+	fn calc_crossings(&self, ray: &Ray) -> Vec<Float> {
+		// rejects rays missing the bounding sphere, and moves the origin next to the torus,
+		// as the quartic's coefficients lose precision quickly with the distance to it
+		let bounding_radius = self.major_radius + self.minor_radius;
+		let b = ray.origin.to_vector().dot(ray.direction);
+		let c = ray.origin.to_vector().length_sq() - bounding_radius * bounding_radius;
+		if b * b - c < 0. {
+			return Vec::new();
+		}
+		let t_start = -b - bounding_radius;
+		let start = ray.origin + t_start * ray.direction;
+
+		// the coefficients are computed in f64 whatever the scalar type, as the quartic needs the precision
+		let (o, d) = ([start.x as f64, start.y as f64, start.z as f64], [ray.direction.x as f64, ray.direction.y as f64, ray.direction.z as f64]);
+		let r_major_sq = self.major_radius as f64 * self.major_radius as f64;
+		let r_minor_sq = self.minor_radius as f64 * self.minor_radius as f64;
+
+		// (|p|^2 + R^2 - r^2)^2 = 4 * R^2 * (x^2 + z^2), with |d| = 1
+		let od = o[0] * d[0] + o[1] * d[1] + o[2] * d[2];
+		let e = o[0] * o[0] + o[1] * o[1] + o[2] * o[2] - r_major_sq - r_minor_sq;
+		let roots = solve_quartic(
+			1.,
+			4. * od,
+			2. * e + 4. * od * od + 4. * r_major_sq * d[1] * d[1],
+			4. * od * e + 8. * r_major_sq * o[1] * d[1],
+			e * e - 4. * r_major_sq * (r_minor_sq - o[1] * o[1]),
+		);
+
+		roots.into_iter().map(|t| t as Float + t_start).collect()
+	}
+}
+
+// ---------- Cuboid ----------
+
+// Axis-aligned box (in object space) between two corners.
+#[derive(Debug)]
+pub struct Cuboid {
+	pub min: Point3,
+	pub max: Point3,
+	pub material_index: usize,	// in the material table of the scene
+	pub transform: Mat4,
+	pub inverse_transform: Mat4,
+}
+
+impl Graphic for Cuboid {
+	fn get_material_index(&self) -> usize {
+		self.material_index
+	}
+	fn get_type_name(&self) -> &'static str {
+		"box"
+	}
+	fn get_transform(&self) -> &Mat4 {
+		&self.transform
+	}
+	fn get_inverse_transform(&self) -> &Mat4 {
+		&self.inverse_transform
+	}
+	fn set_transform(&mut self, transform: Mat4, inverse_transform: Mat4) {
+		self.transform = transform;
+		self.inverse_transform = inverse_transform;
+	}
+
+	fn test_intersection(&self, ray: &Ray) -> Option<Point3> {
+		let (t_near, t_far) = self.calc_slab_range(ray)?;
+		[t_near, t_far].iter()
+			.find(|&&t| ray.contains(t))
+			.map(|&t| ray.origin + t * ray.direction)
+	}
+
+	fn test_closest_hit(&self, ray: &Ray) -> Option<Hit<'_>> {
+		self.test_intersection(ray).map(|point| Hit::from_point(ray, point, self))
+	}
+
+	fn test_intersection_intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+		match self.calc_slab_range(ray) {
+			Some((t_near, t_far)) => pair_crossings(ray, &[t_near, t_far], self),
+			None => Vec::new(),
+		}
+	}
+
+	fn calc_normal_at(&self, point: Point3) -> Normal3 {
+		self.calc_face_frame_at(point).0
+	}
+
+	fn calc_uv_at(&self, point: Point3) -> UV {
+		self.calc_face_frame_at(point).1
+	}
+
+	fn calc_tangents_at(&self, point: Point3) -> (Vector3, Vector3) {
+		let (_, _, dp_du, dp_dv) = self.calc_face_frame_at(point);
+		(dp_du, dp_dv)
+	}
+
+	fn calc_bounds(&self) -> Option<Bounds> {
+		Some(Bounds::new(self.min, self.max))
+	}
+
+	fn write_scene_commands(&self, writer: &mut SceneWriter) -> Result<(), String> {
+		writer.write_object(self.material_index, &self.transform, format!("box {} {} {} {} {} {}",
+			self.min.x, self.min.y, self.min.z, self.max.x, self.max.y, self.max.z))
+	}
+}
+
+impl Cuboid {
+	// slab method: intersects the ray's parameter ranges inside each pair of parallel faces
+	fn calc_slab_range(&self, ray: &Ray) -> Option<(Float, Float)> {
+		let origin = [ray.origin.x, ray.origin.y, ray.origin.z];
+		let direction = [ray.direction.x, ray.direction.y, ray.direction.z];
+		let min = [self.min.x, self.min.y, self.min.z];
+		let max = [self.max.x, self.max.y, self.max.z];
+
+		let mut t_near = Float::NEG_INFINITY;
+		let mut t_far = Float::INFINITY;
+		for axis in 0..3 {
+			let inv_direction = 1. / direction[axis];
+			let mut t0 = (min[axis] - origin[axis]) * inv_direction;
+			let mut t1 = (max[axis] - origin[axis]) * inv_direction;
+			if t0 > t1 {
+				::std::mem::swap(&mut t0, &mut t1);
+			}
+			t_near = t_near.max(t0);
+			t_far = t_far.min(t1);
+		}
+
+		if t_near > t_far { None } else { Some((t_near, t_far)) }
+	}
+
+	// normal, UVs and tangents of the face containing the point; each face is mapped to the unit square
+	fn calc_face_frame_at(&self, point: Point3) -> (Normal3, UV, Vector3, Vector3) {
+		let size = self.max - self.min;
+		let center = self.min + 0.5 * size;
+		// the face is the one where the point is furthest from the center, relative to the box's size
+		let rel = [(point.x - center.x) / size.x, (point.y - center.y) / size.y, (point.z - center.z) / size.z];
+		let (u, v, w) = ((point.x - self.min.x) / size.x, (point.y - self.min.y) / size.y, (point.z - self.min.z) / size.z);
+		let (dir, normal) = (Vector3::new, Normal3::new);
+
+		if rel[0].abs() >= rel[1].abs() && rel[0].abs() >= rel[2].abs() {
+			if rel[0] > 0. {
+				(normal(1., 0., 0.), UV { u: 1. - w, v: v }, dir(0., 0., -size.z), dir(0., size.y, 0.))
+			} else {
+				(normal(-1., 0., 0.), UV { u: w, v: v }, dir(0., 0., size.z), dir(0., size.y, 0.))
+			}
+		} else if rel[1].abs() >= rel[2].abs() {
+			if rel[1] > 0. {
+				(normal(0., 1., 0.), UV { u: u, v: 1. - w }, dir(size.x, 0., 0.), dir(0., 0., -size.z))
+			} else {
+				(normal(0., -1., 0.), UV { u: u, v: w }, dir(size.x, 0., 0.), dir(0., 0., size.z))
+			}
+		} else if rel[2] > 0. {
+			(normal(0., 0., 1.), UV { u: u, v: v }, dir(size.x, 0., 0.), dir(0., size.y, 0.))
+		} else {
+			(normal(0., 0., -1.), UV { u: 1. - u, v: v }, dir(-size.x, 0., 0.), dir(0., size.y, 0.))
+		}
+	}
+}
+
+// ---------- Quad ----------
+
+// Parallelogram with a corner at pos and sides along edge_u and edge_v.
+#[derive(Debug)]
+pub struct Quad {
+	pub pos: Point3,
+	pub edge_u: Vector3,
+	pub edge_v: Vector3,
+	pub normal: Normal3,
+	pub material_index: usize,	// in the material table of the scene
+	pub transform: Mat4,
+	pub inverse_transform: Mat4,
+}
+
+impl Graphic for Quad {
+	fn get_material_index(&self) -> usize {
+		self.material_index
+	}
+	fn get_type_name(&self) -> &'static str {
+		"quad"
+	}
+	fn get_transform(&self) -> &Mat4 {
+		&self.transform
+	}
+	fn get_inverse_transform(&self) -> &Mat4 {
+		&self.inverse_transform
+	}
+	fn set_transform(&mut self, transform: Mat4, inverse_transform: Mat4) {
+		self.transform = transform;
+		self.inverse_transform = inverse_transform;
+	}
+
+	fn test_intersection(&self, ray: &Ray) -> Option<Point3> {
+		let hit = test_plane_intersection(ray, self.pos, self.normal)?;
+		let uv = self.calc_uv_at(hit);
+		if uv.u >= 0. && uv.u <= 1. && uv.v >= 0. && uv.v <= 1. {
+			Some(hit)
+		} else {
+			None
+		}
+	}
+
+	fn test_closest_hit(&self, ray: &Ray) -> Option<Hit<'_>> {
+		self.test_intersection(ray).map(|point| Hit::from_point(ray, point, self))
+	}
+
+	fn test_intersection_intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+		get_flat_intervals(ray, self.test_intersection(ray), self)
+	}
+
+	fn calc_normal_at(&self, _point: Point3) -> Normal3 {
+		self.normal
+	}
+
+	fn calc_uv_at(&self, point: Point3) -> UV {
+		// coordinates of the point along the (not necessarily perpendicular) edges
+		let n = self.edge_u.cross(self.edge_v);
+		let to_point = point - self.pos;
+		let inv_n_sq = 1. / n.length_sq();
+		UV {
+			u: to_point.cross(self.edge_v).dot(n) * inv_n_sq,
+			v: self.edge_u.cross(to_point).dot(n) * inv_n_sq,
+		}
+	}
+
+	fn calc_tangents_at(&self, _point: Point3) -> (Vector3, Vector3) {
+		(self.edge_u, self.edge_v)
+	}
+
+	fn calc_bounds(&self) -> Option<Bounds> {
+		Some(Bounds::from_points(&[self.pos, self.pos + self.edge_u, self.pos + self.edge_v, self.pos + self.edge_u + self.edge_v]))
+	}
+
+	fn write_scene_commands(&self, writer: &mut SceneWriter) -> Result<(), String> {
+		writer.write_object(self.material_index, &self.transform, format!("quad {} {} {} {} {} {} {} {} {}",
+			self.pos.x, self.pos.y, self.pos.z, self.edge_u.x, self.edge_u.y, self.edge_u.z, self.edge_v.x, self.edge_v.y, self.edge_v.z))
+	}
+
+	fn is_planar(&self) -> bool {
+		true
+	}
 }
@@ -0,0 +1,202 @@
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use float::*;
+use structs::*;
+
+// Reader for Radiance RGBE (.hdr) images, as commonly used for environment maps.
+
+fn invalid(msg: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, format!("invalid HDR: {}", msg))
+}
+
+/// Returns the width, height and pixels (row-major, first row at the top) of the image.
+pub fn read_hdr_file(file_path: &str) -> Result<(u32, u32, Vec<Color>), io::Error> {
+	let mut bytes = Vec::new();
+	fs::File::open(file_path)?.read_to_end(&mut bytes)?;
+
+	if !bytes.starts_with(b"#?") {
+		return Err(invalid("missing header"));
+	}
+
+	// the header is a list of text lines ending with an empty one, followed by the resolution line
+	let mut pos = 0;
+	let read_line = |pos: &mut usize| -> Result<String, io::Error> {
+		let start = *pos;
+		while *pos < bytes.len() && bytes[*pos] != b'\n' {
+			*pos += 1;
+		}
+		if *pos >= bytes.len() {
+			return Err(invalid("header is truncated"));
+		}
+		*pos += 1;
+		Ok(String::from_utf8_lossy(&bytes[start..*pos-1]).into_owned())
+	};
+
+	loop {
+		let line = read_line(&mut pos)?;
+		if line.starts_with("FORMAT=") && line.trim() != "FORMAT=32-bit_rle_rgbe" {
+			return Err(invalid("only the RGBE format is supported"));
+		}
+		if line.trim().is_empty() {
+			break;
+		}
+	}
+
+	let resolution = read_line(&mut pos)?;
+	let tokens: Vec<&str> = resolution.split_whitespace().collect();
+	if tokens.len() != 4 || tokens[0] != "-Y" || tokens[2] != "+X" {
+		return Err(invalid("only the standard -Y +X orientation is supported"));
+	}
+	let height = tokens[1].parse::<u32>().map_err(|_| invalid("bad height"))?;
+	let width = tokens[3].parse::<u32>().map_err(|_| invalid("bad width"))?;
+
+	let n_pixels = width.checked_mul(height).ok_or_else(|| invalid("image too large"))?;
+	// run-length encoding stores at most 127 pixels of each of the 4 channels in 2 bytes
+	if ((bytes.len() - pos) as u64) * 127 < n_pixels as u64 * 8 {
+		return Err(invalid("pixel data is truncated"));
+	}
+
+	let mut pixels = Vec::with_capacity(n_pixels as usize);
+	let mut scanline = vec![[0u8; 4]; width as usize];
+	for _ in 0..height {
+		pos = read_scanline(&bytes, pos, &mut scanline)?;
+		for rgbe in &scanline {
+			pixels.push(rgbe_to_color(*rgbe));
+		}
+	}
+
+	Ok((width, height, pixels))
+}
+
+fn read_scanline(bytes: &[u8], mut pos: usize, scanline: &mut [[u8; 4]]) -> Result<usize, io::Error> {
+	let width = scanline.len();
+	if pos + 4 > bytes.len() {
+		return Err(invalid("pixel data is truncated"));
+	}
+
+	let is_rle = (8..32768).contains(&width) && bytes[pos] == 2 && bytes[pos+1] == 2 && bytes[pos+2] & 0x80 == 0;
+	if !is_rle {
+		// flat scanline
+		if pos + 4 * width > bytes.len() {
+			return Err(invalid("pixel data is truncated"));
+		}
+		for (i, pixel) in scanline.iter_mut().enumerate() {
+			pixel.copy_from_slice(&bytes[pos + 4*i..pos + 4*i + 4]);
+		}
+		return Ok(pos + 4 * width);
+	}
+
+	if ((bytes[pos+2] as usize) << 8 | bytes[pos+3] as usize) != width {
+		return Err(invalid("scanline width mismatch"));
+	}
+	pos += 4;
+
+	// each of the four components is run-length encoded separately
+	for component in 0..4 {
+		let mut x = 0;
+		while x < width {
+			if pos >= bytes.len() {
+				return Err(invalid("pixel data is truncated"));
+			}
+			let count = bytes[pos] as usize;
+			pos += 1;
+			if count > 128 {
+				let run = count - 128;
+				if x + run > width || pos >= bytes.len() {
+					return Err(invalid("bad run length"));
+				}
+				for pixel in &mut scanline[x..x+run] {
+					pixel[component] = bytes[pos];
+				}
+				pos += 1;
+				x += run;
+			} else {
+				if count == 0 || x + count > width || pos + count > bytes.len() {
+					return Err(invalid("bad run length"));
+				}
+				for (i, pixel) in scanline[x..x+count].iter_mut().enumerate() {
+					pixel[component] = bytes[pos + i];
+				}
+				pos += count;
+				x += count;
+			}
+		}
+	}
+	Ok(pos)
+}
+
+fn rgbe_to_color(rgbe: [u8; 4]) -> Color {
+	if rgbe[3] == 0 {
+		return Color::black();
+	}
+	let scale = (2. as Float).powi(rgbe[3] as i32 - (128 + 8));
+	Color { r: rgbe[0] as Float * scale, g: rgbe[1] as Float * scale, b: rgbe[2] as Float * scale }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::env;
+	use std::process;
+
+	// reads the bytes as an .hdr file
+	fn read_hdr_bytes(name: &str, bytes: &[u8]) -> Result<(u32, u32, Vec<Color>), io::Error> {
+		let path = env::temp_dir().join(format!("raytracer-test-{}-{}.hdr", process::id(), name)).to_string_lossy().into_owned();
+		fs::File::create(&path)?.write_all(bytes)?;
+		let result = read_hdr_file(&path);
+		fs::remove_file(&path)?;
+		result
+	}
+
+	fn header(width: u32, height: u32) -> Vec<u8> {
+		format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", height, width).into_bytes()
+	}
+
+	#[test]
+	fn reads_run_length_encoded_scanlines() {
+		let mut bytes = header(8, 1);
+		bytes.extend_from_slice(&[2, 2, 0, 8]);
+		bytes.extend_from_slice(&[136, 128]);	// red: a run of 8
+		bytes.extend_from_slice(&[8, 0, 16, 32, 48, 64, 80, 96, 112]);	// green: 8 literals
+		bytes.extend_from_slice(&[4, 1, 2, 3, 4, 132, 0]);	// blue: 4 literals, then a run of 4
+		bytes.extend_from_slice(&[136, 129]);	// exponent: a run of 8, for a scale of 1/128
+		let (width, height, pixels) = read_hdr_bytes("rle", &bytes).unwrap();
+		assert_eq!((width, height, pixels.len()), (8, 1, 8));
+		for (x, pixel) in pixels.iter().enumerate() {
+			assert_eq!(pixel.r, 1.);
+			assert_eq!(pixel.g, (16 * x) as Float / 128.);
+			assert_eq!(pixel.b, if x < 4 { (x + 1) as Float / 128. } else { 0. });
+		}
+	}
+
+	#[test]
+	fn reads_flat_scanlines() {
+		// too narrow to be run-length encoded
+		let mut bytes = header(2, 2);
+		bytes.extend_from_slice(&[128, 64, 32, 129, 0, 0, 0, 0, 1, 2, 3, 136, 255, 0, 0, 128]);
+		let (width, height, pixels) = read_hdr_bytes("flat", &bytes).unwrap();
+		assert_eq!((width, height), (2, 2));
+		assert_eq!((pixels[0].r, pixels[0].g, pixels[0].b), (1., 0.5, 0.25));
+		assert_eq!((pixels[1].r, pixels[1].g, pixels[1].b), (0., 0., 0.));
+		assert_eq!((pixels[2].r, pixels[2].g, pixels[2].b), (1., 2., 3.));
+		assert_eq!(pixels[3].r, 255. / 256.);
+	}
+
+	#[test]
+	fn rejects_bad_files() {
+		let mut bytes = header(8, 1);
+		bytes.extend_from_slice(&[2, 2, 0, 8, 140, 1]);	// a run past the end of the scanline
+		assert!(read_hdr_bytes("run", &bytes).is_err());
+		let mut bytes = header(2, 2);
+		bytes.extend_from_slice(&[0; 12]);	// the last pixel is missing
+		assert!(read_hdr_bytes("truncated", &bytes).is_err());
+		let mut bytes = header(65536, 65536);
+		bytes.extend_from_slice(&[0; 16]);
+		assert!(read_hdr_bytes("overflow", &bytes).is_err());
+		let mut bytes = header(30000, 30000);	// far more pixels than the data can hold
+		bytes.extend_from_slice(&[2, 2, 117, 48]);
+		assert!(read_hdr_bytes("huge", &bytes).is_err());
+		assert!(read_hdr_bytes("header", b"P6\n2 2\n255\n").is_err());
+	}
+}
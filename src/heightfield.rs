@@ -0,0 +1,260 @@
+use std::mem;
+use float::*;
+use structs::*;
+use vec3::*;
+use mat4::*;
+use graphics::*;
+use bvh::*;
+use bmp::*;
+use scene_writer::*;
+
+// ---------- Heightfield ----------
+
+// Terrain over the unit square of the xz plane, with heights in [0, 1] along y taken from the luminance of an image.
+// Image columns go along x and rows along z, each pixel being a vertex of a grid of cells split in two triangles.
+// Normals are interpolated from the vertices and UVs follow the image, so that a texture of the same layout drapes over it.
+#[derive(Debug)]
+pub struct Heightfield {
+	pub columns: usize,
+	pub rows: usize,
+	pub heights: Vec<Float>,	// row-major
+	pub normals: Vec<Normal3>,	// one per vertex
+	pub max_height: Float,
+	pub material_index: usize,	// in the material table of the scene
+	pub transform: Mat4,
+	pub inverse_transform: Mat4,
+}
+
+impl Heightfield {
+	// fails if the image has too few pixels or the transform cannot be inverted
+	pub fn from_image(image: &BmpImage, material_index: usize, transform: Mat4) -> Result<Heightfield, String> {
+		let (columns, rows) = (image.width as usize, image.height as usize);
+		if columns < 2 || rows < 2 {
+			return Err(String::from("heightfield images need at least 2x2 pixels"));
+		}
+		let inverse_transform = transform.invert().ok_or_else(|| String::from("the transform is singular"))?;
+
+		let mut heights = Vec::with_capacity(columns * rows);
+		for row in 0..rows {
+			for col in 0..columns {
+				let (r, g, b) = image.get_pixel(row as u32, col as u32);
+				heights.push(Color { r: r, g: g, b: b }.luminance());
+			}
+		}
+
+		// vertex normals from the central differences of the heights (one-sided at the borders)
+		let (cell_x, cell_z) = (1. / (columns - 1) as Float, 1. / (rows - 1) as Float);
+		let mut normals = Vec::with_capacity(columns * rows);
+		for row in 0..rows {
+			for col in 0..columns {
+				let (col0, col1) = (col.saturating_sub(1), (col + 1).min(columns - 1));
+				let (row0, row1) = (row.saturating_sub(1), (row + 1).min(rows - 1));
+				let dh_dx = (heights[row * columns + col1] - heights[row * columns + col0]) / ((col1 - col0) as Float * cell_x);
+				let dh_dz = (heights[row1 * columns + col] - heights[row0 * columns + col]) / ((row1 - row0) as Float * cell_z);
+				normals.push(Normal3::new(-dh_dx, 1., -dh_dz).normalize());
+			}
+		}
+
+		Ok(Heightfield {
+			columns: columns,
+			rows: rows,
+			max_height: heights.iter().cloned().fold(0., Float::max),
+			heights: heights,
+			normals: normals,
+			material_index: material_index,
+			transform: transform,
+			inverse_transform: inverse_transform,
+		})
+	}
+
+	fn get_vertex(&self, col: usize, row: usize) -> Point3 {
+		Point3::new(
+			col as Float / (self.columns - 1) as Float,
+			self.heights[row * self.columns + col],
+			row as Float / (self.rows - 1) as Float,
+		)
+	}
+
+	// cell containing the point and the position inside it, in [0, 1]
+	fn locate(&self, point: Point3) -> (usize, usize, Float, Float) {
+		let x = (point.x * (self.columns - 1) as Float).clamp(0., (self.columns - 1) as Float);
+		let z = (point.z * (self.rows - 1) as Float).clamp(0., (self.rows - 1) as Float);
+		let col = (x.floor() as usize).min(self.columns - 2);
+		let row = (z.floor() as usize).min(self.rows - 2);
+		(col, row, x - col as Float, z - row as Float)
+	}
+
+	// weights of the corners (col, row), (col+1, row), (col, row+1) and (col+1, row+1) for a position inside a cell,
+	// which is split along its diagonal from the first corner to the last
+	fn calc_corner_weights(fx: Float, fz: Float) -> [Float; 4] {
+		if fx >= fz {
+			[1. - fx, fx - fz, 0., fz]
+		} else {
+			[1. - fz, 0., fz - fx, fx]
+		}
+	}
+
+	// closest hit with either triangle of a cell
+	fn test_cell(&self, ray: &Ray, col: usize, row: usize) -> Option<Float> {
+		let p00 = self.get_vertex(col, row);
+		let p10 = self.get_vertex(col + 1, row);
+		let p01 = self.get_vertex(col, row + 1);
+		let p11 = self.get_vertex(col + 1, row + 1);
+
+		let hits = [test_triangle_intersection(ray, &[p00, p10, p11]), test_triangle_intersection(ray, &[p00, p11, p01])];
+		hits.iter().filter_map(|&hit| hit).map(|(t, _)| t).fold(None, |closest: Option<Float>, t| {
+			Some(closest.map_or(t, |closest| closest.min(t)))
+		})
+	}
+
+	// part of the ray's range inside the bounding box of the terrain
+	fn calc_bounding_range(&self, ray: &Ray) -> Option<(Float, Float)> {
+		let origin = [ray.origin.x, ray.origin.y, ray.origin.z];
+		let direction = [ray.direction.x, ray.direction.y, ray.direction.z];
+		let max = [1., self.max_height, 1.];
+
+		let mut t_near = Float::NEG_INFINITY;
+		let mut t_far = Float::INFINITY;
+		for axis in 0..3 {
+			let inv_direction = 1. / direction[axis];
+			let mut t0 = (0. - origin[axis]) * inv_direction;
+			let mut t1 = (max[axis] - origin[axis]) * inv_direction;
+			if t0 > t1 {
+				::std::mem::swap(&mut t0, &mut t1);
+			}
+			t_near = t_near.max(t0);
+			t_far = t_far.min(t1);
+		}
+
+		t_near = t_near.max(ray.t_min);
+		t_far = t_far.min(ray.t_max);
+		if t_near > t_far { None } else { Some((t_near, t_far)) }
+	}
+
+	// walks the cells crossed by the ray in order (2D DDA over the xz grid), stopping at the first one that is hit
+	fn march(&self, ray: &Ray) -> Option<Float> {
+		let (t_start, t_end) = self.calc_bounding_range(ray)?;
+		let start = ray.origin + t_start * ray.direction;
+		let (mut col, mut row, _, _) = self.locate(start);
+
+		// grid coordinates advance this much per unit of t
+		let speed_x = ray.direction.x * (self.columns - 1) as Float;
+		let speed_z = ray.direction.z * (self.rows - 1) as Float;
+		let grid_x = start.x * (self.columns - 1) as Float;
+		let grid_z = start.z * (self.rows - 1) as Float;
+
+		let next_boundary = |grid: Float, cell: usize, speed: Float| -> Float {
+			if speed > 0. {
+				t_start + ((cell + 1) as Float - grid) / speed
+			} else if speed < 0. {
+				t_start + (cell as Float - grid) / speed
+			} else {
+				Float::INFINITY
+			}
+		};
+		let mut t_next_x = next_boundary(grid_x, col, speed_x);
+		let mut t_next_z = next_boundary(grid_z, row, speed_z);
+		let (t_delta_x, t_delta_z) = (1. / speed_x.abs(), 1. / speed_z.abs());
+
+		loop {
+			if let Some(t) = self.test_cell(ray, col, row) {
+				return Some(t);
+			}
+
+			if t_next_x < t_next_z {
+				if t_next_x > t_end { return None; }
+				t_next_x += t_delta_x;
+				if speed_x > 0. {
+					col += 1;
+					if col >= self.columns - 1 { return None; }
+				} else {
+					if col == 0 { return None; }
+					col -= 1;
+				}
+			} else {
+				if t_next_z > t_end { return None; }
+				t_next_z += t_delta_z;
+				if speed_z > 0. {
+					row += 1;
+					if row >= self.rows - 1 { return None; }
+				} else {
+					if row == 0 { return None; }
+					row -= 1;
+				}
+			}
+		}
+	}
+
+	fn calc_interpolated_normal(&self, point: Point3) -> Normal3 {
+		let (col, row, fx, fz) = self.locate(point);
+		let weights = Heightfield::calc_corner_weights(fx, fz);
+		let corners = [(col, row), (col + 1, row), (col, row + 1), (col + 1, row + 1)];
+
+		let mut normal = Vector3::new(0., 0., 0.);
+		for (&(col, row), &weight) in corners.iter().zip(weights.iter()) {
+			normal = normal + weight * self.normals[row * self.columns + col].to_vector();
+		}
+		normal.normalize().to_normal()
+	}
+}
+
+impl Graphic for Heightfield {
+	fn get_material_index(&self) -> usize {
+		self.material_index
+	}
+	fn get_type_name(&self) -> &'static str {
+		"heightfield"
+	}
+	fn get_transform(&self) -> &Mat4 {
+		&self.transform
+	}
+	fn get_inverse_transform(&self) -> &Mat4 {
+		&self.inverse_transform
+	}
+	fn set_transform(&mut self, transform: Mat4, inverse_transform: Mat4) {
+		self.transform = transform;
+		self.inverse_transform = inverse_transform;
+	}
+
+	fn test_intersection(&self, ray: &Ray) -> Option<Point3> {
+		self.march(ray).map(|t| ray.origin + t * ray.direction)
+	}
+
+	fn test_closest_hit(&self, ray: &Ray) -> Option<Hit<'_>> {
+		self.test_intersection(ray).map(|point| Hit::from_point(ray, point, self))
+	}
+
+	fn test_intersection_intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+		// the terrain is an open surface
+		get_flat_intervals(ray, self.test_intersection(ray), self)
+	}
+
+	fn calc_normal_at(&self, point: Point3) -> Normal3 {
+		self.calc_interpolated_normal(point)
+	}
+
+	fn calc_uv_at(&self, point: Point3) -> UV {
+		// image rows go down, along z
+		UV { u: point.x, v: 1. - point.z }
+	}
+
+	fn calc_tangents_at(&self, point: Point3) -> (Vector3, Vector3) {
+		// the surface is y = h(x, z), with u = x and v = 1 - z
+		let normal = self.calc_interpolated_normal(point);
+		let dh_dx = -normal.x / normal.y;
+		let dh_dz = -normal.z / normal.y;
+		(Vector3::new(1., dh_dx, 0.), Vector3::new(0., -dh_dz, -1.))
+	}
+
+	fn calc_bounds(&self) -> Option<Bounds> {
+		Some(Bounds::new(Point3::origin(), Point3::new(1., self.max_height, 1.)))
+	}
+
+	fn write_scene_commands(&self, _writer: &mut SceneWriter) -> Result<(), String> {
+		Err(String::from("heightfields cannot be written to scene files, as their image is not kept"))
+	}
+
+	fn estimate_memory(&self) -> usize {
+		mem::size_of_val(self) + self.heights.capacity() * mem::size_of::<Float>() + self.normals.capacity() * mem::size_of::<Normal3>()
+	}
+}
@@ -0,0 +1,249 @@
+use float::*;
+
+// Reader for JSON documents, as used by the JSON scene format. Numbers are read directly as Float, like the numbers of
+// scene files, and objects keep their members in order.
+
+#[derive(Debug,Clone)]
+pub enum JsonValue {
+	Null,
+	Bool(bool),
+	Number(Float),
+	String(String),
+	Array(Vec<JsonValue>),
+	Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+	pub fn get(&self, key: &str) -> Option<&JsonValue> {
+		match *self {
+			JsonValue::Object(ref members) => members.iter().find(|member| member.0 == key).map(|member| &member.1),
+			_ => None,
+		}
+	}
+
+	// name of the kind of value, for error messages
+	pub fn describe(&self) -> &'static str {
+		match *self {
+			JsonValue::Null => "null",
+			JsonValue::Bool(_) => "a boolean",
+			JsonValue::Number(_) => "a number",
+			JsonValue::String(_) => "a string",
+			JsonValue::Array(_) => "an array",
+			JsonValue::Object(_) => "an object",
+		}
+	}
+}
+
+/// Parses a whole document, reporting errors with their line and column.
+pub fn parse_json(text: &str) -> Result<JsonValue, String> {
+	let mut parser = JsonParser { chars: text.chars().collect(), pos: 0 };
+	let value = parser.parse_value().map_err(|msg| parser.locate(msg))?;
+	parser.skip_whitespace();
+	if parser.pos < parser.chars.len() {
+		return Err(parser.locate(String::from("unexpected characters after the document")));
+	}
+	Ok(value)
+}
+
+struct JsonParser {
+	chars: Vec<char>,
+	pos: usize,
+}
+
+impl JsonParser {
+	fn locate(&self, msg: String) -> String {
+		let before = &self.chars[..self.pos.min(self.chars.len())];
+		let line = before.iter().filter(|&&c| c == '\n').count() + 1;
+		let column = before.iter().rev().take_while(|&&c| c != '\n').count() + 1;
+		format!("line {}, column {}: {}", line, column, msg)
+	}
+
+	fn skip_whitespace(&mut self) {
+		while self.pos < self.chars.len() && self.chars[self.pos].is_whitespace() {
+			self.pos += 1;
+		}
+	}
+
+	fn peek(&self) -> Option<char> {
+		self.chars.get(self.pos).cloned()
+	}
+
+	fn expect(&mut self, expected: char) -> Result<(), String> {
+		self.skip_whitespace();
+		match self.peek() {
+			Some(c) if c == expected => { self.pos += 1; Ok(()) }
+			Some(c) => Err(format!("expected '{}' but found '{}'", expected, c)),
+			None => Err(format!("expected '{}' but the document ended", expected)),
+		}
+	}
+
+	fn parse_value(&mut self) -> Result<JsonValue, String> {
+		self.skip_whitespace();
+		match self.peek() {
+			Some('{') => self.parse_object(),
+			Some('[') => self.parse_array(),
+			Some('"') => Ok(JsonValue::String(self.parse_string()?)),
+			Some('t') => self.parse_literal("true", JsonValue::Bool(true)),
+			Some('f') => self.parse_literal("false", JsonValue::Bool(false)),
+			Some('n') => self.parse_literal("null", JsonValue::Null),
+			Some(c) if c == '-' || c.is_ascii_digit() => self.parse_number(),
+			Some(c) => Err(format!("unexpected character '{}'", c)),
+			None => Err(String::from("the document ended where a value was expected")),
+		}
+	}
+
+	fn parse_literal(&mut self, literal: &str, value: JsonValue) -> Result<JsonValue, String> {
+		for expected in literal.chars() {
+			if self.peek() != Some(expected) {
+				return Err(format!("expected {}", literal));
+			}
+			self.pos += 1;
+		}
+		Ok(value)
+	}
+
+	fn parse_number(&mut self) -> Result<JsonValue, String> {
+		let start = self.pos;
+		while let Some(c) = self.peek() {
+			if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E' {
+				self.pos += 1;
+			} else {
+				break;
+			}
+		}
+		let token: String = self.chars[start..self.pos].iter().collect();
+		match token.parse::<Float>() {
+			Ok(number) => Ok(JsonValue::Number(number)),
+			Err(_) => { self.pos = start; Err(format!("bad number {}", token)) }
+		}
+	}
+
+	fn parse_string(&mut self) -> Result<String, String> {
+		self.expect('"')?;
+		let mut string = String::new();
+		loop {
+			let c = self.peek().ok_or("the document ended inside a string")?;
+			self.pos += 1;
+			match c {
+				'"' => { return Ok(string); }
+				'\\' => {
+					let escaped = self.peek().ok_or("the document ended inside a string")?;
+					self.pos += 1;
+					match escaped {
+						'"' | '\\' | '/' => { string.push(escaped); }
+						'b' => { string.push('\u{8}'); }
+						'f' => { string.push('\u{c}'); }
+						'n' => { string.push('\n'); }
+						'r' => { string.push('\r'); }
+						't' => { string.push('\t'); }
+						'u' => {
+							// surrogate pairs are not combined, as scene files have no use for them
+							let digits: String = self.chars.iter().skip(self.pos).take(4).collect();
+							let code = u32::from_str_radix(&digits, 16).map_err(|_| format!("bad escape \\u{}", digits))?;
+							string.push(::std::char::from_u32(code).unwrap_or('\u{fffd}'));
+							self.pos += 4;
+						}
+						_ => { return Err(format!("bad escape \\{}", escaped)); }
+					}
+				}
+				_ => { string.push(c); }
+			}
+		}
+	}
+
+	fn parse_array(&mut self) -> Result<JsonValue, String> {
+		self.expect('[')?;
+		let mut values = Vec::new();
+		self.skip_whitespace();
+		if self.peek() == Some(']') {
+			self.pos += 1;
+			return Ok(JsonValue::Array(values));
+		}
+		loop {
+			values.push(self.parse_value()?);
+			self.skip_whitespace();
+			match self.peek() {
+				Some(',') => { self.pos += 1; }
+				Some(']') => { self.pos += 1; return Ok(JsonValue::Array(values)); }
+				_ => { return Err(String::from("expected ',' or ']' in array")); }
+			}
+		}
+	}
+
+	fn parse_object(&mut self) -> Result<JsonValue, String> {
+		self.expect('{')?;
+		let mut members = Vec::new();
+		self.skip_whitespace();
+		if self.peek() == Some('}') {
+			self.pos += 1;
+			return Ok(JsonValue::Object(members));
+		}
+		loop {
+			self.skip_whitespace();
+			let key = self.parse_string()?;
+			self.expect(':')?;
+			members.push((key, self.parse_value()?));
+			self.skip_whitespace();
+			match self.peek() {
+				Some(',') => { self.pos += 1; }
+				Some('}') => { self.pos += 1; return Ok(JsonValue::Object(members)); }
+				_ => { return Err(String::from("expected ',' or '}' in object")); }
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn number(value: Option<&JsonValue>) -> Float {
+		match value {
+			Some(&JsonValue::Number(number)) => number,
+			other => panic!("expected a number, found {:?}", other),
+		}
+	}
+
+	#[test]
+	fn parses_nested_values() {
+		let document = parse_json(r#" { "size": [640, -4.5e2], "name": "a \"b\"\nA", "on": true, "off": false, "none": null, "empty": {} } "#).unwrap();
+		match document.get("size") {
+			Some(JsonValue::Array(values)) => {
+				assert_eq!(values.len(), 2);
+				assert_eq!(number(values.first()), 640.);
+				assert_eq!(number(values.get(1)), -450.);
+			}
+			other => panic!("expected an array, found {:?}", other),
+		}
+		match document.get("name") {
+			Some(JsonValue::String(name)) => { assert_eq!(name, "a \"b\"\nA"); }
+			other => panic!("expected a string, found {:?}", other),
+		}
+		assert!(matches!(document.get("on"), Some(&JsonValue::Bool(true))));
+		assert!(matches!(document.get("off"), Some(&JsonValue::Bool(false))));
+		assert!(matches!(document.get("none"), Some(&JsonValue::Null)));
+		assert!(matches!(document.get("empty"), Some(JsonValue::Object(members)) if members.is_empty()));
+		assert!(document.get("missing").is_none());
+	}
+
+	#[test]
+	fn keeps_the_order_of_members() {
+		match parse_json(r#"{"b": 1, "a": 2, "c": 3}"#).unwrap() {
+			JsonValue::Object(members) => {
+				let keys: Vec<&str> = members.iter().map(|member| member.0.as_str()).collect();
+				assert_eq!(keys, ["b", "a", "c"]);
+			}
+			other => panic!("expected an object, found {:?}", other),
+		}
+	}
+
+	#[test]
+	fn reports_where_errors_are() {
+		assert_eq!(parse_json("{\n  \"a\": tru }").unwrap_err(), "line 2, column 11: expected true");
+		assert_eq!(parse_json("[1, 2").unwrap_err(), "line 1, column 6: expected ',' or ']' in array");
+		assert_eq!(parse_json("[1] 2").unwrap_err(), "line 1, column 5: unexpected characters after the document");
+		assert_eq!(parse_json("{\"a\": 1-2}").unwrap_err(), "line 1, column 7: bad number 1-2");
+		assert!(parse_json("\"unterminated").is_err());
+		assert!(parse_json("").is_err());
+	}
+}
@@ -0,0 +1,42 @@
+//! A simple ray tracer: scenes are parsed from scene description files (or built in code), and rendered into a
+//! framebuffer of colors.
+
+#![allow(clippy::redundant_field_names, clippy::needless_return, clippy::needless_range_loop, clippy::identity_op, clippy::unnecessary_cast)]
+
+pub mod bmp;
+pub mod png;
+pub mod float;
+pub mod vec3;
+pub mod mat4;
+pub mod graphics;
+pub mod lights;
+pub mod structs;
+pub mod scene;
+pub mod scene_parser;
+pub mod scene_builder;
+pub mod scene_writer;
+pub mod scene_json;
+pub mod json;
+pub mod textures;
+pub mod noise;
+pub mod hdr;
+pub mod background;
+pub mod solvers;
+pub mod csg;
+pub mod sdf;
+pub mod heightfield;
+pub mod bvh;
+pub mod render;
+pub mod animation;
+
+pub use float::Float;
+pub use vec3::{Point3, Vector3, Normal3};
+pub use mat4::Mat4;
+pub use structs::{Ray, Color, Material, Brdf, UV};
+pub use graphics::Graphic;
+pub use lights::Light;
+pub use scene::{Scene, Camera};
+pub use scene_parser::parse_scene_file;
+pub use scene_builder::SceneBuilder;
+pub use scene_writer::{write_scene_file, format_scene};
+pub use render::{render, render_with_stats, RenderOptions, RenderStats, Framebuffer};
@@ -1,21 +1,25 @@
-use std::f64;
+use float::*;
 use structs::*;
-use vec4::*;
+use vec3::*;
+use scene_writer::*;
 
 // ---------- Light ----------
 
 pub trait Light {
 	fn get_color(&self) -> Color;
-	fn calc_attenuation_at_distance(&self, distance: f64) -> f64;
-	fn calc_to_light_direction(&self, point: &Vec4) -> Vec4;
-	fn calc_to_light_distance(&self, point: &Vec4) -> f64;
+	fn calc_attenuation_at_distance(&self, distance: Float) -> Float;
+	fn calc_to_light_direction(&self, point: &Point3) -> Vector3;
+	fn calc_to_light_distance(&self, point: &Point3) -> Float;
+	fn write_scene_commands(&self, _writer: &mut SceneWriter) -> Result<(), String> {
+		Err(String::from("a light cannot be written to a scene file"))
+	}
 }
 
 // ---------- PointLight ----------
 
 #[derive(Debug)]
 pub struct PointLight {
-	pub pos: Vec4,
+	pub pos: Point3,
 	pub color: Color,
 	pub attenuation: Attenuation,
 }
@@ -25,26 +29,32 @@ impl Light for PointLight {
 		self.color
 	}
 
-	fn calc_attenuation_at_distance(&self, distance: f64) -> f64 {
+	fn calc_attenuation_at_distance(&self, distance: Float) -> Float {
 		self.attenuation.constant
 		+ self.attenuation.linear * distance
 		+ self.attenuation.quadratic * distance * distance
 	}
 
-	fn calc_to_light_direction(&self, point: &Vec4) -> Vec4 {
+	fn calc_to_light_direction(&self, point: &Point3) -> Vector3 {
 		(self.pos - *point).normalize()
 	}
 
-	fn calc_to_light_distance(&self, point: &Vec4) -> f64 {
+	fn calc_to_light_distance(&self, point: &Point3) -> Float {
 		(self.pos - *point).length()
 	}
+
+	fn write_scene_commands(&self, writer: &mut SceneWriter) -> Result<(), String> {
+		writer.set_attenuation(&self.attenuation);
+		writer.write_line(format!("point {} {} {} {}", self.pos.x, self.pos.y, self.pos.z, format_color(self.color)));
+		Ok(())
+	}
 }
 
 // ---------- DirectionalLight ----------
 
 #[derive(Debug)]
 pub struct DirectionalLight {
-	pub direction: Vec4,
+	pub direction: Vector3,
 	pub color: Color,
 }
 
@@ -53,15 +63,21 @@ impl Light for DirectionalLight {
 		self.color
 	}
 
-	fn calc_attenuation_at_distance(&self, _distance: f64) -> f64 {
+	fn calc_attenuation_at_distance(&self, _distance: Float) -> Float {
 		1.
 	}
 
-	fn calc_to_light_direction(&self, _point: &Vec4) -> Vec4 {
+	fn calc_to_light_direction(&self, _point: &Point3) -> Vector3 {
 		self.direction
 	}
 
-	fn calc_to_light_distance(&self, _point: &Vec4) -> f64 {
-		f64::INFINITY
+	fn calc_to_light_distance(&self, _point: &Point3) -> Float {
+		Float::INFINITY
+	}
+
+	fn write_scene_commands(&self, writer: &mut SceneWriter) -> Result<(), String> {
+		let direction = to_exact_direction(self.direction);
+		writer.write_line(format!("directional {} {} {} {}", direction.x, direction.y, direction.z, format_color(self.color)));
+		Ok(())
 	}
 }
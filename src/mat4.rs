@@ -1,14 +1,40 @@
 use std::ops::{Add,Mul};
-use vec4::*;
+use float::*;
+use vec3::*;
 
 #[derive(Debug,Copy,Clone)]
 pub struct Mat4 {
-	pub data: [[f64; 4]; 4]
+	pub data: [[Float; 4]; 4]
+}
+
+// order in which the rotations around each axis of Euler angles are applied
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum RotationOrder {
+	Xyz,
+	Xzy,
+	Yxz,
+	Yzx,
+	Zxy,
+	Zyx,
+}
+
+impl RotationOrder {
+	pub fn parse(order: &str) -> Option<RotationOrder> {
+		match order {
+			"xyz" => Some(RotationOrder::Xyz),
+			"xzy" => Some(RotationOrder::Xzy),
+			"yxz" => Some(RotationOrder::Yxz),
+			"yzx" => Some(RotationOrder::Yzx),
+			"zxy" => Some(RotationOrder::Zxy),
+			"zyx" => Some(RotationOrder::Zyx),
+			_ => None,
+		}
+	}
 }
 // ------------------------------
 // impl<'a> Index<usize> for Mat4 {
-// 	type Output = &'a [f64; 4];
-// 	fn index(&'a self, i: usize) -> &'a [f64; 4] {
+// 	type Output = &'a [Float; 4];
+// 	fn index(&'a self, i: usize) -> &'a [Float; 4] {
 // 		&self.data[i]
 // 	}
 // }
@@ -32,7 +58,7 @@ impl Mul for Mat4 {
 		let mut data = [[0.0; 4]; 4];
 		for row in 0..4 {
 			for col in 0..4 {
-				let mut sum: f64 = 0.0;
+				let mut sum: Float = 0.0;
 				for k in 0..4 {
 					sum += self.data[row][k] * o.data[k][col];
 				}
@@ -43,20 +69,9 @@ impl Mul for Mat4 {
 		Mat4 { data: data }
 	}
 }
-impl Mul<Vec4> for Mat4 {
-	type Output = Vec4;
-	fn mul(self, o: Vec4) -> Vec4 {
-		Vec4 {
-			x: o.x * self.data[0][0] + o.y * self.data[0][1] + o.z * self.data[0][2] + o.w * self.data[0][3],
-			y: o.x * self.data[1][0] + o.y * self.data[1][1] + o.z * self.data[1][2] + o.w * self.data[1][3],
-			z: o.x * self.data[2][0] + o.y * self.data[2][1] + o.z * self.data[2][2] + o.w * self.data[2][3],
-			w: o.x * self.data[3][0] + o.y * self.data[3][1] + o.z * self.data[3][2] + o.w * self.data[3][3],
-		}
-	}
-}
-impl Mul<f64> for Mat4 {
+impl Mul<Float> for Mat4 {
 	type Output = Mat4;
-	fn mul(self, o: f64) -> Mat4 {
+	fn mul(self, o: Float) -> Mat4 {
 		let mut data = [[0.0; 4]; 4];
 		for row in 0..4 {
 			for col in 0..4 {
@@ -67,7 +82,7 @@ impl Mul<f64> for Mat4 {
 		Mat4 { data: data }
 	}
 }
-impl Mul<Mat4> for f64 {
+impl Mul<Mat4> for Float {
 	type Output = Mat4;
 	fn mul(self, o: Mat4) -> Mat4 {
 		o * self
@@ -75,7 +90,7 @@ impl Mul<Mat4> for f64 {
 }
 // // ------------------------------
 impl Mat4 {
-	pub fn id(val: f64) -> Mat4 {
+	pub fn id(val: Float) -> Mat4 {
 		Mat4 {
 			data: [
 				[val, 0.,  0.,  0. ],
@@ -86,7 +101,7 @@ impl Mat4 {
 		}
 	}
 
-	pub fn create_translation(tx: f64, ty: f64, tz: f64) -> Mat4 {
+	pub fn create_translation(tx: Float, ty: Float, tz: Float) -> Mat4 {
 		Mat4 {
 			data: [
 				[1., 0., 0., tx],
@@ -97,7 +112,7 @@ impl Mat4 {
 		}
 	}
 
-	pub fn create_scale(sx: f64, sy: f64, sz: f64) -> Mat4 {
+	pub fn create_scale(sx: Float, sy: Float, sz: Float) -> Mat4 {
 		Mat4 {
 			data: [
 				[sx, 0., 0., 0.],
@@ -108,7 +123,7 @@ impl Mat4 {
 		}
 	}
 
-	pub fn create_rotation(degrees: f64, Vec4 { x, y, z, .. }: Vec4) -> Mat4 {
+	pub fn create_rotation(degrees: Float, Vector3 { x, y, z }: Vector3) -> Mat4 {
 		let m1 = Mat4 {
 			data: [
 				[x*x, x*y, x*z, 0.],
@@ -129,11 +144,94 @@ impl Mat4 {
 		let mut one = Mat4::id(1.);
 		one.data[3][3] = 0.;
 		let radians = degrees.to_radians();
-		let mut res = f64::cos(radians) * one + (1. - f64::cos(radians)) * m1 + f64::sin(radians) * m2;
+		let mut res = Float::cos(radians) * one + (1. - Float::cos(radians)) * m1 + Float::sin(radians) * m2;
 		res.data[3][3] = 1.;
 		res
 	}
 
+	// rotations around the x, y and z axes (in degrees), applied in the given order
+	pub fn create_euler_rotation(x: Float, y: Float, z: Float, order: RotationOrder) -> Mat4 {
+		let rotation_x = Mat4::create_rotation(x, Vector3::new(1., 0., 0.));
+		let rotation_y = Mat4::create_rotation(y, Vector3::new(0., 1., 0.));
+		let rotation_z = Mat4::create_rotation(z, Vector3::new(0., 0., 1.));
+		match order {
+			RotationOrder::Xyz => rotation_z * rotation_y * rotation_x,
+			RotationOrder::Xzy => rotation_y * rotation_z * rotation_x,
+			RotationOrder::Yxz => rotation_z * rotation_x * rotation_y,
+			RotationOrder::Yzx => rotation_x * rotation_z * rotation_y,
+			RotationOrder::Zxy => rotation_y * rotation_x * rotation_z,
+			RotationOrder::Zyx => rotation_x * rotation_y * rotation_z,
+		}
+	}
+
+	// rotation by the quaternion xi + yj + zk + w, which is normalized first (None if it is zero)
+	pub fn create_quaternion_rotation(x: Float, y: Float, z: Float, w: Float) -> Option<Mat4> {
+		let length = Float::sqrt(x*x + y*y + z*z + w*w);
+		if length == 0. {
+			return None;
+		}
+		let (x, y, z, w) = (x / length, y / length, z / length, w / length);
+		Some(Mat4 {
+			data: [
+				[1. - 2.*(y*y + z*z), 2.*(x*y - z*w),      2.*(x*z + y*w),      0.],
+				[2.*(x*y + z*w),      1. - 2.*(x*x + z*z), 2.*(y*z - x*w),      0.],
+				[2.*(x*z - y*w),      2.*(y*z + x*w),      1. - 2.*(x*x + y*y), 0.],
+				[0.,                  0.,                  0.,                  1.],
+			]
+		})
+	}
+
+	// moves the origin to pos and turns the z axis toward the target, with the y axis as close to up as possible
+	// (None if there is no such orientation, e.g. when up points to the target)
+	pub fn create_look_at(pos: Point3, target: Point3, up: Vector3) -> Option<Mat4> {
+		let axis_z = target - pos;
+		let axis_x = up.cross(axis_z);
+		if axis_z.length_sq() == 0. || axis_x.length_sq() == 0. {
+			return None;
+		}
+		let axis_z = axis_z.normalize();
+		let axis_x = axis_x.normalize();
+		let axis_y = axis_z.cross(axis_x);
+		Some(Mat4 {
+			data: [
+				[axis_x.x, axis_y.x, axis_z.x, pos.x],
+				[axis_x.y, axis_y.y, axis_z.y, pos.y],
+				[axis_x.z, axis_y.z, axis_z.z, pos.z],
+				[0.,       0.,       0.,       1.],
+			]
+		})
+	}
+
+	pub fn transform_point(&self, p: Point3) -> Point3 {
+		let m = &self.data;
+		Point3 {
+			x: p.x * m[0][0] + p.y * m[0][1] + p.z * m[0][2] + m[0][3],
+			y: p.x * m[1][0] + p.y * m[1][1] + p.z * m[1][2] + m[1][3],
+			z: p.x * m[2][0] + p.y * m[2][1] + p.z * m[2][2] + m[2][3],
+		}
+	}
+
+	// vectors are not affected by translations
+	pub fn transform_vector(&self, v: Vector3) -> Vector3 {
+		let m = &self.data;
+		Vector3 {
+			x: v.x * m[0][0] + v.y * m[0][1] + v.z * m[0][2],
+			y: v.x * m[1][0] + v.y * m[1][1] + v.z * m[1][2],
+			z: v.x * m[2][0] + v.y * m[2][1] + v.z * m[2][2],
+		}
+	}
+
+	// normals go through the transpose of the inverse transform, so this must be called on the inverse of the
+	// transform applied to the points (the transpose is implicit); the result is not normalized
+	pub fn transform_normal(&self, n: Normal3) -> Normal3 {
+		let m = &self.data;
+		Normal3 {
+			x: n.x * m[0][0] + n.y * m[1][0] + n.z * m[2][0],
+			y: n.x * m[0][1] + n.y * m[1][1] + n.z * m[2][1],
+			z: n.x * m[0][2] + n.y * m[1][2] + n.z * m[2][2],
+		}
+	}
+
 	pub fn transpose(&self) -> Mat4 {
 		let mut data = [[0.0; 4]; 4];
 		for row in 0..4 {
@@ -145,7 +243,7 @@ impl Mat4 {
 		Mat4 { data: data }
 	}
 
-	pub fn invert(&self) -> Result<Mat4,()> {
+	pub fn invert(&self) -> Option<Mat4> {
 		let mut inv = [[0.0; 4]; 4];
 
 		let m = &self.data;
@@ -262,13 +360,13 @@ impl Mat4 {
 					m[2][0] * m[0][1] * m[1][2] -
 					m[2][0] * m[0][2] * m[1][1];
 
-		let pre_det: f64 = m[0][0] * inv[0][0]
+		let pre_det: Float = m[0][0] * inv[0][0]
 						 + m[0][1] * inv[1][0]
 						 + m[0][2] * inv[2][0]
 						 + m[0][3] * inv[3][0];
 
 		if pre_det == 0.0 {
-			return Err(());
+			return None;
 		}
 
 		let det = 1.0 / pre_det;
@@ -279,6 +377,6 @@ impl Mat4 {
 			}
 		}
 
-		return Ok(Mat4 { data: inv });
+		return Some(Mat4 { data: inv });
 	}
 }
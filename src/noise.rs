@@ -0,0 +1,86 @@
+use float::*;
+use vec3::*;
+
+// Ken Perlin's improved noise (2002), using his reference permutation table.
+
+static PERMUTATION: [u8; 256] = [
+	151,160,137,91,90,15,131,13,201,95,96,53,194,233,7,225,140,36,103,30,69,142,8,99,37,240,21,10,23,
+	190,6,148,247,120,234,75,0,26,197,62,94,252,219,203,117,35,11,32,57,177,33,88,237,149,56,87,174,20,
+	125,136,171,168,68,175,74,165,71,134,139,48,27,166,77,146,158,231,83,111,229,122,60,211,133,230,
+	220,105,92,41,55,46,245,40,244,102,143,54,65,25,63,161,1,216,80,73,209,76,132,187,208,89,18,169,
+	200,196,135,130,116,188,159,86,164,100,109,198,173,186,3,64,52,217,226,250,124,123,5,202,38,147,
+	118,126,255,82,85,212,207,206,59,227,47,16,58,17,182,189,28,42,223,183,170,213,119,248,152,2,44,
+	154,163,70,221,153,101,155,167,43,172,9,129,22,39,253,19,98,108,110,79,113,224,232,178,185,112,
+	104,218,246,97,228,251,34,242,193,238,210,144,12,191,179,162,241,81,51,145,235,249,14,239,107,49,
+	192,214,31,181,199,106,157,184,84,204,176,115,121,50,45,127,4,150,254,138,236,205,93,222,114,67,
+	29,24,72,243,141,128,195,78,66,215,61,156,180,
+];
+
+fn perm(i: usize) -> usize {
+	PERMUTATION[i & 255] as usize
+}
+
+fn fade(t: Float) -> Float {
+	t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+fn lerp(t: Float, a: Float, b: Float) -> Float {
+	a + t * (b - a)
+}
+
+fn grad(hash: usize, x: Float, y: Float, z: Float) -> Float {
+	// one of 12 gradient directions, picked by the low 4 bits of the hash
+	let h = hash & 15;
+	let u = if h < 8 { x } else { y };
+	let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+	(if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+/// Gradient noise in roughly [-1,1], zero at integer lattice points.
+pub fn perlin_noise(point: Point3) -> Float {
+	let (xf, yf, zf) = (point.x.floor(), point.y.floor(), point.z.floor());
+	let (xi, yi, zi) = ((xf as i64 & 255) as usize, (yf as i64 & 255) as usize, (zf as i64 & 255) as usize);
+	let (x, y, z) = (point.x - xf, point.y - yf, point.z - zf);
+	let (u, v, w) = (fade(x), fade(y), fade(z));
+
+	let a  = perm(xi) + yi;
+	let aa = perm(a) + zi;
+	let ab = perm(a + 1) + zi;
+	let b  = perm(xi + 1) + yi;
+	let ba = perm(b) + zi;
+	let bb = perm(b + 1) + zi;
+
+	lerp(w,
+		lerp(v,
+			lerp(u, grad(perm(aa),     x,      y,      z),      grad(perm(ba),     x - 1., y,      z)),
+			lerp(u, grad(perm(ab),     x,      y - 1., z),      grad(perm(bb),     x - 1., y - 1., z))),
+		lerp(v,
+			lerp(u, grad(perm(aa + 1), x,      y,      z - 1.), grad(perm(ba + 1), x - 1., y,      z - 1.)),
+			lerp(u, grad(perm(ab + 1), x,      y - 1., z - 1.), grad(perm(bb + 1), x - 1., y - 1., z - 1.))))
+}
+
+/// Fractional Brownian motion: a sum of octaves of noise, each with double the frequency and half the amplitude.
+pub fn fbm(point: Point3, octaves: u32) -> Float {
+	let mut sum = 0.;
+	let mut frequency = 1.;
+	let mut amplitude = 1.;
+	for _ in 0..octaves {
+		sum += amplitude * perlin_noise(point.scale(frequency));
+		frequency *= 2.;
+		amplitude *= 0.5;
+	}
+	sum
+}
+
+/// Like fbm, but summing the absolute value of each octave, which creates sharp creases.
+pub fn turbulence(point: Point3, octaves: u32) -> Float {
+	let mut sum = 0.;
+	let mut frequency = 1.;
+	let mut amplitude = 1.;
+	for _ in 0..octaves {
+		sum += amplitude * perlin_noise(point.scale(frequency)).abs();
+		frequency *= 2.;
+		amplitude *= 0.5;
+	}
+	sum
+}
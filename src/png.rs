@@ -0,0 +1,431 @@
+use std::io;
+use bmp::*;
+
+// Minimal PNG decoder (8 and 16 bit, non-interlaced) with its own inflate implementation,
+// so that textures can be loaded without external dependencies.
+
+pub static PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+fn invalid(msg: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, format!("invalid PNG: {}", msg))
+}
+
+pub fn decode_png(bytes: &[u8]) -> Result<BmpImage, io::Error> {
+	if !bytes.starts_with(&PNG_SIGNATURE) {
+		return Err(invalid("missing signature"));
+	}
+
+	let read_u32 = |at: usize| (bytes[at] as u32) << 24 | (bytes[at+1] as u32) << 16 | (bytes[at+2] as u32) << 8 | bytes[at+3] as u32;
+
+	let mut width = 0;
+	let mut height = 0;
+	let mut bit_depth = 0;
+	let mut color_type = 0;
+	let mut palette: &[u8] = &[];
+	let mut compressed = Vec::new();
+
+	let mut pos = PNG_SIGNATURE.len();
+	while pos + 8 <= bytes.len() {
+		let length = read_u32(pos) as usize;
+		let kind = &bytes[pos+4..pos+8];
+		let start = pos + 8;
+		if start + length + 4 > bytes.len() {
+			return Err(invalid("chunk is truncated"));
+		}
+		let chunk = &bytes[start..start+length];
+
+		match kind {
+			b"IHDR" => {
+				if length < 13 {
+					return Err(invalid("bad IHDR chunk"));
+				}
+				width = read_u32(start);
+				height = read_u32(start+4);
+				bit_depth = chunk[8];
+				color_type = chunk[9];
+				if chunk[12] != 0 {
+					return Err(invalid("interlaced images are not supported"));
+				}
+			},
+			b"PLTE" => { palette = chunk; },
+			b"IDAT" => { compressed.extend_from_slice(chunk); },
+			b"IEND" => { break; },
+			_ => {}
+		}
+		pos = start + length + 4;	// skips the CRC
+	}
+
+	let channels = match color_type {
+		0 => 1,	// grayscale
+		2 => 3,	// RGB
+		3 => 1,	// palette indices
+		4 => 2,	// grayscale + alpha
+		6 => 4,	// RGBA
+		_ => { return Err(invalid("unknown color type")); }
+	};
+	if bit_depth != 8 && !(bit_depth == 16 && color_type != 3) {
+		return Err(invalid("only 8 and 16 bit images are supported"));
+	}
+	if width == 0 || height == 0 {
+		return Err(invalid("bad dimensions"));
+	}
+	if compressed.len() < 2 {
+		return Err(invalid("missing image data"));
+	}
+
+	// skips the 2 byte zlib header
+	let raw = inflate(&compressed[2..])?;
+
+	let bytes_per_sample = (bit_depth / 8) as usize;
+	let bytes_per_pixel = channels * bytes_per_sample;
+	let stride = width as usize * bytes_per_pixel;
+	if raw.len() < height as usize * (stride + 1) {
+		return Err(invalid("image data is truncated"));
+	}
+
+	let mut image = BmpImage::new(width, height);
+	let mut previous = vec![0u8; stride];
+	let mut current = vec![0u8; stride];
+
+	for row in 0..height {
+		let line = &raw[row as usize * (stride+1)..(row as usize + 1) * (stride+1)];
+		unfilter_scanline(line[0], &line[1..], &previous, &mut current, bytes_per_pixel)?;
+
+		for col in 0..width as usize {
+			// for 16 bit samples only the most significant byte is kept
+			let sample = |channel: usize| current[col * bytes_per_pixel + channel * bytes_per_sample];
+			let (r, g, b) = match color_type {
+				0 | 4 => (sample(0), sample(0), sample(0)),
+				3 => {
+					let index = sample(0) as usize * 3;
+					if index + 3 > palette.len() {
+						return Err(invalid("palette index out of range"));
+					}
+					(palette[index], palette[index+1], palette[index+2])
+				},
+				_ => (sample(0), sample(1), sample(2)),
+			};
+			image.set_pixel_bytes(row, col as u32, r, g, b);
+		}
+
+		::std::mem::swap(&mut previous, &mut current);
+	}
+
+	Ok(image)
+}
+
+fn unfilter_scanline(filter: u8, line: &[u8], previous: &[u8], current: &mut [u8], bpp: usize) -> Result<(), io::Error> {
+	for i in 0..line.len() {
+		let a = if i >= bpp { current[i-bpp] as i16 } else { 0 };
+		let b = previous[i] as i16;
+		let c = if i >= bpp { previous[i-bpp] as i16 } else { 0 };
+
+		let predictor = match filter {
+			0 => 0,
+			1 => a,
+			2 => b,
+			3 => (a + b) / 2,
+			4 => {
+				let p = a + b - c;
+				let (pa, pb, pc) = ((p - a).abs(), (p - b).abs(), (p - c).abs());
+				if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+			},
+			_ => { return Err(invalid("unknown scanline filter")); }
+		};
+		current[i] = line[i].wrapping_add(predictor as u8);
+	}
+	Ok(())
+}
+
+// ---------- Inflate ----------
+
+struct BitReader<'a> {
+	data: &'a [u8],
+	pos: usize,
+	bit: u32,
+}
+
+impl<'a> BitReader<'a> {
+	fn read_bits(&mut self, count: u32) -> Result<u32, io::Error> {
+		let mut value = 0;
+		for i in 0..count {
+			if self.pos >= self.data.len() {
+				return Err(invalid("compressed data is truncated"));
+			}
+			value |= ((self.data[self.pos] >> self.bit) as u32 & 1) << i;
+			self.bit += 1;
+			if self.bit == 8 {
+				self.bit = 0;
+				self.pos += 1;
+			}
+		}
+		Ok(value)
+	}
+
+	fn align_to_byte(&mut self) {
+		if self.bit != 0 {
+			self.bit = 0;
+			self.pos += 1;
+		}
+	}
+}
+
+// canonical Huffman code, stored as the number of codes of each length and the symbols ordered by code
+struct Huffman {
+	counts: [u16; 16],
+	symbols: Vec<u16>,
+}
+
+impl Huffman {
+	fn new(lengths: &[u8]) -> Huffman {
+		let mut counts = [0u16; 16];
+		for &length in lengths {
+			counts[length as usize] += 1;
+		}
+		counts[0] = 0;
+
+		let mut offsets = [0u16; 16];
+		for length in 1..15 {
+			offsets[length+1] = offsets[length] + counts[length];
+		}
+
+		let mut symbols = vec![0u16; lengths.len()];
+		for (symbol, &length) in lengths.iter().enumerate() {
+			if length != 0 {
+				symbols[offsets[length as usize] as usize] = symbol as u16;
+				offsets[length as usize] += 1;
+			}
+		}
+
+		Huffman { counts: counts, symbols: symbols }
+	}
+
+	fn decode(&self, reader: &mut BitReader) -> Result<u16, io::Error> {
+		let mut code: i32 = 0;
+		let mut first: i32 = 0;
+		let mut index: i32 = 0;
+		for length in 1..16 {
+			code |= reader.read_bits(1)? as i32;
+			let count = self.counts[length] as i32;
+			if code - first < count {
+				return Ok(self.symbols[(index + code - first) as usize]);
+			}
+			index += count;
+			first = (first + count) << 1;
+			code <<= 1;
+		}
+		Err(invalid("bad Huffman code"))
+	}
+}
+
+static LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+static LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+static DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+static DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+static CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn inflate(data: &[u8]) -> Result<Vec<u8>, io::Error> {
+	let mut reader = BitReader { data: data, pos: 0, bit: 0 };
+	let mut out = Vec::new();
+
+	loop {
+		let is_last = reader.read_bits(1)? == 1;
+		match reader.read_bits(2)? {
+			0 => {
+				reader.align_to_byte();
+				if reader.pos + 4 > data.len() {
+					return Err(invalid("compressed data is truncated"));
+				}
+				let length = data[reader.pos] as usize | (data[reader.pos+1] as usize) << 8;
+				reader.pos += 4;	// skips the one's complement of the length
+				if reader.pos + length > data.len() {
+					return Err(invalid("compressed data is truncated"));
+				}
+				out.extend_from_slice(&data[reader.pos..reader.pos+length]);
+				reader.pos += length;
+			},
+			1 => {
+				let mut lengths = [0u8; 288];
+				for (symbol, length) in lengths.iter_mut().enumerate() {
+					*length = match symbol { 0..=143 => 8, 144..=255 => 9, 256..=279 => 7, _ => 8 };
+				}
+				let literals = Huffman::new(&lengths);
+				let distances = Huffman::new(&[5u8; 30]);
+				inflate_block(&mut reader, &mut out, &literals, &distances)?;
+			},
+			2 => {
+				let (literals, distances) = read_dynamic_tables(&mut reader)?;
+				inflate_block(&mut reader, &mut out, &literals, &distances)?;
+			},
+			_ => { return Err(invalid("bad block type")); }
+		}
+
+		if is_last {
+			return Ok(out);
+		}
+	}
+}
+
+fn read_dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman), io::Error> {
+	let n_literals = reader.read_bits(5)? as usize + 257;
+	let n_distances = reader.read_bits(5)? as usize + 1;
+	let n_code_lengths = reader.read_bits(4)? as usize + 4;
+
+	let mut code_lengths = [0u8; 19];
+	for &index in CODE_LENGTH_ORDER.iter().take(n_code_lengths) {
+		code_lengths[index] = reader.read_bits(3)? as u8;
+	}
+	let code_length_huffman = Huffman::new(&code_lengths);
+
+	let mut lengths = Vec::with_capacity(n_literals + n_distances);
+	while lengths.len() < n_literals + n_distances {
+		let symbol = code_length_huffman.decode(reader)?;
+		let (value, repeat) = match symbol {
+			0..=15 => (symbol as u8, 1),
+			16 => {
+				match lengths.last() {
+					Some(&previous) => (previous, 3 + reader.read_bits(2)?),
+					None => { return Err(invalid("repeat with no previous length")); }
+				}
+			},
+			17 => (0, 3 + reader.read_bits(3)?),
+			_ => (0, 11 + reader.read_bits(7)?),
+		};
+		for _ in 0..repeat {
+			lengths.push(value);
+		}
+	}
+	if lengths.len() > n_literals + n_distances {
+		return Err(invalid("too many code lengths"));
+	}
+
+	Ok((Huffman::new(&lengths[..n_literals]), Huffman::new(&lengths[n_literals..])))
+}
+
+fn inflate_block(reader: &mut BitReader, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> Result<(), io::Error> {
+	loop {
+		let symbol = literals.decode(reader)? as usize;
+		if symbol < 256 {
+			out.push(symbol as u8);
+		} else if symbol == 256 {
+			return Ok(());
+		} else {
+			let symbol = symbol - 257;
+			if symbol >= LENGTH_BASE.len() {
+				return Err(invalid("bad length symbol"));
+			}
+			let length = LENGTH_BASE[symbol] as usize + reader.read_bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+			let symbol = distances.decode(reader)? as usize;
+			if symbol >= DIST_BASE.len() {
+				return Err(invalid("bad distance symbol"));
+			}
+			let distance = DIST_BASE[symbol] as usize + reader.read_bits(DIST_EXTRA[symbol] as u32)? as usize;
+			if distance > out.len() {
+				return Err(invalid("distance too far back"));
+			}
+
+			// copies byte by byte since the source and destination ranges may overlap
+			let start = out.len() - distance;
+			for i in 0..length {
+				let byte = out[start + i];
+				out.push(byte);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// the numbers of the dynamic block, as compressed by zlib
+	fn squares_text() -> Vec<u8> {
+		(0..60).map(|i| format!("{},", i * i % 97)).collect::<String>().into_bytes()
+	}
+
+	static SQUARES_DEFLATED: [u8; 91] = [
+		29, 140, 201, 13, 4, 65, 8, 196, 18, 242, 163, 185, 33, 255, 196, 182, 118, 36, 132, 56, 236, 122, 24, 201, 97, 141,
+		23, 209, 228, 209, 201, 26, 129, 39, 57, 140, 227, 132, 209, 206, 137, 8, 230, 97, 78, 5, 39, 90, 174, 2, 52, 39, 245,
+		88, 186, 240, 97, 151, 74, 220, 89, 165, 41, 170, 48, 99, 155, 150, 177, 132, 99, 34, 185, 96, 139, 57, 70, 61, 190,
+		250, 86, 29, 245, 234, 63, 36, 84, 130, 52, 201, 63,
+	];
+
+	// a single stored (uncompressed) block
+	fn store(data: &[u8]) -> Vec<u8> {
+		let length = data.len() as u16;
+		let mut bytes = vec![1, length as u8, (length >> 8) as u8, !length as u8, (!length >> 8) as u8];
+		bytes.extend_from_slice(data);
+		bytes
+	}
+
+	fn chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+		let mut bytes = (data.len() as u32).to_be_bytes().to_vec();
+		bytes.extend_from_slice(kind);
+		bytes.extend_from_slice(data);
+		bytes.extend_from_slice(&[0; 4]);	// the CRC is not checked
+		bytes
+	}
+
+	fn make_png(width: u32, height: u32, color_type: u8, scanlines: &[u8]) -> Vec<u8> {
+		let mut header = width.to_be_bytes().to_vec();
+		header.extend_from_slice(&height.to_be_bytes());
+		header.extend_from_slice(&[8, color_type, 0, 0, 0]);
+		let mut data = vec![0x78, 0x01];
+		data.extend(store(scanlines));
+
+		let mut bytes = PNG_SIGNATURE.to_vec();
+		bytes.extend(chunk(b"IHDR", &header));
+		bytes.extend(chunk(b"IDAT", &data));
+		bytes.extend(chunk(b"IEND", &[]));
+		bytes
+	}
+
+	#[test]
+	fn inflates_stored_blocks() {
+		assert_eq!(inflate(&store(b"hello")).unwrap(), b"hello");
+	}
+
+	#[test]
+	fn inflates_fixed_huffman_blocks() {
+		// "abcabcabcabc", with a back reference overlapping its own output
+		assert_eq!(inflate(&[75, 76, 74, 78, 132, 33, 0]).unwrap(), b"abcabcabcabc");
+	}
+
+	#[test]
+	fn inflates_dynamic_huffman_blocks() {
+		assert_eq!(inflate(&SQUARES_DEFLATED).unwrap(), squares_text());
+	}
+
+	#[test]
+	fn rejects_truncated_data() {
+		assert!(inflate(&SQUARES_DEFLATED[..40]).is_err());
+		assert!(inflate(&store(b"hello")[..7]).is_err());
+	}
+
+	#[test]
+	fn decodes_filtered_scanlines() {
+		// 2x2 RGB: the first row with the sub filter, the second one with the up filter
+		let scanlines = [
+			1, 10, 20, 30, 5, 5, 5,
+			2, 1, 1, 1, 2, 2, 2,
+		];
+		let image = decode_png(&make_png(2, 2, 2, &scanlines)).unwrap();
+		assert_eq!((image.width, image.height), (2, 2));
+		let byte = |row, col| {
+			let (r, g, b) = image.get_pixel(row, col);
+			(BmpImage::float_to_byte(r + 0.5 / 255.), BmpImage::float_to_byte(g + 0.5 / 255.), BmpImage::float_to_byte(b + 0.5 / 255.))
+		};
+		assert_eq!(byte(0, 0), (10, 20, 30));
+		assert_eq!(byte(0, 1), (15, 25, 35));
+		assert_eq!(byte(1, 0), (11, 21, 31));
+		assert_eq!(byte(1, 1), (17, 27, 37));
+	}
+
+	#[test]
+	fn rejects_missing_image_data() {
+		let png = make_png(4, 4, 0, &[0, 1, 2, 3, 4]);
+		assert!(decode_png(&png).is_err());
+		assert!(decode_png(&png[1..]).is_err());
+	}
+}
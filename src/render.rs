@@ -0,0 +1,503 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::mem;
+use std::ptr;
+use std::time::{Duration, Instant};
+use float::*;
+use graphics::*;
+use lights::*;
+use structs::*;
+use vec3::*;
+use mat4::*;
+use scene::*;
+use animation::*;
+use bmp::*;
+
+// hit in world space, hit on the primitive, distance to it and, if the graphic moves while the shutter is open, the
+// matrices moving world space from where the graphic is for the frame to where it is at the time of the ray and back
+type Intersection<'a> = (Point3, Hit<'a>, Float, Option<(Mat4, Mat4)>);
+
+// ---------- RenderOptions ----------
+
+#[derive(Debug,Clone)]
+pub struct RenderOptions {
+	pub packet_size: usize,	// primary rays are traced in blocks of packet_size x packet_size pixels (1 traces them one by one)
+}
+
+impl Default for RenderOptions {
+	fn default() -> RenderOptions {
+		RenderOptions {
+			packet_size: 4,
+		}
+	}
+}
+
+// ---------- Framebuffer ----------
+
+// Rendered colors, unclamped, row by row from the top left corner.
+#[derive(Debug,Clone)]
+pub struct Framebuffer {
+	pub width: u32,
+	pub height: u32,
+	pub pixels: Vec<Color>,
+}
+
+impl Framebuffer {
+	pub fn new(width: u32, height: u32) -> Framebuffer {
+		Framebuffer {
+			width: width,
+			height: height,
+			pixels: vec![Color::black(); (width * height) as usize],
+		}
+	}
+
+	pub fn get_pixel(&self, row: u32, col: u32) -> Color {
+		self.pixels[(row * self.width + col) as usize]
+	}
+
+	pub fn set_pixel(&mut self, row: u32, col: u32, color: Color) {
+		self.pixels[(row * self.width + col) as usize] = color;
+	}
+
+	// colors are clamped to [0, 1]
+	pub fn to_image(&self) -> BmpImage {
+		let mut image = BmpImage::new(self.width, self.height);
+		for row in 0..self.height {
+			for col in 0..self.width {
+				let color = self.get_pixel(row, col);
+				image.set_pixel(row, col, color.r, color.g, color.b);
+			}
+		}
+		image
+	}
+}
+
+// ---------- RenderStats ----------
+
+/// Counters and timings of a render (see render_with_stats), to tune scenes with.
+#[derive(Debug,Clone,Default)]
+pub struct RenderStats {
+	pub primary_rays: u64,
+	pub reflection_rays: u64,
+	pub shadow_rays: u64,
+	pub intersection_tests: BTreeMap<&'static str, u64>,	// by type of graphic (CSG graphics are tested as a whole)
+	pub bvh_node_visits: u64,	// nodes tested, once per packet for the primary rays traced in packets
+	pub max_depth_reached: u32,	// most reflections on the way of a primary ray
+	pub max_raytrace_depth: u32,	// of the scene
+	pub parse_time: Duration,
+	pub build_time: Duration,
+	pub render_time: Duration,
+	pub peak_memory: usize,	// bytes, estimated from the scene and the framebuffer, which take most of it
+}
+
+impl RenderStats {
+	// reflections per primary ray, which is how deep rays go on average, as each hit reflects one ray at most
+	pub fn calc_average_depth(&self) -> Float {
+		if self.primary_rays == 0 { 0. } else { self.reflection_rays as Float / self.primary_rays as Float }
+	}
+}
+
+impl fmt::Display for RenderStats {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let n_rays = self.primary_rays + self.reflection_rays + self.shadow_rays;
+		writeln!(f, "Rays: {} ({} primary, {} reflection, {} shadow)", n_rays, self.primary_rays, self.reflection_rays, self.shadow_rays)?;
+		let tests: Vec<String> = self.intersection_tests.iter().map(|(name, n_tests)| format!("{} {}", n_tests, name)).collect();
+		writeln!(f, "Intersection tests: {} ({})", self.intersection_tests.values().sum::<u64>(), tests.join(", "))?;
+		writeln!(f, "BVH node visits: {} ({:.1} per ray)", self.bvh_node_visits, self.bvh_node_visits as f64 / n_rays.max(1) as f64)?;
+		writeln!(f, "Depth: {:.2} on average, {} at most, of {}", self.calc_average_depth(), self.max_depth_reached, self.max_raytrace_depth)?;
+		writeln!(f, "Time: {:.3} s parsing, {:.3} s building, {:.3} s rendering",
+			self.parse_time.as_secs_f64(), self.build_time.as_secs_f64(), self.render_time.as_secs_f64())?;
+		write!(f, "Peak memory: about {:.1} MB", self.peak_memory as f64 / (1024. * 1024.))
+	}
+}
+
+// counters of a render, which end up in its RenderStats
+struct Counters {
+	primary_rays: u64,
+	reflection_rays: u64,
+	shadow_rays: u64,
+	intersection_tests: Vec<u64>,	// by graphic
+	bvh_node_visits: u64,
+	max_depth_reached: u32,
+}
+
+// ---------- Rendering ----------
+
+/// Renders the scene into a framebuffer of the scene's image size.
+pub fn render(scene: &Scene, options: &RenderOptions) -> Framebuffer {
+	render_with_stats(scene, options).0
+}
+
+/// Like render, also returning statistics of the render, and the times the scene took to be parsed and built.
+pub fn render_with_stats(scene: &Scene, options: &RenderOptions) -> (Framebuffer, RenderStats) {
+	let start = Instant::now();
+	let mut counters = Counters {
+		primary_rays: 0,
+		reflection_rays: 0,
+		shadow_rays: 0,
+		intersection_tests: vec![0; scene.graphics.len()],
+		bvh_node_visits: 0,
+		max_depth_reached: 0,
+	};
+	let mut framebuffer = Framebuffer::new(scene.image_width, scene.image_height);
+	let packet_size = options.packet_size.max(1);
+	// what moves while the shutter is open is blurred by sampling each pixel at several times
+	let is_motion_blurred = scene.is_motion_blurred();
+	let n_samples = if is_motion_blurred { scene.camera.shutter_samples.max(1) } else { 1 };
+	let camera_moves = is_motion_blurred && !scene.animation.camera.is_empty();
+
+	// primary rays are traced in packets of neighbouring pixels
+	for block_row in (0..scene.image_height).step_by(packet_size) {
+		for block_col in (0..scene.image_width).step_by(packet_size) {
+			let mut pixels = Vec::new();
+			for row in block_row..(block_row + packet_size as u32).min(scene.image_height) {
+				for col in block_col..(block_col + packet_size as u32).min(scene.image_width) {
+					pixels.push((row, col));
+				}
+			}
+
+			let mut colors = vec![Color::black(); pixels.len()];
+			for sample in 0..n_samples {
+				let times: Vec<Float> = pixels.iter().map(|&(row, col)| {
+					if is_motion_blurred { calc_sample_time(row, col, sample, n_samples, scene) } else { scene.time }
+				}).collect();
+				// a moving camera is somewhere else at the time of each ray, so those cannot share an origin
+				let mut packets = if camera_moves {
+					pixels.iter().zip(&times).map(|(&(row, col), &time)| {
+						let camera = scene.get_camera_at(time);
+						RayPacket::new(camera.pos, &[calc_direction_through_pixel(row, col, &camera, scene)])
+					}).collect()
+				} else {
+					let directions: Vec<Vector3> = pixels.iter().map(|&(row, col)| calc_direction_through_pixel(row, col, &scene.camera, scene)).collect();
+					vec![RayPacket::new(scene.camera.pos, &directions)]
+				};
+				for (ray, &time) in packets.iter_mut().flat_map(|packet| packet.rays.iter_mut()).zip(&times) {
+					ray.time = time;
+				}
+
+				counters.primary_rays += pixels.len() as u64;
+				let mut colors = colors.iter_mut();
+				for packet in &packets {
+					let intersections = get_closest_intersections(packet, scene, &mut counters);
+					for (ray, intersection) in packet.rays.iter().zip(intersections) {
+						let color = colors.next().unwrap();
+						*color = *color + shade(*ray, intersection, scene, 0, &mut counters);
+					}
+				}
+			}
+			for (&(row, col), &color) in pixels.iter().zip(&colors) {
+				framebuffer.set_pixel(row, col, (1. / n_samples as Float) * color);
+			}
+		}
+	}
+
+	let mut intersection_tests = BTreeMap::new();
+	for (graphic, &n_tests) in scene.graphics.iter().zip(&counters.intersection_tests) {
+		*intersection_tests.entry(graphic.get_type_name()).or_insert(0) += n_tests;
+	}
+	let stats = RenderStats {
+		primary_rays: counters.primary_rays,
+		reflection_rays: counters.reflection_rays,
+		shadow_rays: counters.shadow_rays,
+		intersection_tests: intersection_tests,
+		bvh_node_visits: counters.bvh_node_visits,
+		max_depth_reached: counters.max_depth_reached,
+		max_raytrace_depth: scene.max_raytrace_depth,
+		parse_time: scene.parse_time,
+		build_time: scene.build_time,
+		render_time: start.elapsed(),
+		peak_memory: scene.estimate_memory() + framebuffer.pixels.capacity() * mem::size_of::<Color>(),
+	};
+	(framebuffer, stats)
+}
+
+// time of a sample of the pixel while the shutter is open: the samples split the shutter interval evenly, and each
+// pixel places them at its own offset within their parts, which turns the banding regular steps would leave into noise
+fn calc_sample_time(row: u32, col: u32, sample: u32, n_samples: u32, scene: &Scene) -> Float {
+	let (open, close) = scene.camera.shutter;
+	let offset = hash_pixel(row, col) as Float / 4294967296.;
+	scene.time + open + (close - open) * (sample as Float + offset) / n_samples as Float
+}
+
+// scrambles the coordinates of a pixel into a number that looks random (Thomas Wang's integer hash)
+fn hash_pixel(row: u32, col: u32) -> u32 {
+	let mut hash = row.wrapping_mul(0x9e37_79b9) ^ col;
+	hash = (hash ^ 61) ^ (hash >> 16);
+	hash = hash.wrapping_mul(9);
+	hash ^= hash >> 4;
+	hash = hash.wrapping_mul(0x27d4_eb2d);
+	hash ^ (hash >> 15)
+}
+
+fn calc_direction_through_pixel(row: u32, col: u32, camera: &Camera, scene: &Scene) -> Vector3 {
+	let Camera { fov_y, fov_x, axis_x, axis_y, axis_z, .. } = *camera;
+	let half_width  = scene.image_width  as Float / 2.;
+	let half_height = scene.image_height as Float / 2.;
+
+	let alpha = Float::tan(fov_x/2.) * (col as Float - half_width + 0.5)  / half_width;
+	let beta  = Float::tan(fov_y/2.) * (half_height - row as Float - 0.5) / half_height;
+	(alpha * axis_x + beta * axis_y + axis_z).normalize()
+}
+
+// excluded is the graphic the ray leaves from, if it cannot hit it again
+fn raytrace(ray: Ray, scene: &Scene, n_ray_bounces: u32, excluded: Option<&dyn Graphic>, counters: &mut Counters) -> Color {
+	if n_ray_bounces > scene.max_raytrace_depth {
+		return Color::black();
+	}
+	counters.reflection_rays += 1;
+	counters.max_depth_reached = counters.max_depth_reached.max(n_ray_bounces);
+
+	let intersection = get_closest_intersection(&ray, scene, excluded, counters);
+	shade(ray, intersection, scene, n_ray_bounces, counters)
+}
+
+// color seen by the ray, given its closest intersection
+fn shade(ray: Ray, intersection: Option<Intersection>, scene: &Scene, n_ray_bounces: u32, counters: &mut Counters) -> Color {
+	if let Some((hit, Hit { point: hit_os, graphic, inverted, uv, .. }, _, correction)) = intersection {
+		// the primitive's transforms, taking it to where it is at the time of the ray if it moves
+		let (transform, inverse_transform) = match correction {
+			Some((to_world, from_world)) => (to_world * *graphic.get_transform(), *graphic.get_inverse_transform() * from_world),
+			None => (*graphic.get_transform(), *graphic.get_inverse_transform()),
+		};
+
+		let mut normal_os = graphic.calc_normal_at(hit_os);
+		if inverted {
+			normal_os = -normal_os;
+		}
+		let mut normal = inverse_transform.transform_normal(normal_os).normalize();
+		// secondary rays leave from the actual surface, whatever the normal and bump maps do
+		let geometric_normal = normal;
+		let excluded_from_secondary = if graphic.is_planar() { Some(graphic) } else { None };
+
+		let material = &scene.materials[graphic.get_material_index()];
+		let uv = uv.unwrap_or_else(|| graphic.calc_uv_at(hit_os));
+		if material.bump_texture.is_some() || material.normal_texture.is_some() {
+			normal = calc_mapped_normal(graphic, &transform, material, hit_os, uv, normal);
+		}
+		let kd = material.get_diffuse_at(uv, hit_os);
+		let ks = material.get_specular_at(uv, hit_os);
+		let to_camera = (ray.origin - hit).normalize();
+		let mut color = scene.ambient_color + material.get_emission_at(uv, hit_os);
+		for light in &scene.lights {
+			// TODO: re-enable
+			if !is_shadowed_from_light(&**light, hit, geometric_normal, excluded_from_secondary, ray.time, scene, counters) {
+				color = color + get_lighting_color(&**light, material, kd, ks, hit, to_camera, normal);
+			}
+		}
+
+		// reflection
+		let reflectance = match material.brdf {
+			Brdf::Phong => ks,
+			// mirror reflections fade out as the surface gets rougher
+			Brdf::CookTorrance => {
+				let f0 = get_cook_torrance_f0(material, kd);
+				let smoothness = 1. - material.roughness;
+				(smoothness * smoothness) * schlick_fresnel(f0, normal.dot(to_camera).max(0.))
+			}
+		};
+		let s = reflectance.r + reflectance.g + reflectance.b;
+		if s > 0. {
+			let reflection_direction = (ray.direction - 2. * normal.dot(ray.direction) * normal.to_vector()).normalize();
+			let mut reflected_ray = Ray::from_surface(hit, reflection_direction, geometric_normal);
+			reflected_ray.time = ray.time;
+
+			let reflected_color = reflectance * raytrace(reflected_ray, scene, n_ray_bounces+1, excluded_from_secondary, counters);
+			color = color + reflected_color;
+		}
+
+		color
+	} else {
+		match scene.background {
+			Some(ref background) => background.get_color(ray.direction),
+			None => Color::black(),
+		}
+	}
+}
+
+// applies the normal map and the bump map of the material to the geometric normal (all in world space, where transform
+// takes the graphic)
+fn calc_mapped_normal(graphic: &dyn Graphic, transform: &Mat4, material: &Material, hit_os: Point3, uv: UV, normal: Normal3) -> Normal3 {
+	let (dp_du_os, dp_dv_os) = graphic.calc_tangents_at(hit_os);
+	let normal = normal.to_vector();
+
+	// tangent frame in world space, made orthonormal around the normal
+	let mut tangent = transform.transform_vector(dp_du_os);
+	tangent = (tangent - tangent.dot(normal) * normal).normalize();
+	let mut bitangent = transform.transform_vector(dp_dv_os);
+	bitangent = (bitangent - bitangent.dot(normal) * normal - bitangent.dot(tangent) * tangent).normalize();
+	let mut mapped_normal = normal;
+
+	if let Some(ref texture) = material.normal_texture {
+		// colors in [0,1] encode tangent-space directions in [-1,1]
+		let c = texture.get_color_at(uv, hit_os);
+		mapped_normal = ((2. * c.r - 1.) * tangent + (2. * c.g - 1.) * bitangent + (2. * c.b - 1.) * normal).normalize();
+	}
+
+	if let Some(ref texture) = material.bump_texture {
+		// finite differences of the height along u and v, moving the solid texture position along with the UVs
+		let delta = 1e-3;
+		let height = texture.get_color_at(uv, hit_os).luminance();
+		let height_u = texture.get_color_at(UV { u: uv.u + delta, v: uv.v }, hit_os + delta * dp_du_os).luminance();
+		let height_v = texture.get_color_at(UV { u: uv.u, v: uv.v + delta }, hit_os + delta * dp_dv_os).luminance();
+		let dh_du = (height_u - height) / delta;
+		let dh_dv = (height_v - height) / delta;
+		mapped_normal = (mapped_normal - material.bump_strength * (dh_du * tangent + dh_dv * bitangent)).normalize();
+	}
+
+	mapped_normal.to_normal()
+}
+
+// closest intersection of the ray, skipping the excluded graphic
+fn get_closest_intersection<'a>(ray: &Ray, scene: &'a Scene, excluded: Option<&dyn Graphic>, counters: &mut Counters) -> Option<Intersection<'a>> {
+	let mut closest: Option<Intersection<'a>> = None;
+	// farther hits are culled by the graphics themselves, and farther nodes by the BVH
+	let mut ray = *ray;
+
+	let n_visits = scene.bvh.traverse(&mut ray, |index, ray| {
+		let graphic = &*scene.graphics[index];
+		if let Some(excluded) = excluded {
+			if ptr::eq(graphic as *const dyn Graphic as *const u8, excluded as *const dyn Graphic as *const u8) {
+				return;
+			}
+		}
+		counters.intersection_tests[index] += 1;
+		let intersection = match scene.get_motion(index) {
+			Some(motion) => test_moving_graphic(ray, graphic, motion),
+			None => test_graphic(ray, &ray.transformed(graphic.get_inverse_transform()), graphic, None),
+		};
+		if let Some(intersection) = intersection {
+			ray.t_max = intersection.2;
+			closest = Some(intersection);
+		}
+	});
+	counters.bvh_node_visits += n_visits as u64;
+
+	closest
+}
+
+// like get_closest_intersection, for each ray of a packet of primary rays
+fn get_closest_intersections<'a>(packet: &RayPacket, scene: &'a Scene, counters: &mut Counters) -> Vec<Option<Intersection<'a>>> {
+	let mut closest: Vec<Option<Intersection<'a>>> = vec![None; packet.rays.len()];
+	let mut packet = packet.clone();
+
+	let n_visits = scene.bvh.traverse_packet(&mut packet, |index, packet| {
+		let graphic = &*scene.graphics[index];
+		counters.intersection_tests[index] += packet.rays.len() as u64;
+		// the rays of the packet see a moving graphic at different places
+		if let Some(motion) = scene.get_motion(index) {
+			for (ray, closest) in packet.rays.iter_mut().zip(closest.iter_mut()) {
+				if let Some(intersection) = test_moving_graphic(ray, graphic, motion) {
+					ray.t_max = intersection.2;
+					*closest = Some(intersection);
+				}
+			}
+			return;
+		}
+		let packet_os = packet.transformed(graphic.get_inverse_transform());
+		for ((ray, ray_os), closest) in packet.rays.iter_mut().zip(&packet_os.rays).zip(closest.iter_mut()) {
+			if let Some(intersection) = test_graphic(ray, ray_os, graphic, None) {
+				ray.t_max = intersection.2;
+				*closest = Some(intersection);
+			}
+		}
+	});
+	counters.bvh_node_visits += n_visits as u64;
+
+	closest
+}
+
+// the intersection with the graphic, if it is hit within the range of the ray; ray_os is the ray in the object space
+// of the graphic, and correction moves the graphic from where it is for the frame, if it moves
+fn test_graphic<'a>(ray: &Ray, ray_os: &Ray, graphic: &'a dyn Graphic, correction: Option<(Mat4, Mat4)>) -> Option<Intersection<'a>> {
+	let hit_os = graphic.test_closest_hit(ray_os)?;
+	// the primitive may be part of a composite graphic, in which case its transform already goes to world space
+	let mut hit = hit_os.graphic.get_transform().transform_point(hit_os.point);
+	if let Some((to_world, _)) = correction {
+		hit = to_world.transform_point(hit);
+	}
+
+	let distance = (hit - ray.origin).length();
+	if distance < ray.t_max { Some((hit, hit_os, distance, correction)) } else { None }
+}
+
+// like test_graphic, for a graphic moving while the shutter is open, which the ray sees where it is at its time
+fn test_moving_graphic<'a>(ray: &Ray, graphic: &'a dyn Graphic, motion: &Motion) -> Option<Intersection<'a>> {
+	let (to_world, from_world) = motion.get_correction_at(ray.time)?;
+	let ray_os = ray.transformed(&(*graphic.get_inverse_transform() * from_world));
+	test_graphic(ray, &ray_os, graphic, Some((to_world, from_world)))
+}
+
+fn get_lighting_color(light: &dyn Light, material: &Material, kd: Color, ks: Color, hit: Point3, to_camera: Vector3, normal: Normal3) -> Color {
+	let to_light = light.calc_to_light_direction(&hit);
+	let to_light_dist = light.calc_to_light_distance(&hit);
+
+	let ln = normal.dot(to_light);
+	if ln > 0. {
+		let reflected = match material.brdf {
+			Brdf::Phong => {
+				let lambert = ln * kd;
+				let h = (to_light + to_camera).normalize();
+				let normal_dot_h = normal.dot(h);
+				let phong = Float::powf(if normal_dot_h > 0. { normal_dot_h } else { 0. }, material.shininess) * ks;
+				lambert + phong
+			}
+			Brdf::CookTorrance => {
+				get_cook_torrance_color(material, kd, to_light, to_camera, normal)
+			}
+		};
+		let attenuation = light.calc_attenuation_at_distance(to_light_dist);
+		(1. / attenuation) * light.get_color() * reflected
+	} else {
+		Color::black()
+	}
+}
+
+// light reflected towards the camera by a Cook-Torrance material, already multiplied by the cosine of the light direction
+fn get_cook_torrance_color(material: &Material, base_color: Color, to_light: Vector3, to_camera: Vector3, normal: Normal3) -> Color {
+	let nl = normal.dot(to_light);
+	let nv = normal.dot(to_camera);
+	if nv <= 0. {
+		return Color::black();
+	}
+	let h = (to_light + to_camera).normalize();
+	let nh = normal.dot(h).max(0.);
+	let vh = to_camera.dot(h).max(0.);
+
+	// GGX normal distribution
+	let alpha = Float::max(material.roughness * material.roughness, 1e-4);
+	let alpha_sq = alpha * alpha;
+	let d_denominator = nh * nh * (alpha_sq - 1.) + 1.;
+	let d = alpha_sq / (consts::PI * d_denominator * d_denominator);
+
+	// separable Smith masking-shadowing for GGX
+	let g1 = |cos: Float| 2. * cos / (cos + (alpha_sq + (1. - alpha_sq) * cos * cos).sqrt());
+	let g = g1(nl) * g1(nv);
+
+	let f0 = get_cook_torrance_f0(material, base_color);
+	let f = schlick_fresnel(f0, vh);
+
+	// lights are scaled so that a white Lambertian surface reflects ln * light color, as in the Phong model,
+	// which means multiplying the BRDF by pi
+	let specular = (consts::PI * d * g / (4. * nl * nv)) * f;
+	let diffuse = (1. - material.metallic) * ((Color::white() - f) * base_color);
+	nl * (diffuse + specular)
+}
+
+// reflectance at normal incidence: 4% for dielectrics, the base color for metals
+fn get_cook_torrance_f0(material: &Material, base_color: Color) -> Color {
+	(1. - material.metallic) * Color { r: 0.04, g: 0.04, b: 0.04 } + material.metallic * base_color
+}
+
+fn schlick_fresnel(f0: Color, cos: Float) -> Color {
+	f0 + Float::powi(1. - cos, 5) * (Color::white() - f0)
+}
+
+fn is_shadowed_from_light(light: &dyn Light, point: Point3, normal: Normal3, excluded: Option<&dyn Graphic>, time: Float, scene: &Scene, counters: &mut Counters) -> bool {
+	let to_light = light.calc_to_light_direction(&point);
+	let mut ray = Ray::from_surface(point, to_light, normal);
+	ray.t_max = light.calc_to_light_distance(&point);
+	ray.time = time;
+	counters.shadow_rays += 1;
+
+	get_closest_intersection(&ray, scene, excluded, counters).is_some()
+}
@@ -1,41 +1,174 @@
+use std::collections::HashMap;
+use std::mem;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
+
+use float::*;
 use graphics::*;
+use bvh::*;
 use lights::*;
+use background::*;
 use structs::*;
-use vec4::*;
+use vec3::*;
+use animation::*;
 
 // ---------- Scene ----------
 
 #[derive(Default)]
 pub struct Scene {
-	pub graphics: Vec<Box<Graphic>>,
-	pub lights: Vec<Box<Light>>,
+	pub graphics: Vec<Box<dyn Graphic>>,
+	pub materials: Vec<Material>,	// indexed by the graphics
+	pub bvh: Bvh,	// over the graphics
+	pub lights: Vec<Box<dyn Light>>,
 	pub camera: Camera,
 	pub ambient_color: Color,
+	pub background: Option<Box<dyn Background>>,	// black when not set
 	pub max_raytrace_depth: u32,
 
 	pub image_width: u32,
 	pub image_height: u32,
 	pub image_filename: String,
+
+	pub animation: Animation,
+	pub time: Float,	// of the frame (see set_time)
+	pub motions: Vec<Option<Motion>>,	// of the graphics that move while the shutter is open, by index (empty if none do)
+
+	pub parse_time: Duration,	// taken to read the scene file, if the scene comes from one
+	pub build_time: Duration,	// taken by SceneBuilder::build
+}
+
+impl Scene {
+	/// Moves the animated graphics and camera to the time (a frame number, possibly between frames), refitting the
+	/// bounding volume hierarchy to the graphics' new places.
+	pub fn set_time(&mut self, time: Float) {
+		self.time = time;
+		if !self.animation.is_animated() {
+			return;
+		}
+		for (path, animated_transform) in &self.animation.graphics {
+			let transform = animated_transform.get_transform_at(time);
+			// a transform that cannot be inverted at this time leaves the graphic where it was
+			if let Some(inverse_transform) = transform.invert() {
+				get_graphic_mut(&mut self.graphics, path).set_transform(transform, inverse_transform);
+			}
+		}
+
+		if !self.animation.camera.is_empty() {
+			self.camera = self.get_camera_at(time);
+		}
+
+		// graphics moving as a whole are blurred, but not the parts of CSG graphics that move on their own
+		self.motions.clear();
+		if self.is_motion_blurred() {
+			self.motions.resize(self.graphics.len(), None);
+			for (path, animated_transform) in &self.animation.graphics {
+				if path.len() == 1 {
+					self.motions[path[0]] = Motion::new(animated_transform.clone(), time);
+				}
+			}
+		}
+
+		let graphic_bounds = self.calc_graphic_bounds();
+		self.bvh.refit(&graphic_bounds);
+	}
+
+	// whether anything moves while the shutter is open
+	pub fn is_motion_blurred(&self) -> bool {
+		self.camera.shutter.1 > self.camera.shutter.0 && self.animation.is_animated()
+	}
+
+	// the motion of the graphic while the shutter is open, if it moves meanwhile
+	pub fn get_motion(&self, index: usize) -> Option<&Motion> {
+		self.motions.get(index).and_then(|motion| motion.as_ref())
+	}
+
+	// the camera at the time, as animated by its keys, with the same shutter
+	pub fn get_camera_at(&self, time: Float) -> Camera {
+		let (pos, look_at, up, fov_y) = self.animation.camera_setup;
+		let (pos, look_at, fov_y) = self.animation.camera.sample(time, pos, look_at, fov_y);
+		let mut camera = Camera::new(pos, look_at, up, fov_y, self.image_width as Float, self.image_height as Float);
+		camera.shutter = self.camera.shutter;
+		camera.shutter_samples = self.camera.shutter_samples;
+		camera
+	}
+
+	/// World bounds of the graphics (None for the unbounded ones), from which the bounding volume hierarchy is built.
+	/// Those of the graphics moving while the shutter is open cover all the places they go through.
+	pub fn calc_graphic_bounds(&self) -> Vec<Option<Bounds>> {
+		let (open, close) = self.camera.shutter;
+		self.graphics.iter().enumerate().map(|(index, graphic)| {
+			let bounds = graphic.calc_bounds()?.transformed(graphic.get_transform());
+			match self.get_motion(index) {
+				Some(motion) => Some(motion.calc_swept_bounds(&bounds, self.time + open, self.time + close)),
+				None => Some(bounds),
+			}
+		}).collect()
+	}
+
+	/// Bytes taken by the scene: its graphics, materials and their textures (counted once however many materials share
+	/// them), lights, background and bounding volume hierarchy.
+	pub fn estimate_memory(&self) -> usize {
+		let mut textures: HashMap<*const u8, usize> = HashMap::new();
+		for material in &self.materials {
+			let channels = [&material.ke_texture, &material.kd_texture, &material.ks_texture, &material.bump_texture, &material.normal_texture];
+			for texture in channels.iter().filter_map(|texture| texture.as_ref()) {
+				textures.insert(Rc::as_ptr(texture) as *const u8, texture.estimate_memory());
+			}
+		}
+
+		mem::size_of::<Scene>() +
+			self.graphics.capacity() * mem::size_of::<Box<dyn Graphic>>() +
+			self.graphics.iter().map(|graphic| graphic.estimate_memory()).sum::<usize>() +
+			self.materials.capacity() * mem::size_of::<Material>() +
+			textures.values().sum::<usize>() +
+			self.lights.capacity() * mem::size_of::<Box<dyn Light>>() +
+			self.lights.iter().map(|light| mem::size_of_val(&**light)).sum::<usize>() +
+			self.background.as_ref().map_or(0, |background| background.estimate_memory()) +
+			self.bvh.estimate_allocated_memory()
+	}
+
+	// the output file of a frame of the sequence, numbered before the extension, e.g. out_0001.png for out.png
+	pub fn get_frame_filename(&self, frame: u32) -> String {
+		let path = Path::new(&self.image_filename);
+		let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+		let name = match path.extension() {
+			Some(extension) => format!("{}_{:04}.{}", stem, frame, extension.to_string_lossy()),
+			None => format!("{}_{:04}", stem, frame),
+		};
+		path.with_file_name(name).to_string_lossy().into_owned()
+	}
+}
+
+// graphic at the path of indices, the first one in the graphics and each following one in the children of the previous
+fn get_graphic_mut<'a>(graphics: &'a mut [Box<dyn Graphic>], path: &[usize]) -> &'a mut Box<dyn Graphic> {
+	let graphic = &mut graphics[path[0]];
+	if path.len() == 1 {
+		return graphic;
+	}
+	get_graphic_mut(graphic.get_children_mut().unwrap(), &path[1..])
 }
 
 // ---------- Camera ----------
 
 #[derive(Debug,Default)]
 pub struct Camera {
-	pub pos: Vec4,		// position of the camera in space
-	pub look_at: Vec4,	// position the camera is looking at
-	pub up: Vec4,		// vector indicating which way is up (rotates the camera view)
-	pub fov_y: f64,
-	pub fov_x: f64,
-	pub axis_x: Vec4,
-	pub axis_y: Vec4,
-	pub axis_z: Vec4,
+	pub pos: Point3,	// position of the camera in space
+	pub look_at: Point3,	// position the camera is looking at
+	pub up: Vector3,	// vector indicating which way is up (rotates the camera view)
+	pub fov_y: Float,
+	pub fov_x: Float,
+	pub axis_x: Vector3,
+	pub axis_y: Vector3,
+	pub axis_z: Vector3,
+	pub shutter: (Float, Float),	// opening and closing time, in frames from the time of the frame
+	pub shutter_samples: u32,	// times at which each pixel is sampled while the shutter is open
 }
 
 impl Camera {
-	pub fn new(pos: Vec4, look_at: Vec4, up: Vec4, fov_y: f64, view_width: f64, view_height: f64) -> Camera {
+	pub fn new(pos: Point3, look_at: Point3, up: Vector3, fov_y: Float, view_width: Float, view_height: Float) -> Camera {
 		let axis_z = (look_at - pos).normalize();
-		let axis_x = axis_z.clone().normalize().cross(up).normalize();
+		let axis_x = axis_z.cross(up).normalize();
 		let axis_y = axis_x.cross(axis_z);
 		let fov_y_rad = fov_y.to_radians();
 
@@ -48,10 +181,28 @@ impl Camera {
 			axis_x: axis_x,
 			axis_y: axis_y,
 			axis_z: axis_z,
+			shutter: (0., 0.),
+			shutter_samples: 1,
 		}
 	}
 
-	pub fn calc_fov_x(fov_y: f64, view_width: f64, view_height: f64) -> f64 {
-		2. * f64::atan(f64::tan(fov_y/2.) * view_width / view_height)
+	pub fn calc_fov_x(fov_y: Float, view_width: Float, view_height: Float) -> Float {
+		2. * Float::atan(Float::tan(fov_y/2.) * view_width / view_height)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn numbers_frame_filenames_before_the_extension() {
+		let get_frame_filename = |filename: &str| {
+			let scene = Scene { image_filename: String::from(filename), ..Default::default() };
+			scene.get_frame_filename(7)
+		};
+		assert_eq!(get_frame_filename("out.png"), "out_0007.png");
+		assert_eq!(get_frame_filename("renders/out"), "renders/out_0007");
+		assert_eq!(get_frame_filename("v1.2/out.bmp"), "v1.2/out_0007.bmp");
 	}
 }
@@ -0,0 +1,605 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use float::*;
+use graphics::*;
+use lights::*;
+use structs::*;
+use vec3::*;
+use mat4::*;
+use scene::*;
+use background::*;
+use textures::*;
+use csg::*;
+use sdf::*;
+use heightfield::*;
+use bvh::*;
+use bmp::*;
+use animation::*;
+
+// ---------- SceneBuilder ----------
+
+// samples per pixel of the scene files that open the shutter without saying how many
+pub const DEFAULT_SHUTTER_SAMPLES: u32 = 8;
+
+// Builds a scene the way a scene file describes it: objects take the current material and transform, and point lights
+// the current attenuation, all of which can be changed between them. Problems with an object, such as a singular
+// transform, are returned by the method adding it, and problems with the whole scene, such as a missing camera, by build.
+pub struct SceneBuilder {
+	graphics: Vec<Box<dyn Graphic>>,
+	lights: Vec<Box<dyn Light>>,
+	camera: Option<(Point3, Point3, Vector3, Float)>,	// position, look at, up and vertical field of view (degrees)
+	shutter: (Float, Float),
+	shutter_samples: u32,
+	ambient_color: Color,
+	background: Option<Box<dyn Background>>,
+	max_raytrace_depth: u32,
+	image_width: u32,
+	image_height: u32,
+	image_filename: String,
+
+	materials: Vec<Material>,	// the material table of the scene, starting with the default material
+	material_names: HashMap<String, usize>,
+	material: Material,
+	material_index: Option<usize>,	// of the current material in the table, once it is there
+	attenuation: Attenuation,
+	csg_stack: Vec<(CsgOperation, Vec<Box<dyn Graphic>>)>,	// CSG nodes being defined, with the children added so far
+	transformation_stack: Vec<AnimatedTransform>,
+	animations: HashMap<String, Rc<TransformTrack>>,
+	animation: Animation,
+}
+
+impl Default for SceneBuilder {
+	fn default() -> SceneBuilder {
+		SceneBuilder::new()
+	}
+}
+
+impl SceneBuilder {
+	pub fn new() -> SceneBuilder {
+		SceneBuilder {
+			graphics: Vec::new(),
+			lights: Vec::new(),
+			camera: None,
+			shutter: (0., 0.),
+			shutter_samples: 1,
+			ambient_color: Color::black(),
+			background: None,
+			max_raytrace_depth: 5,
+			image_width: 640,
+			image_height: 460,
+			image_filename: String::from("output.bmp"),
+
+			materials: vec![Default::default()],
+			material_names: HashMap::new(),
+			material: Default::default(),
+			material_index: Some(0),
+			attenuation: Default::default(),
+			csg_stack: Vec::new(),
+			transformation_stack: vec![AnimatedTransform::fixed(Mat4::id(1.))],
+			animations: HashMap::new(),
+			animation: Default::default(),
+		}
+	}
+
+	/// Checks the scene and builds its acceleration structure.
+	pub fn build(mut self) -> Result<Scene, String> {
+		let start = Instant::now();
+		let mut errors = Vec::new();
+		if !self.csg_stack.is_empty() {
+			errors.push(format!("{} CSG node(s) not ended", self.csg_stack.len()));
+		}
+		if self.image_width == 0 || self.image_height == 0 {
+			errors.push(String::from("the image size is zero"));
+		}
+		if self.shutter.1 < self.shutter.0 {
+			errors.push(String::from("the shutter closes before it opens"));
+		}
+		if self.shutter_samples == 0 {
+			errors.push(String::from("the shutter has no samples"));
+		}
+		if let Some((first, last)) = self.animation.frames {
+			if first > last {
+				errors.push(String::from("the frame range is empty"));
+			}
+		}
+		let camera = match self.camera {
+			Some((pos, look_at, up, fov_y)) => {
+				self.animation.camera_setup = (pos, look_at, up, fov_y);
+				let mut camera = Camera::new(pos, look_at, up, fov_y, self.image_width as Float, self.image_height as Float);
+				camera.shutter = self.shutter;
+				camera.shutter_samples = self.shutter_samples;
+				camera
+			}
+			None => {
+				errors.push(String::from("the scene has no camera"));
+				Default::default()
+			}
+		};
+		if !errors.is_empty() {
+			return Err(errors.join("; "));
+		}
+
+		let mut scene = Scene {
+			bvh: Default::default(),
+			graphics: self.graphics,
+			materials: self.materials,
+			lights: self.lights,
+			camera: camera,
+			ambient_color: self.ambient_color,
+			background: self.background,
+			max_raytrace_depth: self.max_raytrace_depth,
+			image_width: self.image_width,
+			image_height: self.image_height,
+			image_filename: self.image_filename,
+			animation: self.animation,
+			time: 0.,
+			motions: Vec::new(),
+			parse_time: Duration::default(),
+			build_time: Duration::default(),
+		};
+		// animations start at the first frame
+		let first_frame = scene.animation.frames.map(|frames| frames.0).unwrap_or(0);
+		scene.set_time(first_frame as Float);
+		scene.bvh = Bvh::build(&scene.calc_graphic_bounds());
+		scene.build_time = start.elapsed();
+		Ok(scene)
+	}
+
+	// ---------- image and camera ----------
+
+	pub fn size(&mut self, width: u32, height: u32) {
+		self.image_width = width;
+		self.image_height = height;
+	}
+
+	pub fn max_depth(&mut self, depth: u32) {
+		self.max_raytrace_depth = depth;
+	}
+
+	pub fn output(&mut self, filename: &str) {
+		self.image_filename = String::from(filename);
+	}
+
+	// the horizontal field of view follows from the image size when the scene is built
+	pub fn camera(&mut self, pos: Point3, look_at: Point3, up: Vector3, fov_y: Float) {
+		self.camera = Some((pos, look_at, up.normalize(), fov_y));
+	}
+
+	// the shutter opens and closes at these times from the time of each frame, in frames, and each pixel is sampled at
+	// as many times in between, which blurs what moves meanwhile
+	pub fn shutter(&mut self, open: Float, close: Float, samples: u32) {
+		self.shutter = (open, close);
+		self.shutter_samples = samples;
+	}
+
+	// ---------- transforms ----------
+
+	pub fn push_transform(&mut self) {
+		let top = self.transformation_stack.last().unwrap().clone();
+		self.transformation_stack.push(top);
+	}
+
+	pub fn pop_transform(&mut self) -> Result<(), String> {
+		if self.transformation_stack.len() <= 1 {
+			return Err(String::from("no transform to pop"));
+		}
+		self.transformation_stack.pop();
+		Ok(())
+	}
+
+	pub fn translate(&mut self, x: Float, y: Float, z: Float) {
+		self.apply_transform(Mat4::create_translation(x, y, z));
+	}
+
+	pub fn rotate(&mut self, axis: Vector3, degrees: Float) {
+		self.apply_transform(Mat4::create_rotation(degrees, axis.normalize()));
+	}
+
+	pub fn scale(&mut self, x: Float, y: Float, z: Float) {
+		self.apply_transform(Mat4::create_scale(x, y, z));
+	}
+
+	pub fn rotate_euler(&mut self, x: Float, y: Float, z: Float, order: RotationOrder) {
+		self.apply_transform(Mat4::create_euler_rotation(x, y, z, order));
+	}
+
+	pub fn rotate_quaternion(&mut self, x: Float, y: Float, z: Float, w: Float) -> Result<(), String> {
+		let rotation = Mat4::create_quaternion_rotation(x, y, z, w).ok_or_else(|| String::from("the quaternion is zero"))?;
+		self.apply_transform(rotation);
+		Ok(())
+	}
+
+	// places the objects at pos, with their z axis toward the target and their y axis toward up
+	pub fn look_at(&mut self, pos: Point3, target: Point3, up: Vector3) -> Result<(), String> {
+		let transform = Mat4::create_look_at(pos, target, up).ok_or_else(|| String::from("the lookat transform has no direction"))?;
+		self.apply_transform(transform);
+		Ok(())
+	}
+
+	// the matrix is applied to the objects before the current transform
+	// a matrix read from a scene, which must be affine, as the objects and their normals are transformed assuming it is
+	pub fn apply_matrix(&mut self, matrix: Mat4) -> Result<(), String> {
+		if matrix.data[3] != [0., 0., 0., 1.] {
+			return Err(String::from("the bottom row of the matrix is not 0 0 0 1"));
+		}
+		self.apply_transform(matrix);
+		Ok(())
+	}
+
+	pub fn apply_transform(&mut self, matrix: Mat4) {
+		self.transformation_stack.last_mut().unwrap().apply(matrix);
+	}
+
+	// at time 0, if the transform is animated
+	pub fn get_transform(&self) -> Mat4 {
+		self.transformation_stack.last().unwrap().get_transform_at(0.)
+	}
+
+	// the current transform and its inverse, which objects need
+	fn get_transforms(&self) -> Result<(Mat4, Mat4), String> {
+		let transform = self.get_transform();
+		let inverse_transform = transform.invert().ok_or_else(|| String::from("the transform is singular"))?;
+		Ok((transform, inverse_transform))
+	}
+
+	// ---------- animation ----------
+
+	// renders the scene as a sequence of frames, numbered from first to last
+	pub fn frames(&mut self, first: u32, last: u32) {
+		self.animation.frames = Some((first, last));
+	}
+
+	// the camera keeps the values given to camera where it has no keys
+	pub fn add_camera_position_key(&mut self, frame: Float, pos: Point3) {
+		self.animation.camera.position.insert(frame, pos);
+	}
+
+	pub fn add_camera_look_at_key(&mut self, frame: Float, look_at: Point3) {
+		self.animation.camera.look_at.insert(frame, look_at);
+	}
+
+	pub fn add_camera_fov_key(&mut self, frame: Float, fov_y: Float) {
+		self.animation.camera.fov_y.insert(frame, fov_y);
+	}
+
+	pub fn define_animation(&mut self, name: &str, track: TransformTrack) {
+		self.animations.insert(String::from(name), Rc::new(track));
+	}
+
+	// applies the named animation to the current transform, like a transform that changes with time
+	pub fn animate(&mut self, name: &str) -> Result<(), String> {
+		match self.animations.get(name) {
+			Some(track) => {
+				self.transformation_stack.last_mut().unwrap().animate(track.clone());
+				Ok(())
+			}
+			None => Err(format!("unknown animation {}", name)),
+		}
+	}
+
+	// ---------- materials ----------
+
+	// objects share the entry of the material table of their material, unless it changes between them
+	pub fn set_material(&mut self, material: Material) {
+		self.material = material;
+		self.material_index = None;
+	}
+
+	// the material given to the objects added from now on
+	pub fn material_mut(&mut self) -> &mut Material {
+		self.material_index = None;
+		&mut self.material
+	}
+
+	// named materials are shared by all the objects using them
+	pub fn define_material(&mut self, name: &str, material: Material) {
+		self.materials.push(material);
+		self.material_names.insert(String::from(name), self.materials.len() - 1);
+	}
+
+	pub fn use_material(&mut self, name: &str) -> Result<(), String> {
+		match self.material_names.get(name) {
+			Some(&index) => {
+				self.set_material_index(index);
+				Ok(())
+			}
+			None => Err(format!("unknown material {}", name)),
+		}
+	}
+
+	// makes the material at the index of the material table the current one
+	pub fn set_material_index(&mut self, index: usize) {
+		self.material = self.materials[index].clone();
+		self.material_index = Some(index);
+	}
+
+	// index of the current material in the material table, which is added to it if needed, e.g. for the graphics given
+	// to add_graphic
+	pub fn get_material_index(&mut self) -> usize {
+		match self.material_index {
+			Some(index) => index,
+			None => {
+				self.materials.push(self.material.clone());
+				self.material_index = Some(self.materials.len() - 1);
+				self.materials.len() - 1
+			}
+		}
+	}
+
+	// ---------- graphics ----------
+
+	// adds a graphic created with the current transform, which moves it if it is animated
+	fn add_transformed_graphic(&mut self, graphic: Box<dyn Graphic>) {
+		self.animate_next_graphic();
+		self.add_graphic(graphic);
+	}
+
+	// the next graphic to be added moves with the current transform, if it is animated
+	fn animate_next_graphic(&mut self) {
+		let top = self.transformation_stack.last().unwrap();
+		if top.is_animated() {
+			// the graphic goes at the end of the scene's graphics or of the children of the CSG nodes being defined,
+			// each of which will be added at the end of its parent
+			let mut path = vec![self.graphics.len()];
+			path.extend(self.csg_stack.iter().map(|(_, children)| children.len()));
+			self.animation.graphics.push((path, top.clone()));
+		}
+	}
+
+	// adds a graphic as it is, ignoring the current transform and material, to the CSG node being defined, if any,
+	// or else to the scene
+	pub fn add_graphic(&mut self, graphic: Box<dyn Graphic>) {
+		match self.csg_stack.last_mut() {
+			Some(&mut (_, ref mut children)) => { children.push(graphic); }
+			None => { self.graphics.push(graphic); }
+		}
+	}
+
+	pub fn add_sphere(&mut self, pos: Point3, radius: Float) -> Result<(), String> {
+		if radius <= 0. {
+			return Err(String::from("the radius of the sphere is not positive"));
+		}
+		let (transform, inverse_transform) = self.get_transforms()?;
+		let sphere = Sphere {
+			pos: pos,
+			radius: radius,
+			material_index: self.get_material_index(),
+			transform: transform,
+			inverse_transform: inverse_transform,
+		};
+		self.add_transformed_graphic(Box::new(sphere));
+		Ok(())
+	}
+
+	// with the corners mapped to the UVs (0,0), (1,0) and (0,1)
+	pub fn add_triangle(&mut self, points: [Point3; 3]) -> Result<(), String> {
+		self.add_textured_triangle(points, Triangle::default_uvs())
+	}
+
+	pub fn add_textured_triangle(&mut self, points: [Point3; 3], uvs: [UV; 3]) -> Result<(), String> {
+		let (transform, inverse_transform) = self.get_transforms()?;
+		let [p1, p2, p3] = points;
+		let triangle = Triangle {
+			points: points,
+			uvs: uvs,
+			normal: (p3 - p2).cross(p1 - p2).normalize().to_normal(),
+			// normal: (p2 - p1).cross(p3 - p1).normalize().to_normal(),
+			material_index: self.get_material_index(),
+			transform: transform,
+			inverse_transform: inverse_transform,
+		};
+		self.add_transformed_graphic(Box::new(triangle));
+		Ok(())
+	}
+
+	pub fn add_plane(&mut self, pos: Point3, normal: Normal3) -> Result<(), String> {
+		let (transform, inverse_transform) = self.get_transforms()?;
+		let plane = Plane {
+			pos: pos,
+			normal: normal.normalize(),
+			material_index: self.get_material_index(),
+			transform: transform,
+			inverse_transform: inverse_transform,
+		};
+		self.add_transformed_graphic(Box::new(plane));
+		Ok(())
+	}
+
+	pub fn add_cylinder(&mut self, radius: Float, y_min: Float, y_max: Float, capped: bool) -> Result<(), String> {
+		if radius <= 0. {
+			return Err(String::from("the radius of the cylinder is not positive"));
+		}
+		if y_min >= y_max {
+			return Err(String::from("the top of the cylinder is not above its bottom"));
+		}
+		let (transform, inverse_transform) = self.get_transforms()?;
+		let cylinder = Cylinder {
+			radius: radius,
+			y_min: y_min,
+			y_max: y_max,
+			capped: capped,
+			material_index: self.get_material_index(),
+			transform: transform,
+			inverse_transform: inverse_transform,
+		};
+		self.add_transformed_graphic(Box::new(cylinder));
+		Ok(())
+	}
+
+	pub fn add_cone(&mut self, radius: Float, height: Float, capped: bool) -> Result<(), String> {
+		if radius <= 0. || height <= 0. {
+			return Err(String::from("the radius or height of the cone is not positive"));
+		}
+		let (transform, inverse_transform) = self.get_transforms()?;
+		let cone = Cone {
+			radius: radius,
+			height: height,
+			capped: capped,
+			material_index: self.get_material_index(),
+			transform: transform,
+			inverse_transform: inverse_transform,
+		};
+		self.add_transformed_graphic(Box::new(cone));
+		Ok(())
+	}
+
+	pub fn add_disk(&mut self, pos: Point3, normal: Normal3, radius: Float) -> Result<(), String> {
+		if radius <= 0. {
+			return Err(String::from("the radius of the disk is not positive"));
+		}
+		if normal.to_vector().length_sq() == 0. {
+			return Err(String::from("the normal of the disk is zero"));
+		}
+		let (transform, inverse_transform) = self.get_transforms()?;
+		let disk = Disk {
+			pos: pos,
+			normal: normal.normalize(),
+			radius: radius,
+			material_index: self.get_material_index(),
+			transform: transform,
+			inverse_transform: inverse_transform,
+		};
+		self.add_transformed_graphic(Box::new(disk));
+		Ok(())
+	}
+
+	pub fn add_torus(&mut self, major_radius: Float, minor_radius: Float) -> Result<(), String> {
+		if major_radius <= 0. || minor_radius <= 0. {
+			return Err(String::from("the radii of the torus are not positive"));
+		}
+		let (transform, inverse_transform) = self.get_transforms()?;
+		let torus = Torus {
+			major_radius: major_radius,
+			minor_radius: minor_radius,
+			material_index: self.get_material_index(),
+			transform: transform,
+			inverse_transform: inverse_transform,
+		};
+		self.add_transformed_graphic(Box::new(torus));
+		Ok(())
+	}
+
+	pub fn add_box(&mut self, min: Point3, max: Point3) -> Result<(), String> {
+		if min.x >= max.x || min.y >= max.y || min.z >= max.z {
+			return Err(String::from("the box has no volume"));
+		}
+		let (transform, inverse_transform) = self.get_transforms()?;
+		let cuboid = Cuboid {
+			min: min,
+			max: max,
+			material_index: self.get_material_index(),
+			transform: transform,
+			inverse_transform: inverse_transform,
+		};
+		self.add_transformed_graphic(Box::new(cuboid));
+		Ok(())
+	}
+
+	pub fn add_quad(&mut self, pos: Point3, edge_u: Vector3, edge_v: Vector3) -> Result<(), String> {
+		// zero or (nearly) parallel edges leave no area and no normal
+		let cross = edge_u.cross(edge_v);
+		if cross.length() <= 1e-6 * edge_u.length() * edge_v.length() {
+			return Err(String::from("degenerate quad"));
+		}
+		let (transform, inverse_transform) = self.get_transforms()?;
+		let quad = Quad {
+			pos: pos,
+			edge_u: edge_u,
+			edge_v: edge_v,
+			normal: cross.normalize().to_normal(),
+			material_index: self.get_material_index(),
+			transform: transform,
+			inverse_transform: inverse_transform,
+		};
+		self.add_transformed_graphic(Box::new(quad));
+		Ok(())
+	}
+
+	pub fn add_sdf(&mut self, root: SdfNode, bounding_radius: Float) -> Result<(), String> {
+		let (transform, inverse_transform) = self.get_transforms()?;
+		let sdf = Sdf::new(root, bounding_radius, self.get_material_index(), transform, inverse_transform);
+		self.add_transformed_graphic(Box::new(sdf));
+		Ok(())
+	}
+
+	pub fn add_heightfield(&mut self, image: &BmpImage) -> Result<(), String> {
+		let heightfield = Heightfield::from_image(image, self.get_material_index(), self.get_transform())?;
+		self.add_transformed_graphic(Box::new(heightfield));
+		Ok(())
+	}
+
+	// the graphics added until the matching end_csg become the children of the node
+	pub fn begin_csg(&mut self, operation: CsgOperation) {
+		// the children keep their own transforms, but the node moves with the current transform too, which is how it
+		// is blurred as a whole
+		self.animate_next_graphic();
+		self.csg_stack.push((operation, Vec::new()));
+	}
+
+	pub fn end_csg(&mut self) -> Result<(), String> {
+		match self.csg_stack.pop() {
+			Some((operation, children)) => {
+				self.add_graphic(Box::new(Csg::new(operation, children)));
+				Ok(())
+			}
+			None => Err(String::from("no CSG node to end")),
+		}
+	}
+
+	pub fn is_defining_csg(&self) -> bool {
+		!self.csg_stack.is_empty()
+	}
+
+	// ---------- lights and background ----------
+
+	pub fn add_point_light(&mut self, pos: Point3, color: Color) {
+		let point_light = PointLight {
+			pos: pos,
+			color: color,
+			attenuation: self.attenuation.clone(),
+		};
+		self.lights.push(Box::new(point_light));
+	}
+
+	pub fn add_directional_light(&mut self, direction: Vector3, color: Color) {
+		let directional_light = DirectionalLight {
+			direction: direction.normalize(),
+			color: color,
+		};
+		self.lights.push(Box::new(directional_light));
+	}
+
+	// for the point lights added from now on
+	pub fn set_attenuation(&mut self, attenuation: Attenuation) {
+		self.attenuation = attenuation;
+	}
+
+	pub fn set_ambient(&mut self, color: Color) {
+		self.ambient_color = color;
+	}
+
+	pub fn set_background(&mut self, background: Box<dyn Background>) {
+		self.background = Some(background);
+	}
+
+	pub fn set_background_color(&mut self, color: Color) {
+		self.set_background(Box::new(ColorBackground { color: color }));
+	}
+
+	pub fn set_sky_gradient(&mut self, horizon: Color, zenith: Color, up: Vector3) {
+		self.set_background(Box::new(GradientBackground { horizon: horizon, zenith: zenith, up: up.normalize() }));
+	}
+
+	// the map's up direction is +y, transformed by the current transform
+	pub fn set_environment_map(&mut self, texture: ImageTexture, intensity: Float) -> Result<(), String> {
+		let (_, inverse_transform) = self.get_transforms()?;
+		self.set_background(Box::new(EnvironmentMap {
+			texture: texture,
+			intensity: intensity,
+			inverse_transform: inverse_transform,
+		}));
+		Ok(())
+	}
+}
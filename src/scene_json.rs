@@ -0,0 +1,615 @@
+use std::io;
+use std::io::prelude::*;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::collections::HashMap;
+use std::str::SplitWhitespace;
+use std::time::Instant;
+
+use float::*;
+use structs::*;
+use vec3::*;
+use mat4::*;
+use scene::*;
+use scene_builder::*;
+use scene_parser::*;
+use textures::*;
+use bmp::*;
+use csg::*;
+use sdf::*;
+use json::*;
+use animation::*;
+
+// JSON scene format (see the README for its schema). Scenes are loaded through a SceneBuilder, like scene files, with
+// errors reporting the path of the offending value, e.g. "objects[2].radius".
+
+fn invalid(path: &str, msg: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, msg))
+}
+
+pub fn parse_json_scene_file(filepath: String) -> Result<Scene, io::Error> {
+	let start = Instant::now();
+	let mut text = String::new();
+	File::open(&filepath)?.read_to_string(&mut text)?;
+
+	let load = || -> Result<Scene, io::Error> {
+		let document = parse_json(&text).map_err(|msg| io::Error::new(io::ErrorKind::InvalidData, msg))?;
+		let mut loader = JsonSceneLoader {
+			builder: SceneBuilder::new(),
+			loaded_images: HashMap::new(),
+			// textures are looked up relative to the scene file
+			scene_dir: Path::new(&filepath).parent().map(|dir| dir.to_path_buf()).unwrap_or_default(),
+		};
+		loader.load_scene(&document)?;
+		loader.builder.build().map_err(|msg| io::Error::new(io::ErrorKind::InvalidData, msg))
+	};
+	let mut scene = load().map_err(|err| io::Error::new(err.kind(), format!("{}: {}", filepath, err)))?;
+	scene.parse_time = start.elapsed().saturating_sub(scene.build_time);
+	Ok(scene)
+}
+
+// ---------- JsonSceneLoader ----------
+
+struct JsonSceneLoader {
+	builder: SceneBuilder,
+	loaded_images: HashMap<String, Rc<BmpImage>>,
+	scene_dir: PathBuf,
+}
+
+impl JsonSceneLoader {
+	fn load_scene(&mut self, document: &JsonValue) -> Result<(), io::Error> {
+		let members = as_object(document, "scene")?;
+		for (key, _) in members {
+			match key.as_str() {
+				"metadata" | "settings" | "camera" | "ambient" | "background" | "materials" | "animations" | "lights" | "objects" => {}
+				_ => { return Err(invalid(key, "unknown scene member")); }
+			}
+		}
+
+		if let Some(settings) = document.get("settings") {
+			if let Some(size) = settings.get("size") {
+				let size = as_numbers(size, "settings.size", 2)?;
+				self.builder.size(to_u32(size[0], "settings.size")?, to_u32(size[1], "settings.size")?);
+			}
+			if let Some(depth) = settings.get("maxdepth") {
+				self.builder.max_depth(to_u32(as_number(depth, "settings.maxdepth")?, "settings.maxdepth")?);
+			}
+			if let Some(output) = settings.get("output") {
+				self.builder.output(as_str(output, "settings.output")?);
+			}
+			if let Some(frames) = settings.get("frames") {
+				let frames = as_numbers(frames, "settings.frames", 2)?;
+				self.builder.frames(to_u32(frames[0], "settings.frames")?, to_u32(frames[1], "settings.frames")?);
+			}
+		}
+
+		if let Some(camera) = document.get("camera") {
+			self.builder.camera(
+				as_point(member(camera, "position", "camera")?, "camera.position")?,
+				as_point(member(camera, "look_at", "camera")?, "camera.look_at")?,
+				as_vector(member(camera, "up", "camera")?, "camera.up")?,
+				as_number(member(camera, "fov", "camera")?, "camera.fov")?,
+			);
+			if let Some(keys) = camera.get("keys") {
+				for (i, key) in as_array(keys, "camera.keys")?.iter().enumerate() {
+					self.load_camera_key(key, &format!("camera.keys[{}]", i))?;
+				}
+			}
+			if let Some(shutter) = camera.get("shutter") {
+				let shutter = as_numbers(shutter, "camera.shutter", 2)?;
+				let samples = match camera.get("shutter_samples") {
+					Some(samples) => to_u32(as_number(samples, "camera.shutter_samples")?, "camera.shutter_samples")?,
+					None => DEFAULT_SHUTTER_SAMPLES,
+				};
+				self.builder.shutter(shutter[0], shutter[1], samples);
+			}
+		}
+
+		if let Some(ambient) = document.get("ambient") {
+			self.builder.set_ambient(as_color(ambient, "ambient")?);
+		}
+		if let Some(background) = document.get("background") {
+			self.load_background(background)?;
+		}
+
+		if let Some(materials) = document.get("materials") {
+			for (name, value) in as_object(materials, "materials")? {
+				let material = self.load_material(value, &format!("materials.{}", name))?;
+				self.builder.define_material(name, material);
+			}
+		}
+
+		if let Some(animations) = document.get("animations") {
+			for (name, keys) in as_object(animations, "animations")? {
+				let track = load_animation(keys, &format!("animations.{}", name))?;
+				self.builder.define_animation(name, track);
+			}
+		}
+
+		if let Some(lights) = document.get("lights") {
+			for (i, light) in as_array(lights, "lights")?.iter().enumerate() {
+				self.load_light(light, &format!("lights[{}]", i))?;
+			}
+		}
+
+		if let Some(objects) = document.get("objects") {
+			self.load_objects(objects, "objects")?;
+		}
+		Ok(())
+	}
+
+	// {"frame", "position", "look_at", "fov"}, each but the frame optional
+	fn load_camera_key(&mut self, key: &JsonValue, path: &str) -> Result<(), io::Error> {
+		let frame = as_number(member(key, "frame", path)?, &format!("{}.frame", path))?;
+		if let Some(position) = key.get("position") {
+			self.builder.add_camera_position_key(frame, as_point(position, &format!("{}.position", path))?);
+		}
+		if let Some(look_at) = key.get("look_at") {
+			self.builder.add_camera_look_at_key(frame, as_point(look_at, &format!("{}.look_at", path))?);
+		}
+		if let Some(fov) = key.get("fov") {
+			self.builder.add_camera_fov_key(frame, as_number(fov, &format!("{}.fov", path))?);
+		}
+		Ok(())
+	}
+
+	fn load_background(&mut self, background: &JsonValue) -> Result<(), io::Error> {
+		let path = "background";
+		match as_str(member(background, "type", path)?, "background.type")? {
+			"color" => {
+				self.builder.set_background_color(as_color(member(background, "color", path)?, "background.color")?);
+			}
+			"gradient" => {
+				self.builder.set_sky_gradient(
+					as_color(member(background, "horizon", path)?, "background.horizon")?,
+					as_color(member(background, "zenith", path)?, "background.zenith")?,
+					as_vector(member(background, "up", path)?, "background.up")?,
+				);
+			}
+			"envmap" => {
+				let texture = load_environment_map(as_str(member(background, "file", path)?, "background.file")?, &self.scene_dir)?;
+				let intensity = match background.get("intensity") { Some(value) => as_number(value, "background.intensity")?, None => 1. };
+				self.builder.set_environment_map(texture, intensity).map_err(|msg| invalid("background", &msg))?;
+			}
+			kind => { return Err(invalid("background.type", &format!("unknown background type {}", kind))); }
+		}
+		Ok(())
+	}
+
+	fn load_light(&mut self, light: &JsonValue, path: &str) -> Result<(), io::Error> {
+		let color = as_color(member(light, "color", path)?, &format!("{}.color", path))?;
+		match as_str(member(light, "type", path)?, &format!("{}.type", path))? {
+			"point" => {
+				let attenuation = match light.get("attenuation") {
+					Some(value) => {
+						let factors = as_numbers(value, &format!("{}.attenuation", path), 3)?;
+						Attenuation { constant: factors[0], linear: factors[1], quadratic: factors[2] }
+					}
+					None => Default::default(),
+				};
+				self.builder.set_attenuation(attenuation);
+				self.builder.add_point_light(as_point(member(light, "position", path)?, &format!("{}.position", path))?, color);
+			}
+			"directional" => {
+				self.builder.add_directional_light(as_vector(member(light, "direction", path)?, &format!("{}.direction", path))?, color);
+			}
+			kind => { return Err(invalid(&format!("{}.type", path), &format!("unknown light type {}", kind))); }
+		}
+		Ok(())
+	}
+
+	fn load_material(&mut self, value: &JsonValue, path: &str) -> Result<Material, io::Error> {
+		let mut material: Material = Default::default();
+		for (key, value) in as_object(value, path)? {
+			let value_path = format!("{}.{}", path, key);
+			match key.as_str() {
+				"diffuse" => { material.kd = as_color(value, &value_path)?; }
+				"specular" => { material.ks = as_color(value, &value_path)?; }
+				"emission" => { material.ke = as_color(value, &value_path)?; }
+				"shininess" => { material.shininess = as_number(value, &value_path)?; }
+				"brdf" => {
+					material.brdf = match as_str(value, &value_path)? {
+						"phong" => Brdf::Phong,
+						"cooktorrance" => Brdf::CookTorrance,
+						brdf => { return Err(invalid(&value_path, &format!("unknown BRDF {}", brdf))); }
+					};
+				}
+				"roughness" => { material.roughness = as_number(value, &value_path)?; }
+				"metallic" => { material.metallic = as_number(value, &value_path)?; }
+				"bump_strength" => { material.bump_strength = as_number(value, &value_path)?; }
+				"textures" => {
+					for (channel, texture) in as_object(value, &value_path)? {
+						let texture_path = format!("{}.{}", value_path, channel);
+						let texture = Some(self.load_texture(texture, &texture_path)?);
+						match channel.as_str() {
+							"diffuse" => { material.kd_texture = texture; }
+							"specular" => { material.ks_texture = texture; }
+							"emission" => { material.ke_texture = texture; }
+							"bump" => { material.bump_texture = texture; }
+							"normal" => { material.normal_texture = texture; }
+							_ => { return Err(invalid(&texture_path, "unknown texture channel")); }
+						}
+					}
+				}
+				_ => { return Err(invalid(&value_path, "unknown material member")); }
+			}
+		}
+		Ok(material)
+	}
+
+	fn load_texture(&mut self, texture: &JsonValue, path: &str) -> Result<Rc<dyn Texture>, io::Error> {
+		let number = |key: &str| as_number(member(texture, key, path)?, &format!("{}.{}", path, key));
+		let color = |key: &str| as_color(member(texture, key, path)?, &format!("{}.{}", path, key));
+
+		let texture: Rc<dyn Texture> = match as_str(member(texture, "type", path)?, &format!("{}.type", path))? {
+			"image" => {
+				let wrap = match texture.get("wrap").map(|value| as_str(value, &format!("{}.wrap", path))).unwrap_or(Ok("wrap"))? {
+					"wrap" => WrapMode::Wrap,
+					"clamp" => WrapMode::Clamp,
+					mode => { return Err(invalid(path, &format!("unknown wrap mode {}", mode))); }
+				};
+				let filter = match texture.get("filter").map(|value| as_str(value, &format!("{}.filter", path))).unwrap_or(Ok("bilinear"))? {
+					"bilinear" => FilterMode::Bilinear,
+					"nearest" => FilterMode::Nearest,
+					mode => { return Err(invalid(path, &format!("unknown filter mode {}", mode))); }
+				};
+				let filename = as_str(member(texture, "file", path)?, &format!("{}.file", path))?;
+				let image = load_image(filename, &self.scene_dir, &mut self.loaded_images)?;
+				Rc::new(ImageTexture::from_image(&image, wrap, filter))
+			}
+			"checker" => Rc::new(CheckerTexture { scale: number("scale")?, color1: color("color1")?, color2: color("color2")? }),
+			"gradient" => Rc::new(GradientTexture {
+				color1: color("color1")?,
+				color2: color("color2")?,
+				axis: as_vector(member(texture, "axis", path)?, &format!("{}.axis", path))?,
+			}),
+			"noise" => Rc::new(NoiseTexture {
+				scale: number("scale")?,
+				octaves: to_u32(number("octaves")?, &format!("{}.octaves", path))?,
+				color1: color("color1")?,
+				color2: color("color2")?,
+			}),
+			"marble" => Rc::new(MarbleTexture {
+				scale: number("scale")?,
+				turbulence: number("turbulence")?,
+				octaves: to_u32(number("octaves")?, &format!("{}.octaves", path))?,
+				color1: color("color1")?,
+				color2: color("color2")?,
+			}),
+			"wood" => Rc::new(WoodTexture { rings: number("rings")?, turbulence: number("turbulence")?, color1: color("color1")?, color2: color("color2")? }),
+			kind => { return Err(invalid(&format!("{}.type", path), &format!("unknown texture type {}", kind))); }
+		};
+		Ok(texture)
+	}
+
+	fn load_objects(&mut self, objects: &JsonValue, path: &str) -> Result<(), io::Error> {
+		for (i, object) in as_array(objects, path)?.iter().enumerate() {
+			self.load_object(object, &format!("{}[{}]", path, i))?;
+		}
+		Ok(())
+	}
+
+	// the object's transform and material, if it has them, apply to it (and to its children) only
+	fn load_object(&mut self, object: &JsonValue, path: &str) -> Result<(), io::Error> {
+		let parent_material = self.builder.get_material_index();
+		match object.get("material") {
+			// objects using one of the scene's materials share it
+			Some(JsonValue::String(name)) => {
+				self.builder.use_material(name).map_err(|msg| invalid(&format!("{}.material", path), &msg))?;
+			}
+			Some(material) => {
+				let material = self.load_material(material, &format!("{}.material", path))?;
+				self.builder.set_material(material);
+			}
+			None => {}
+		}
+		self.builder.push_transform();
+		if let Some(transforms) = object.get("transform") {
+			self.load_transforms(transforms, &format!("{}.transform", path))?;
+		}
+
+		self.load_shape(object, path)?;
+
+		self.builder.pop_transform().unwrap();
+		self.builder.set_material_index(parent_material);
+		Ok(())
+	}
+
+	fn load_shape(&mut self, object: &JsonValue, path: &str) -> Result<(), io::Error> {
+		let value = |key: &str| member(object, key, path);
+		let value_path = |key: &str| format!("{}.{}", path, key);
+		let number = |key: &str| as_number(value(key)?, &value_path(key));
+		let point = |key: &str| as_point(value(key)?, &value_path(key));
+		let vector = |key: &str| as_vector(value(key)?, &value_path(key));
+		let located = |msg: String| invalid(path, &msg);
+		let capped = || match object.get("capped") { Some(capped) => as_bool(capped, &value_path("capped")), None => Ok(true) };
+
+		match as_str(value("type")?, &value_path("type"))? {
+			"group" => { self.load_objects(value("objects")?, &value_path("objects"))?; }
+			"sphere" => { self.builder.add_sphere(point("center")?, number("radius")?).map_err(located)?; }
+			"triangle" => {
+				let points = as_array(value("vertices")?, &value_path("vertices"))?;
+				if points.len() != 3 {
+					return Err(invalid(&value_path("vertices"), "expected 3 vertices"));
+				}
+				let mut corners = [Point3::origin(); 3];
+				for i in 0..3 {
+					corners[i] = as_point(&points[i], &format!("{}[{}]", value_path("vertices"), i))?;
+				}
+				match object.get("uvs") {
+					Some(uvs) => {
+						let uvs = as_numbers(uvs, &value_path("uvs"), 6)?;
+						self.builder.add_textured_triangle(corners, [UV { u: uvs[0], v: uvs[1] }, UV { u: uvs[2], v: uvs[3] }, UV { u: uvs[4], v: uvs[5] }]).map_err(located)?;
+					}
+					None => { self.builder.add_triangle(corners).map_err(located)?; }
+				}
+			}
+			"mesh" => { self.load_mesh(object, path)?; }
+			"plane" => { self.builder.add_plane(point("point")?, vector("normal")?.to_normal()).map_err(located)?; }
+			"cylinder" => { self.builder.add_cylinder(number("radius")?, number("y_min")?, number("y_max")?, capped()?).map_err(located)?; }
+			"cone" => { self.builder.add_cone(number("radius")?, number("height")?, capped()?).map_err(located)?; }
+			"disk" => { self.builder.add_disk(point("center")?, vector("normal")?.to_normal(), number("radius")?).map_err(located)?; }
+			"torus" => { self.builder.add_torus(number("major_radius")?, number("minor_radius")?).map_err(located)?; }
+			"box" => { self.builder.add_box(point("min")?, point("max")?).map_err(located)?; }
+			"quad" => { self.builder.add_quad(point("corner")?, vector("edge_u")?, vector("edge_v")?).map_err(located)?; }
+			"sdf" => {
+				let function = as_str(value("function")?, &value_path("function"))?;
+				let mut tokens: SplitWhitespace = function.split_whitespace();
+				let root = SdfNode::parse(&mut tokens).map_err(|msg| invalid(&value_path("function"), &msg))?;
+				self.builder.add_sdf(root, number("bounding_radius")?).map_err(located)?;
+			}
+			"heightfield" => {
+				let image = load_image(as_str(value("file")?, &value_path("file"))?, &self.scene_dir, &mut self.loaded_images)?;
+				self.builder.add_heightfield(&image).map_err(located)?;
+			}
+			"csg" => {
+				let operation = match as_str(value("operation")?, &value_path("operation"))? {
+					"union" => CsgOperation::Union,
+					"intersection" => CsgOperation::Intersection,
+					"difference" => CsgOperation::Difference,
+					operation => { return Err(invalid(&value_path("operation"), &format!("unknown CSG operation {}", operation))); }
+				};
+				self.builder.begin_csg(operation);
+				self.load_objects(value("objects")?, &value_path("objects"))?;
+				self.builder.end_csg().unwrap();
+			}
+			kind => { return Err(invalid(&value_path("type"), &format!("unknown object type {}", kind))); }
+		}
+		Ok(())
+	}
+
+	// triangles indexing a list of vertices, with optional per-vertex UVs
+	fn load_mesh(&mut self, mesh: &JsonValue, path: &str) -> Result<(), io::Error> {
+		let vertices_path = format!("{}.vertices", path);
+		let vertices = as_array(member(mesh, "vertices", path)?, &vertices_path)?.iter().enumerate()
+			.map(|(i, vertex)| as_point(vertex, &format!("{}[{}]", vertices_path, i)))
+			.collect::<Result<Vec<Point3>, io::Error>>()?;
+		let uvs = match mesh.get("uvs") {
+			Some(uvs) => {
+				let uvs_path = format!("{}.uvs", path);
+				let uvs = as_array(uvs, &uvs_path)?.iter().enumerate()
+					.map(|(i, uv)| as_numbers(uv, &format!("{}[{}]", uvs_path, i), 2).map(|uv| UV { u: uv[0], v: uv[1] }))
+					.collect::<Result<Vec<UV>, io::Error>>()?;
+				if uvs.len() != vertices.len() {
+					return Err(invalid(&uvs_path, "expected one UV per vertex"));
+				}
+				Some(uvs)
+			}
+			None => None,
+		};
+
+		let triangles_path = format!("{}.triangles", path);
+		for (i, triangle) in as_array(member(mesh, "triangles", path)?, &triangles_path)?.iter().enumerate() {
+			let triangle_path = format!("{}[{}]", triangles_path, i);
+			let mut indices = [0; 3];
+			for (j, &index) in as_numbers(triangle, &triangle_path, 3)?.iter().enumerate() {
+				indices[j] = to_u32(index, &triangle_path)? as usize;
+				if indices[j] >= vertices.len() {
+					return Err(invalid(&triangle_path, "vertex index out of range"));
+				}
+			}
+			let points = [vertices[indices[0]], vertices[indices[1]], vertices[indices[2]]];
+			match uvs {
+				Some(ref uvs) => { self.builder.add_textured_triangle(points, [uvs[indices[0]], uvs[indices[1]], uvs[indices[2]]]).map_err(|msg| invalid(&triangle_path, &msg))?; }
+				None => { self.builder.add_triangle(points).map_err(|msg| invalid(&triangle_path, &msg))?; }
+			}
+		}
+		Ok(())
+	}
+
+	// applied in order, like the transform commands of scene files
+	fn load_transforms(&mut self, transforms: &JsonValue, path: &str) -> Result<(), io::Error> {
+		for (i, transform) in as_array(transforms, path)?.iter().enumerate() {
+			let transform_path = format!("{}[{}]", path, i);
+			let members = as_object(transform, &transform_path)?;
+			if members.len() != 1 {
+				return Err(invalid(&transform_path, "expected a single transform"));
+			}
+			let (kind, value) = &members[0];
+			let value_path = format!("{}.{}", transform_path, kind);
+			match kind.as_str() {
+				"translate" => {
+					let offset = as_numbers(value, &value_path, 3)?;
+					self.builder.translate(offset[0], offset[1], offset[2]);
+				}
+				"rotate" => {
+					let axis = as_vector(member(value, "axis", &value_path)?, &format!("{}.axis", value_path))?;
+					self.builder.rotate(axis, as_number(member(value, "angle", &value_path)?, &format!("{}.angle", value_path))?);
+				}
+				"scale" => {
+					let factors = as_numbers(value, &value_path, 3)?;
+					self.builder.scale(factors[0], factors[1], factors[2]);
+				}
+				"animate" => {
+					self.builder.animate(as_str(value, &value_path)?).map_err(|msg| invalid(&value_path, &msg))?;
+				}
+				"euler" => {
+					let angles = as_numbers(member(value, "angles", &value_path)?, &format!("{}.angles", value_path), 3)?;
+					let order = match value.get("order") {
+						Some(order) => {
+							let order_path = format!("{}.order", value_path);
+							let order = as_str(order, &order_path)?;
+							RotationOrder::parse(order).ok_or_else(|| invalid(&order_path, &format!("unknown rotation order {}", order)))?
+						}
+						None => RotationOrder::Xyz,
+					};
+					self.builder.rotate_euler(angles[0], angles[1], angles[2], order);
+				}
+				"quaternion" => {
+					let q = as_numbers(value, &value_path, 4)?;
+					self.builder.rotate_quaternion(q[0], q[1], q[2], q[3]).map_err(|msg| invalid(&value_path, &msg))?;
+				}
+				"lookat" => {
+					self.builder.look_at(
+						as_point(member(value, "position", &value_path)?, &format!("{}.position", value_path))?,
+						as_point(member(value, "target", &value_path)?, &format!("{}.target", value_path))?,
+						as_vector(member(value, "up", &value_path)?, &format!("{}.up", value_path))?,
+					).map_err(|msg| invalid(&value_path, &msg))?;
+				}
+				"matrix" => {
+					let values = as_numbers(value, &value_path, 16)?;
+					let mut matrix = Mat4::id(1.);
+					for row in 0..4 {
+						for col in 0..4 {
+							matrix.data[row][col] = values[4 * row + col];
+						}
+					}
+					self.builder.apply_matrix(matrix).map_err(|msg| invalid(&value_path, &msg))?;
+				}
+				_ => { return Err(invalid(&transform_path, &format!("unknown transform {}", kind))); }
+			}
+		}
+		Ok(())
+	}
+}
+
+// array of keys {"frame", "translate", "rotate": {"axis", "angle"}, "quaternion", "scale"}, each but the frame optional
+fn load_animation(keys: &JsonValue, path: &str) -> Result<TransformTrack, io::Error> {
+	let mut track: TransformTrack = Default::default();
+	for (i, key) in as_array(keys, path)?.iter().enumerate() {
+		let key_path = format!("{}[{}]", path, i);
+		let value_path = |name: &str| format!("{}.{}", key_path, name);
+		let frame = as_number(member(key, "frame", &key_path)?, &value_path("frame"))?;
+		if let Some(offset) = key.get("translate") {
+			track.translation.insert(frame, as_vector(offset, &value_path("translate"))?);
+		}
+		if let Some(rotation) = key.get("rotate") {
+			let rotation_path = value_path("rotate");
+			let axis = as_vector(member(rotation, "axis", &rotation_path)?, &format!("{}.axis", rotation_path))?;
+			let angle = as_number(member(rotation, "angle", &rotation_path)?, &format!("{}.angle", rotation_path))?;
+			track.rotation.insert(frame, Quaternion::from_axis_angle(angle, axis));
+		}
+		if let Some(quaternion) = key.get("quaternion") {
+			let q = as_numbers(quaternion, &value_path("quaternion"), 4)?;
+			let quaternion = Quaternion::new(q[0], q[1], q[2], q[3]).ok_or_else(|| invalid(&value_path("quaternion"), "zero quaternion"))?;
+			track.rotation.insert(frame, quaternion);
+		}
+		if let Some(factors) = key.get("scale") {
+			track.scale.insert(frame, as_vector(factors, &value_path("scale"))?);
+		}
+	}
+	Ok(track)
+}
+
+// ---------- values ----------
+
+fn member<'a>(object: &'a JsonValue, key: &str, path: &str) -> Result<&'a JsonValue, io::Error> {
+	as_object(object, path)?;
+	object.get(key).ok_or_else(|| invalid(path, &format!("missing member {}", key)))
+}
+
+fn as_object<'a>(value: &'a JsonValue, path: &str) -> Result<&'a [(String, JsonValue)], io::Error> {
+	match *value {
+		JsonValue::Object(ref members) => Ok(members),
+		_ => Err(invalid(path, &format!("expected an object, found {}", value.describe()))),
+	}
+}
+
+fn as_array<'a>(value: &'a JsonValue, path: &str) -> Result<&'a [JsonValue], io::Error> {
+	match *value {
+		JsonValue::Array(ref values) => Ok(values),
+		_ => Err(invalid(path, &format!("expected an array, found {}", value.describe()))),
+	}
+}
+
+fn as_str<'a>(value: &'a JsonValue, path: &str) -> Result<&'a str, io::Error> {
+	match *value {
+		JsonValue::String(ref string) => Ok(string),
+		_ => Err(invalid(path, &format!("expected a string, found {}", value.describe()))),
+	}
+}
+
+fn as_bool(value: &JsonValue, path: &str) -> Result<bool, io::Error> {
+	match *value {
+		JsonValue::Bool(boolean) => Ok(boolean),
+		_ => Err(invalid(path, &format!("expected a boolean, found {}", value.describe()))),
+	}
+}
+
+fn as_number(value: &JsonValue, path: &str) -> Result<Float, io::Error> {
+	match *value {
+		JsonValue::Number(number) => Ok(number),
+		_ => Err(invalid(path, &format!("expected a number, found {}", value.describe()))),
+	}
+}
+
+fn to_u32(number: Float, path: &str) -> Result<u32, io::Error> {
+	if number >= 0. && number <= u32::MAX as Float && number.fract() == 0. {
+		Ok(number as u32)
+	} else {
+		Err(invalid(path, &format!("expected a non-negative integer, found {}", number)))
+	}
+}
+
+// array of exactly count numbers
+fn as_numbers(value: &JsonValue, path: &str, count: usize) -> Result<Vec<Float>, io::Error> {
+	let values = as_array(value, path)?;
+	if values.len() != count {
+		return Err(invalid(path, &format!("expected {} numbers, found {}", count, values.len())));
+	}
+	values.iter().map(|value| as_number(value, path)).collect()
+}
+
+fn as_point(value: &JsonValue, path: &str) -> Result<Point3, io::Error> {
+	let xyz = as_numbers(value, path, 3)?;
+	Ok(Point3::new(xyz[0], xyz[1], xyz[2]))
+}
+
+fn as_vector(value: &JsonValue, path: &str) -> Result<Vector3, io::Error> {
+	let xyz = as_numbers(value, path, 3)?;
+	Ok(Vector3::new(xyz[0], xyz[1], xyz[2]))
+}
+
+fn as_color(value: &JsonValue, path: &str) -> Result<Color, io::Error> {
+	let rgb = as_numbers(value, path, 3)?;
+	Ok(Color { r: rgb[0], g: rgb[1], b: rgb[2] })
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::env;
+	use std::fs;
+	use std::process;
+
+	static CAMERA: &str = r#""settings": {"size": [64, 48]}, "camera": {"position": [0, 0, 5], "look_at": [0, 0, 0], "up": [0, 1, 0], "fov": 45}"#;
+
+	// the error of the scene, which must not load, without the file name
+	fn load_error(name: &str, members: &str) -> String {
+		let path = env::temp_dir().join(format!("raytracer-test-{}-{}.json", process::id(), name));
+		fs::write(&path, format!("{{{}, {}}}", CAMERA, members)).unwrap();
+		let result = parse_json_scene_file(path.to_string_lossy().into_owned());
+		let _ = fs::remove_file(&path);
+		match result {
+			Ok(_) => panic!("the scene loaded: {}", members),
+			Err(err) => err.to_string().replacen(&format!("{}: ", path.display()), "", 1),
+		}
+	}
+
+	#[test]
+	fn reports_unknown_members() {
+		assert_eq!(load_error("scene_member", r#""lihgts": []"#), "lihgts: unknown scene member");
+		assert_eq!(load_error("material_member", r#""materials": {"red": {"difuse": [1, 0, 0]}}"#), "materials.red.difuse: unknown material member");
+	}
+}
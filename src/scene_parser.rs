@@ -2,213 +2,778 @@ use std::io;
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::collections::HashMap;
 use std::str::SplitWhitespace;
+use std::time::Instant;
 
-use graphics::*;
-use lights::*;
+use float::*;
 use structs::*;
-use vec4::*;
+use vec3::*;
 use mat4::*;
 use scene::*;
+use scene_builder::*;
+use scene_json::*;
+use textures::*;
+use bmp::*;
+use hdr::*;
+use csg::*;
+use sdf::*;
+use animation::*;
 
+// scene files, or JSON scenes when the extension is .json
 pub fn parse_scene_file(filepath: String) -> Result<Scene, io::Error> {
-	let mut graphics: Vec<Box<Graphic>> = Vec::new();
-	let mut lights: Vec<Box<Light>> = Vec::new();
-	let mut camera: Camera = Default::default();
-	let mut ambient_color: Color = Color::black();
-	let mut max_raytrace_depth: u32 = 5;
-	let mut image_width: u32 = 640;
-	let mut image_height: u32 = 460;
-	let mut image_filename: String = String::from("output.bmp");
-
-	let mut attenuation: Attenuation = Default::default();
-	let mut diffuse_color: Color = Color::black();
-	let mut specular_color: Color = Color::black();
-	let mut shininess: f64 = 0.;
-	let mut emission_color: Color = Color::black();
-
-	let mut vertices: Vec<Vec4> = Vec::new();
-
-	let mut transformation_stack: Vec<Mat4> = Vec::new();
-	transformation_stack.push(Mat4::id(1.));
-
-	let f = try!(File::open(filepath));
-	let reader = BufReader::new(f);
-
-	for line in reader.lines() {
-		let line_as_string = try!(line);
-		let line = line_as_string.trim();
-		if line.starts_with("#") || line.is_empty() {
-			continue;
+	if filepath.ends_with(".json") {
+		return parse_json_scene_file(filepath);
+	}
+	let start = Instant::now();
+
+	let mut parser = SceneFileParser {
+		builder: SceneBuilder::new(),
+		loaded_images: HashMap::new(),
+		vertices: Vec::new(),
+		tex_vertices: Vec::new(),
+		variables: HashMap::new(),
+		material_block: None,
+		animation_block: None,
+		included_files: Vec::new(),
+		location: filepath.clone(),
+	};
+	parser.parse_file(Path::new(&filepath))?;
+
+	if let Some((name, material)) = parser.material_block.take() {
+		println!("{}: Missing endmaterial!", filepath);
+		parser.builder.define_material(&name, material);
+	}
+	if let Some((name, track)) = parser.animation_block.take() {
+		println!("{}: Missing endanimation!", filepath);
+		parser.builder.define_animation(&name, track);
+	}
+	let mut builder = parser.builder;
+	while builder.is_defining_csg() {
+		println!("{}: Missing endcsg!", filepath);
+		let _ = builder.end_csg();
+	}
+
+	let mut scene = builder.build().map_err(|msg| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", filepath, msg)))?;
+	scene.parse_time = start.elapsed().saturating_sub(scene.build_time);
+	Ok(scene)
+}
+
+// ---------- SceneFileParser ----------
+
+// State shared by a scene file and the files it includes: vertices, variables, the current material and transforms
+// carry over from one file to the other, as if the included file was pasted in place of the include command.
+struct SceneFileParser {
+	builder: SceneBuilder,
+	loaded_images: HashMap<String, Rc<BmpImage>>,
+	vertices: Vec<Point3>,
+	tex_vertices: Vec<(Point3, UV)>,
+	variables: HashMap<String, String>,
+	material_block: Option<(String, Material)>,	// named material being defined, between material and endmaterial
+	animation_block: Option<(String, TransformTrack)>,	// animation being defined, between animation and endanimation
+	included_files: Vec<PathBuf>,	// files being parsed, to detect include cycles
+	location: String,	// file and line of the command being parsed
+}
+
+impl SceneFileParser {
+	// false if a break command was found, which ends the whole scene
+	fn parse_file(&mut self, filepath: &Path) -> Result<bool, io::Error> {
+		let f = File::open(filepath).map_err(|err| io::Error::new(err.kind(), format!("{}: {}", filepath.display(), err)))?;
+		let canonical_path = filepath.canonicalize()?;
+		if self.included_files.contains(&canonical_path) {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}: include cycle", filepath.display())));
+		}
+		self.included_files.push(canonical_path);
+
+		// textures and included files are looked up relative to the scene file
+		let scene_dir = filepath.parent().map(|dir| dir.to_path_buf()).unwrap_or_default();
+
+		let reader = BufReader::new(f);
+		let mut finished = true;
+		for (line_number, line) in reader.lines().enumerate() {
+			let line_as_string = line?;
+			let line = line_as_string.trim();
+			if line.starts_with("#") || line.is_empty() {
+				continue;
+			}
+
+			self.location = format!("{}:{}", filepath.display(), line_number + 1);
+			let located = |location: &String, err: io::Error| io::Error::new(err.kind(), format!("{}: {}", location, err));
+			let line = self.substitute_variables(line).map_err(|err| located(&self.location, err))?;
+			let keep_parsing = if line.split_whitespace().next() == Some("include") {
+				// the rest of the line, so that paths may contain spaces
+				let filename = line["include".len()..].trim();
+				if filename.is_empty() {
+					return Err(located(&self.location, invalid(String::from("missing file to include"))));
+				}
+				let location = self.location.clone();
+				self.parse_file(&scene_dir.join(filename)).map_err(|err| io::Error::new(err.kind(), format!("{}\n\tincluded from {}", err, location)))?
+			} else {
+				self.parse_command(&line, &scene_dir).map_err(|err| located(&self.location, err))?
+			};
+			if !keep_parsing {
+				finished = false;
+				break;
+			}
+		}
+
+		self.included_files.pop();
+		Ok(finished)
+	}
+
+	// replaces every $name by the value of the variable
+	fn substitute_variables(&self, line: &str) -> Result<String, io::Error> {
+		let mut result = String::new();
+		let mut rest = line;
+		while let Some(start) = rest.find('$') {
+			result.push_str(&rest[..start]);
+			rest = &rest[start + 1..];
+			let length = rest.find(|c: char| !is_variable_char(c)).unwrap_or(rest.len());
+			let name = &rest[..length];
+			match self.variables.get(name) {
+				Some(value) => { result.push_str(value); }
+				None if name.is_empty() => { return Err(invalid(String::from("missing variable name after $"))); }
+				None => { return Err(invalid(format!("undefined variable ${}", name))); }
+			}
+			rest = &rest[length..];
+		}
+		result.push_str(rest);
+		Ok(result)
+	}
+
+	// define name value (unless already defined, so that files can give defaults to be overridden before including them)
+	// set name value
+	fn define_variable(&mut self, tokens: &mut SplitWhitespace, overwrite: bool) -> Result<(), io::Error> {
+		let name = tokens.next().ok_or_else(|| invalid(String::from("missing variable name")))?;
+		if !name.chars().all(is_variable_char) {
+			return Err(invalid(format!("bad variable name {}", name)));
 		}
+		let value = tokens.collect::<Vec<&str>>().join(" ");
+		if overwrite || !self.variables.contains_key(name) {
+			self.variables.insert(String::from(name), value);
+		}
+		Ok(())
+	}
+
+	fn get_vertex(&self, tokens: &mut SplitWhitespace) -> Result<Point3, io::Error> {
+		let index = parse_u32(tokens)? as usize;
+		self.vertices.get(index).cloned().ok_or_else(|| invalid(format!("no vertex {}", index)))
+	}
+
+	fn get_tex_vertex(&self, tokens: &mut SplitWhitespace) -> Result<(Point3, UV), io::Error> {
+		let index = parse_u32(tokens)? as usize;
+		self.tex_vertices.get(index).cloned().ok_or_else(|| invalid(format!("no textured vertex {}", index)))
+	}
+
+	// the named material being defined, or else the one given to the objects that follow
+	fn material_mut(&mut self) -> &mut Material {
+		match self.material_block {
+			Some((_, ref mut material)) => material,
+			None => self.builder.material_mut(),
+		}
+	}
 
+	// false if the command is break
+	fn parse_command(&mut self, line: &str, scene_dir: &Path) -> Result<bool, io::Error> {
 		let mut tokens = line.split_whitespace();
-		match tokens.next() {
-			None => {}
-			Some(command) => {
-				match command {
-					"break" => {
-						break;
-					},
-					"size" => {
-						image_width = parse_u32(&mut tokens);
-						image_height = parse_u32(&mut tokens);
-					},
-					"maxdepth" => {
-						max_raytrace_depth = parse_u32(&mut tokens);
-					},
-					"output" => {
-						image_filename = tokens.collect::<Vec<&str>>().join(" ");
-					},
-					"camera" => {
-						let pos     = Vec4 { x:parse_f64(&mut tokens), y:parse_f64(&mut tokens), z:parse_f64(&mut tokens), w:1. };
-						let look_at	= Vec4 { x:parse_f64(&mut tokens), y:parse_f64(&mut tokens), z:parse_f64(&mut tokens), w:1. };
-						let up	    = Vec4 { x:parse_f64(&mut tokens), y:parse_f64(&mut tokens), z:parse_f64(&mut tokens), w:0. }.normalize();	// note: w = 0
-						let fov_y = parse_f64(&mut tokens);
-
-						camera = Camera::new(
-							pos,
-							look_at,
-							up,
-							fov_y,
-							image_width as f64,
-							image_height as f64,
-						);
-					},
-					"sphere" => {
-						let transform: Mat4 = transformation_stack.last().unwrap().clone();
-						let inverse_transform: Mat4 = transform.invert().unwrap();
-						let transposed_inverse_transform: Mat4 = inverse_transform.transpose();
-
-						let sphere = Sphere {
-							pos: Vec4 { x:parse_f64(&mut tokens), y:parse_f64(&mut tokens), z:parse_f64(&mut tokens), w:1. },
-							radius: parse_f64(&mut tokens),
-							material: Material {
-								ke: emission_color,
-								kd: diffuse_color,
-								ks: specular_color,
-								shininess: shininess,
-							},
-							transform: transform,
-							inverse_transform: inverse_transform,
-							transposed_inverse_transform: transposed_inverse_transform,
-						};
-						graphics.push(Box::new(sphere));
-					},
-					"maxverts" => {
-						vertices.reserve(parse_u32(&mut tokens) as usize);
-					},
-					"vertex" => {
-						vertices.push(Vec4 { x:parse_f64(&mut tokens), y:parse_f64(&mut tokens), z:parse_f64(&mut tokens), w:1. });
-					},
-					"tri" => {
-						let p1 = vertices[parse_u32(&mut tokens) as usize];
-						let p2 = vertices[parse_u32(&mut tokens) as usize];
-						let p3 = vertices[parse_u32(&mut tokens) as usize];
-
-						let transform: Mat4 = transformation_stack.last().unwrap().clone();
-						let inverse_transform: Mat4 = transform.invert().unwrap();
-						let transposed_inverse_transform: Mat4 = inverse_transform.transpose();
-
-						let triangle = Triangle {
-							points: [p1, p2, p3],
-							normal: (p3 - p2).cross(p1 - p2).normalize(),
-							// normal: (p2 - p1).cross(p3 - p1).normalize(),
-							material: Material {
-								ke: emission_color,
-								kd: diffuse_color,
-								ks: specular_color,
-								shininess: shininess,
-							},
-							transform: transform,
-							inverse_transform: inverse_transform,
-							transposed_inverse_transform: transposed_inverse_transform,
-						};
-
-						graphics.push(Box::new(triangle));
-					},
-					"translate" => {
-						let matrix: Mat4 = Mat4::create_translation(parse_f64(&mut tokens), parse_f64(&mut tokens), parse_f64(&mut tokens));
-						let top_matrix: Mat4 = transformation_stack.pop().unwrap();
-						transformation_stack.push(top_matrix * matrix);
-					},
-					"rotate" => {
-						let axis = Vec4 { x:parse_f64(&mut tokens), y:parse_f64(&mut tokens), z:parse_f64(&mut tokens), w:1. }.normalize();
-						let matrix: Mat4 = Mat4::create_rotation(parse_f64(&mut tokens), axis);
-						let top_matrix: Mat4 = transformation_stack.pop().unwrap();
-						transformation_stack.push(top_matrix * matrix);
-					},
-					"scale" => {
-						let matrix: Mat4 = Mat4::create_scale(parse_f64(&mut tokens), parse_f64(&mut tokens), parse_f64(&mut tokens));
-						let top_matrix: Mat4 = transformation_stack.pop().unwrap();
-						transformation_stack.push(top_matrix * matrix);
-					},
-					"pushTransform" => {
-						let mat = transformation_stack.last().unwrap().clone();
-						transformation_stack.push(mat);	// doubles the top element
-					},
-					"popTransform" => {
-						match transformation_stack.pop() {
-							Some(_) => {}
-							None => { println!("Stack has no elements! Cannot pop."); }
-						}
-					},
-					"directional" => {
-						let directional_light = DirectionalLight {
-							direction: Vec4 { x:parse_f64(&mut tokens), y:parse_f64(&mut tokens), z:parse_f64(&mut tokens), w:0. }.normalize(),	// note: w = 0
-							color: Color { r:parse_f64(&mut tokens), g:parse_f64(&mut tokens), b:parse_f64(&mut tokens) },
-						};
-						lights.push(Box::new(directional_light));
-					},
-					"point" => {
-						let point_light = PointLight {
-							pos: Vec4 { x:parse_f64(&mut tokens), y:parse_f64(&mut tokens), z:parse_f64(&mut tokens), w:1. },
-							color: Color { r:parse_f64(&mut tokens), g:parse_f64(&mut tokens), b:parse_f64(&mut tokens) },
-							attenuation: attenuation.clone(),
-						};
-						lights.push(Box::new(point_light));
-					},
-					"attenuation" => {
-						attenuation = Attenuation { constant:parse_f64(&mut tokens), linear:parse_f64(&mut tokens), quadratic:parse_f64(&mut tokens) };
-					},
-					"ambient" => {
-						ambient_color = Color { r:parse_f64(&mut tokens), g:parse_f64(&mut tokens), b:parse_f64(&mut tokens) };
-					},
-					"diffuse" => {
-						diffuse_color = Color { r:parse_f64(&mut tokens), g:parse_f64(&mut tokens), b:parse_f64(&mut tokens) };
-					},
-					"specular" => {
-						specular_color = Color { r:parse_f64(&mut tokens), g:parse_f64(&mut tokens), b:parse_f64(&mut tokens) };
-					},
-					"shininess" => {
-						shininess = parse_f64(&mut tokens);
-					},
-					"emission" => {
-						emission_color = Color { r:parse_f64(&mut tokens), g:parse_f64(&mut tokens), b:parse_f64(&mut tokens) };
-					},
-					_ => {
-						println!("Unrecognized command {}!", command);
+		let command = match tokens.next() {
+			Some(command) => command,
+			None => { return Ok(true); }
+		};
+		if self.material_block.is_some() && !is_material_command(command) {
+			return Err(invalid(format!("{} cannot be used in a material block", command)));
+		}
+		if self.animation_block.is_some() && command != "key" && command != "endanimation" {
+			return Err(invalid(format!("{} cannot be used in an animation block", command)));
+		}
+		match command {
+			"define" => {
+				self.define_variable(&mut tokens, false)?;
+			},
+			"set" => {
+				self.define_variable(&mut tokens, true)?;
+			},
+			"break" => {
+				return Ok(false);
+			},
+			"size" => {
+				self.builder.size(parse_u32(&mut tokens)?, parse_u32(&mut tokens)?);
+			},
+			"maxdepth" => {
+				self.builder.max_depth(parse_u32(&mut tokens)?);
+			},
+			"output" => {
+				self.builder.output(&tokens.collect::<Vec<&str>>().join(" "));
+			},
+			"camera" => {
+				let pos     = parse_point(&mut tokens)?;
+				let look_at	= parse_point(&mut tokens)?;
+				let up	    = parse_vector(&mut tokens)?;
+				self.builder.camera(pos, look_at, up, parse_float(&mut tokens)?);
+			},
+			"sphere" => {
+				self.builder.add_sphere(parse_point(&mut tokens)?, parse_float(&mut tokens)?).map_err(invalid)?;
+			},
+			"maxverts" => {
+				self.vertices.reserve(parse_u32(&mut tokens)? as usize);
+			},
+			"vertex" => {
+				self.vertices.push(parse_point(&mut tokens)?);
+			},
+			"tri" => {
+				let p1 = self.get_vertex(&mut tokens)?;
+				let p2 = self.get_vertex(&mut tokens)?;
+				let p3 = self.get_vertex(&mut tokens)?;
+				self.builder.add_triangle([p1, p2, p3]).map_err(invalid)?;
+			},
+			"vertextex" => {
+				let point = parse_point(&mut tokens)?;
+				let uv = UV { u:parse_float(&mut tokens)?, v:parse_float(&mut tokens)? };
+				self.tex_vertices.push((point, uv));
+			},
+			"tritex" => {
+				let (p1, uv1) = self.get_tex_vertex(&mut tokens)?;
+				let (p2, uv2) = self.get_tex_vertex(&mut tokens)?;
+				let (p3, uv3) = self.get_tex_vertex(&mut tokens)?;
+				self.builder.add_textured_triangle([p1, p2, p3], [uv1, uv2, uv3]).map_err(invalid)?;
+			},
+			"plane" => {
+				self.builder.add_plane(parse_point(&mut tokens)?, parse_normal(&mut tokens)?).map_err(invalid)?;
+			},
+			"cylinder" => {
+				self.builder.add_cylinder(parse_float(&mut tokens)?, parse_float(&mut tokens)?, parse_float(&mut tokens)?, parse_capped(&mut tokens)?).map_err(invalid)?;
+			},
+			"cone" => {
+				self.builder.add_cone(parse_float(&mut tokens)?, parse_float(&mut tokens)?, parse_capped(&mut tokens)?).map_err(invalid)?;
+			},
+			"disk" => {
+				self.builder.add_disk(parse_point(&mut tokens)?, parse_normal(&mut tokens)?, parse_float(&mut tokens)?).map_err(invalid)?;
+			},
+			"torus" => {
+				self.builder.add_torus(parse_float(&mut tokens)?, parse_float(&mut tokens)?).map_err(invalid)?;
+			},
+			"box" => {
+				self.builder.add_box(parse_point(&mut tokens)?, parse_point(&mut tokens)?).map_err(invalid)?;
+			},
+			"quad" => {
+				self.builder.add_quad(parse_point(&mut tokens)?, parse_vector(&mut tokens)?, parse_vector(&mut tokens)?).map_err(invalid)?;
+			},
+			"sdf" => {
+				let bounding_radius = parse_float(&mut tokens)?;
+				let root = SdfNode::parse(&mut tokens).map_err(invalid)?;
+				self.builder.add_sdf(root, bounding_radius).map_err(invalid)?;
+			},
+			"heightfield" => {
+				let image = load_image(parse_file_name(&mut tokens)?, scene_dir, &mut self.loaded_images)?;
+				self.builder.add_heightfield(&image).map_err(invalid)?;
+			},
+			"csg" => {
+				let operation = match tokens.next() {
+					Some("union") => CsgOperation::Union,
+					Some("intersection") => CsgOperation::Intersection,
+					Some("difference") => CsgOperation::Difference,
+					Some(operation) => { return Err(invalid(format!("unknown CSG operation {}", operation))); }
+					None => { return Err(invalid(String::from("missing CSG operation"))); }
+				};
+				self.builder.begin_csg(operation);
+			},
+			"endcsg" => {
+				if self.builder.end_csg().is_err() {
+					println!("{}: No CSG node to end!", self.location);
+				}
+			},
+			"translate" => {
+				self.builder.translate(parse_float(&mut tokens)?, parse_float(&mut tokens)?, parse_float(&mut tokens)?);
+			},
+			"rotate" => {
+				let axis = parse_vector(&mut tokens)?;
+				self.builder.rotate(axis, parse_float(&mut tokens)?);
+			},
+			"scale" => {
+				self.builder.scale(parse_float(&mut tokens)?, parse_float(&mut tokens)?, parse_float(&mut tokens)?);
+			},
+			"euler" => {
+				// euler rx ry rz [xyz|xzy|yxz|yzx|zxy|zyx], in degrees, rotating around x, then y and then z by default
+				let (x, y, z) = (parse_float(&mut tokens)?, parse_float(&mut tokens)?, parse_float(&mut tokens)?);
+				let order = match tokens.next() {
+					Some(order) => RotationOrder::parse(order).ok_or_else(|| invalid(format!("bad rotation order {}", order)))?,
+					None => RotationOrder::Xyz,
+				};
+				self.builder.rotate_euler(x, y, z, order);
+			},
+			"quaternion" => {
+				// quaternion x y z w
+				self.builder.rotate_quaternion(parse_float(&mut tokens)?, parse_float(&mut tokens)?, parse_float(&mut tokens)?, parse_float(&mut tokens)?).map_err(invalid)?;
+			},
+			"lookat" => {
+				self.builder.look_at(parse_point(&mut tokens)?, parse_point(&mut tokens)?, parse_vector(&mut tokens)?).map_err(invalid)?;
+			},
+			"matrix" => {
+				// row by row, applied like the other transforms
+				let mut matrix = Mat4::id(1.);
+				for row in 0..4 {
+					for col in 0..4 {
+						matrix.data[row][col] = parse_float(&mut tokens)?;
 					}
 				}
+				self.builder.apply_matrix(matrix).map_err(invalid)?;
+			},
+			"pushTransform" => {
+				self.builder.push_transform();
+			},
+			"popTransform" => {
+				if self.builder.pop_transform().is_err() {
+					println!("{}: Stack has no elements! Cannot pop.", self.location);
+				}
+			},
+			"directional" => {
+				self.builder.add_directional_light(parse_vector(&mut tokens)?, parse_color(&mut tokens)?);
+			},
+			"point" => {
+				self.builder.add_point_light(parse_point(&mut tokens)?, parse_color(&mut tokens)?);
+			},
+			"attenuation" => {
+				self.builder.set_attenuation(Attenuation { constant:parse_float(&mut tokens)?, linear:parse_float(&mut tokens)?, quadratic:parse_float(&mut tokens)? });
+			},
+			"ambient" => {
+				self.builder.set_ambient(parse_color(&mut tokens)?);
+			},
+			"background" => {
+				self.builder.set_background_color(parse_color(&mut tokens)?);
+			},
+			"skygradient" => {
+				self.builder.set_sky_gradient(parse_color(&mut tokens)?, parse_color(&mut tokens)?, parse_vector(&mut tokens)?);
+			},
+			"envmap" => {
+				let texture = load_environment_map(parse_file_name(&mut tokens)?, scene_dir)?;
+				let intensity = parse_optional_float(&mut tokens, 1.)?;
+				self.builder.set_environment_map(texture, intensity).map_err(invalid)?;
+			},
+			"frames" => {
+				self.builder.frames(parse_u32(&mut tokens)?, parse_u32(&mut tokens)?);
+			},
+			"shutter" => {
+				// shutter open close [samples], in frames from the time of each frame
+				let (open, close) = (parse_float(&mut tokens)?, parse_float(&mut tokens)?);
+				let samples = parse_optional_u32(&mut tokens, DEFAULT_SHUTTER_SAMPLES)?;
+				self.builder.shutter(open, close, samples);
+			},
+			"camerakey" => {
+				// camerakey <frame> position x y z | lookat x y z | fov degrees
+				let frame = parse_float(&mut tokens)?;
+				match tokens.next() {
+					Some("position") => { self.builder.add_camera_position_key(frame, parse_point(&mut tokens)?); }
+					Some("lookat") => { self.builder.add_camera_look_at_key(frame, parse_point(&mut tokens)?); }
+					Some("fov") => { self.builder.add_camera_fov_key(frame, parse_float(&mut tokens)?); }
+					Some(channel) => { return Err(invalid(format!("unknown camera key {}", channel))); }
+					None => { return Err(invalid(String::from("missing camera key"))); }
+				}
+			},
+			"animation" => {
+				// animation <name> ... endanimation, with key commands in between
+				if self.animation_block.is_some() {
+					return Err(invalid(String::from("animation blocks cannot be nested")));
+				}
+				let name = tokens.next().ok_or_else(|| invalid(String::from("missing animation name")))?;
+				self.animation_block = Some((String::from(name), Default::default()));
+			},
+			"key" => {
+				// key <frame> translate x y z | rotate x y z degrees | quaternion x y z w | scale x y z
+				let frame = parse_float(&mut tokens)?;
+				let track = match self.animation_block {
+					Some((_, ref mut track)) => track,
+					None => { return Err(invalid(String::from("key outside an animation block"))); }
+				};
+				match tokens.next() {
+					Some("translate") => { track.translation.insert(frame, parse_vector(&mut tokens)?); }
+					Some("rotate") => {
+						let axis = parse_vector(&mut tokens)?;
+						track.rotation.insert(frame, Quaternion::from_axis_angle(parse_float(&mut tokens)?, axis));
+					}
+					Some("quaternion") => {
+						let (x, y, z, w) = (parse_float(&mut tokens)?, parse_float(&mut tokens)?, parse_float(&mut tokens)?, parse_float(&mut tokens)?);
+						track.rotation.insert(frame, Quaternion::new(x, y, z, w).ok_or_else(|| invalid(String::from("zero quaternion")))?);
+					}
+					Some("scale") => { track.scale.insert(frame, parse_vector(&mut tokens)?); }
+					Some(kind) => { return Err(invalid(format!("unknown key {}", kind))); }
+					None => { return Err(invalid(String::from("missing key type"))); }
+				}
+			},
+			"endanimation" => {
+				match self.animation_block.take() {
+					Some((name, track)) => { self.builder.define_animation(&name, track); }
+					None => { println!("{}: No animation to end!", self.location); }
+				}
+			},
+			"animate" => {
+				let name = tokens.next().ok_or_else(|| invalid(String::from("missing animation name")))?;
+				self.builder.animate(name).map_err(invalid)?;
+			},
+			"material" => {
+				// material <name> ... endmaterial, starting from the default material
+				if self.material_block.is_some() {
+					return Err(invalid(String::from("material blocks cannot be nested")));
+				}
+				let name = tokens.next().ok_or_else(|| invalid(String::from("missing material name")))?;
+				self.material_block = Some((String::from(name), Default::default()));
+			},
+			"endmaterial" => {
+				match self.material_block.take() {
+					Some((name, material)) => { self.builder.define_material(&name, material); }
+					None => { println!("{}: No material to end!", self.location); }
+				}
+			},
+			"usematerial" => {
+				let name = tokens.next().ok_or_else(|| invalid(String::from("missing material name")))?;
+				self.builder.use_material(name).map_err(invalid)?;
+			},
+			"diffuse" => {
+				self.material_mut().kd = parse_color(&mut tokens)?;
+			},
+			"specular" => {
+				self.material_mut().ks = parse_color(&mut tokens)?;
+			},
+			"shininess" => {
+				self.material_mut().shininess = parse_float(&mut tokens)?;
+			},
+			"emission" => {
+				self.material_mut().ke = parse_color(&mut tokens)?;
+			},
+			"brdf" => {
+				match tokens.next() {
+					Some("phong") => { self.material_mut().brdf = Brdf::Phong; }
+					Some("cooktorrance") => { self.material_mut().brdf = Brdf::CookTorrance; }
+					Some(brdf) => { return Err(invalid(format!("unknown BRDF {}", brdf))); }
+					None => { return Err(invalid(String::from("missing BRDF"))); }
+				}
+			},
+			"roughness" => {
+				self.material_mut().roughness = parse_float(&mut tokens)?;
+			},
+			"metallic" => {
+				self.material_mut().metallic = parse_float(&mut tokens)?;
+			},
+			"bumpstrength" => {
+				self.material_mut().bump_strength = parse_float(&mut tokens)?;
+			},
+			"texture" => {
+				// texture <diffuse|specular|emission|bump|normal> <none|image|checker|gradient|noise|marble|wood ...>
+				let channel = tokens.next().ok_or_else(|| invalid(String::from("missing texture channel")))?;
+				let texture: Option<Rc<dyn Texture>> = match tokens.next() {
+					Some("none") => None,
+					Some("image") => Some(self.parse_image_texture(&mut tokens, scene_dir)?),
+					Some("checker") => Some(Rc::new(CheckerTexture {
+						scale: parse_float(&mut tokens)?,
+						color1: parse_color(&mut tokens)?,
+						color2: parse_color(&mut tokens)?,
+					})),
+					Some("gradient") => Some(Rc::new(GradientTexture {
+						color1: parse_color(&mut tokens)?,
+						color2: parse_color(&mut tokens)?,
+						axis: parse_vector(&mut tokens)?,
+					})),
+					Some("noise") => Some(Rc::new(NoiseTexture {
+						scale: parse_float(&mut tokens)?,
+						octaves: parse_u32(&mut tokens)?,
+						color1: parse_color(&mut tokens)?,
+						color2: parse_color(&mut tokens)?,
+					})),
+					Some("marble") => Some(Rc::new(MarbleTexture {
+						scale: parse_float(&mut tokens)?,
+						turbulence: parse_float(&mut tokens)?,
+						octaves: parse_u32(&mut tokens)?,
+						color1: parse_color(&mut tokens)?,
+						color2: parse_color(&mut tokens)?,
+					})),
+					Some("wood") => Some(Rc::new(WoodTexture {
+						rings: parse_float(&mut tokens)?,
+						turbulence: parse_float(&mut tokens)?,
+						color1: parse_color(&mut tokens)?,
+						color2: parse_color(&mut tokens)?,
+					})),
+					Some(kind) => { return Err(invalid(format!("unknown texture type {}", kind))); }
+					None => { return Err(invalid(String::from("missing texture type"))); }
+				};
+				let material = self.material_mut();
+				match channel {
+					"diffuse" => { material.kd_texture = texture; }
+					"specular" => { material.ks_texture = texture; }
+					"emission" => { material.ke_texture = texture; }
+					"bump" => { material.bump_texture = texture; }
+					"normal" => { material.normal_texture = texture; }
+					_ => { return Err(invalid(format!("unknown texture channel {}", channel))); }
+				}
+			},
+			_ => {
+				println!("{}: Unrecognized command {}!", self.location, command);
 			}
 		}
+		Ok(true)
 	}
 
-	Ok(Scene {
-		graphics: graphics,
-		lights: lights,
-		camera: camera,
-		ambient_color: ambient_color,
-		max_raytrace_depth: max_raytrace_depth,
-		image_width: image_width,
-		image_height: image_height,
-		image_filename: image_filename,
-	})
+	// image <file> [wrap|clamp] [bilinear|nearest]
+	fn parse_image_texture(&mut self, tokens: &mut SplitWhitespace, scene_dir: &Path) -> Result<Rc<dyn Texture>, io::Error> {
+		let filename = parse_file_name(tokens)?;
+		let mut wrap = WrapMode::Wrap;
+		let mut filter = FilterMode::Bilinear;
+		for option in tokens {
+			match option {
+				"wrap" => { wrap = WrapMode::Wrap; }
+				"clamp" => { wrap = WrapMode::Clamp; }
+				"bilinear" => { filter = FilterMode::Bilinear; }
+				"nearest" => { filter = FilterMode::Nearest; }
+				_ => { return Err(invalid(format!("unknown texture option {}", option))); }
+			}
+		}
+
+		let image = load_image(filename, scene_dir, &mut self.loaded_images)?;
+		Ok(Rc::new(ImageTexture::from_image(&image, wrap, filter)))
+	}
 }
 
-fn parse_u32(tokens: &mut SplitWhitespace) -> u32 {
-	tokens.next().unwrap().parse::<u32>().unwrap()
+// images are read once, even if used several times
+pub fn load_image(filename: &str, scene_dir: &Path, loaded_images: &mut HashMap<String, Rc<BmpImage>>) -> Result<Rc<BmpImage>, io::Error> {
+	let path = scene_dir.join(filename).to_string_lossy().into_owned();
+	let image = match loaded_images.get(&path) {
+		Some(image) => image.clone(),
+		None => Rc::new(BmpImage::read_from_file(&path)?),
+	};
+	loaded_images.insert(path, image.clone());
+	Ok(image)
 }
-fn parse_f64(tokens: &mut SplitWhitespace) -> f64 {
-	tokens.next().unwrap().parse::<f64>().unwrap()
+
+// Radiance .hdr file or BMP/PNG image
+pub fn load_environment_map(filename: &str, scene_dir: &Path) -> Result<ImageTexture, io::Error> {
+	let path = scene_dir.join(filename).to_string_lossy().into_owned();
+	if path.ends_with(".hdr") {
+		let (width, height, texels) = read_hdr_file(&path)?;
+		Ok(ImageTexture { width: width, height: height, texels: texels, wrap: WrapMode::Clamp, filter: FilterMode::Bilinear })
+	} else {
+		Ok(ImageTexture::from_image(&BmpImage::read_from_file(&path)?, WrapMode::Clamp, FilterMode::Bilinear))
+	}
+}
+
+fn invalid(msg: String) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// commands that can be used in material blocks
+fn is_material_command(command: &str) -> bool {
+	matches!(command, "endmaterial" | "diffuse" | "specular" | "shininess" | "emission" | "brdf" | "roughness" | "metallic" | "bumpstrength" | "texture")
+}
+
+fn is_variable_char(c: char) -> bool {
+	c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn parse_u32(tokens: &mut SplitWhitespace) -> Result<u32, io::Error> {
+	let token = tokens.next().ok_or_else(|| invalid(String::from("missing number")))?;
+	token.parse::<u32>().map_err(|_| invalid(format!("bad number {}", token)))
+}
+fn parse_float(tokens: &mut SplitWhitespace) -> Result<Float, io::Error> {
+	let token = tokens.next().ok_or_else(|| invalid(String::from("missing number")))?;
+	token.parse::<Float>().map_err(|_| invalid(format!("bad number {}", token)))
+}
+// trailing numbers that may be left out
+fn parse_optional_u32(tokens: &mut SplitWhitespace, default: u32) -> Result<u32, io::Error> {
+	match tokens.next() {
+		Some(token) => token.parse::<u32>().map_err(|_| invalid(format!("bad number {}", token))),
+		None => Ok(default),
+	}
+}
+fn parse_optional_float(tokens: &mut SplitWhitespace, default: Float) -> Result<Float, io::Error> {
+	match tokens.next() {
+		Some(token) => token.parse::<Float>().map_err(|_| invalid(format!("bad number {}", token))),
+		None => Ok(default),
+	}
+}
+// optional trailing "capped" (the default) or "open"
+fn parse_capped(tokens: &mut SplitWhitespace) -> Result<bool, io::Error> {
+	match tokens.next() {
+		Some("open") => Ok(false),
+		Some("capped") | None => Ok(true),
+		Some(option) => Err(invalid(format!("unknown option {}", option))),
+	}
+}
+fn parse_file_name<'a>(tokens: &mut SplitWhitespace<'a>) -> Result<&'a str, io::Error> {
+	tokens.next().ok_or_else(|| invalid(String::from("missing file name")))
+}
+fn parse_color(tokens: &mut SplitWhitespace) -> Result<Color, io::Error> {
+	Ok(Color { r:parse_float(tokens)?, g:parse_float(tokens)?, b:parse_float(tokens)? })
+}
+fn parse_point(tokens: &mut SplitWhitespace) -> Result<Point3, io::Error> {
+	Ok(Point3::new(parse_float(tokens)?, parse_float(tokens)?, parse_float(tokens)?))
+}
+fn parse_vector(tokens: &mut SplitWhitespace) -> Result<Vector3, io::Error> {
+	Ok(Vector3::new(parse_float(tokens)?, parse_float(tokens)?, parse_float(tokens)?))
+}
+fn parse_normal(tokens: &mut SplitWhitespace) -> Result<Normal3, io::Error> {
+	Ok(Normal3::new(parse_float(tokens)?, parse_float(tokens)?, parse_float(tokens)?))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::env;
+	use std::fs;
+	use std::process;
+
+	static CAMERA: &str = "size 64 48\ncamera 0 0 5 0 0 0 0 1 0 45\n";
+
+	// directory of scene files, removed when dropped
+	struct SceneDir {
+		path: PathBuf,
+	}
+
+	impl SceneDir {
+		fn new(name: &str) -> SceneDir {
+			let path = env::temp_dir().join(format!("raytracer-test-{}-{}", process::id(), name));
+			fs::create_dir_all(&path).unwrap();
+			SceneDir { path: path }
+		}
+
+		fn write(&self, filename: &str, contents: &str) -> String {
+			let path = self.path.join(filename);
+			fs::create_dir_all(path.parent().unwrap()).unwrap();
+			fs::write(&path, contents).unwrap();
+			path.to_string_lossy().into_owned()
+		}
+
+		// the error of the scene file, which must not parse
+		fn parse_error(&self, contents: &str) -> String {
+			let path = self.write("error.test", contents);
+			match parse_scene_file(path) {
+				Ok(_) => panic!("the scene parsed: {}", contents),
+				Err(err) => err.to_string(),
+			}
+		}
+	}
+
+	impl Drop for SceneDir {
+		fn drop(&mut self) {
+			let _ = fs::remove_dir_all(&self.path);
+		}
+	}
+
+	fn get_type_names(scene: &Scene) -> Vec<&'static str> {
+		scene.graphics.iter().map(|graphic| graphic.get_type_name()).collect()
+	}
+
+	#[test]
+	fn parses_scene_commands() {
+		let dir = SceneDir::new("commands");
+		let path = dir.write("scene.test", &format!("{}{}", CAMERA, "
+# comment
+maxdepth 3
+output out.bmp
+ambient 0.1 0.1 0.1
+point 1 2 3 1 1 1
+diffuse 0.5 0.25 0
+sphere 0 0 0 1
+vertex 0 0 0
+vertex 1 0 0
+vertex 0 1 0
+tri 0 1 2
+pushTransform
+translate 1 0 0
+box 0 0 0 1 1 1
+popTransform
+cylinder 1 0 1 open
+"));
+		let scene = parse_scene_file(path).unwrap();
+		assert_eq!((scene.image_width, scene.image_height, scene.max_raytrace_depth), (64, 48, 3));
+		assert_eq!(scene.image_filename, "out.bmp");
+		assert_eq!(scene.lights.len(), 1);
+		assert_eq!(get_type_names(&scene), ["sphere", "triangle", "box", "cylinder"]);
+
+		let material = &scene.materials[scene.graphics[0].get_material_index()];
+		assert_eq!((material.kd.r, material.kd.g, material.kd.b), (0.5, 0.25, 0.));
+		assert_eq!(scene.graphics[2].get_transform().data[0][3], 1.);
+		assert_eq!(scene.graphics[3].get_transform().data[0][3], 0.);
+	}
+
+	#[test]
+	fn substitutes_variables_and_includes_files() {
+		let dir = SceneDir::new("include");
+		dir.write("parts/ball.test", "sphere 0 0 0 $radius\n");
+		let path = dir.write("scene.test", &format!("{}{}", CAMERA, "
+define radius 2
+define radius 5
+include parts/ball.test
+set radius 3
+include parts/ball.test
+break
+sphere 0 0 0 bad
+"));
+		let scene = parse_scene_file(path).unwrap();
+		// define keeps the first value, set overrides it, and break ends the scene
+		let radii: Vec<Float> = scene.graphics.iter().map(|graphic| graphic.calc_bounds().unwrap().max.x).collect();
+		assert_eq!(radii, [2., 3.]);
+	}
+
+	#[test]
+	fn parses_csg_nodes() {
+		let dir = SceneDir::new("csg");
+		let path = dir.write("scene.test", &format!("{}{}", CAMERA, "
+csg difference
+box -1 -1 -1 1 1 1
+csg union
+sphere 0 0 0 1.2
+sphere 2 0 0 1
+endcsg
+endcsg
+sphere 5 0 0 1
+"));
+		let scene = parse_scene_file(path).unwrap();
+		assert_eq!(get_type_names(&scene), ["csg", "sphere"]);
+		let bounds = scene.graphics[0].calc_bounds().unwrap();
+		assert!(bounds.min.x < -0.99 && bounds.max.x < 1.01);
+	}
+
+	#[test]
+	fn reports_errors_with_their_location() {
+		let dir = SceneDir::new("errors");
+		let location = |line: u32| format!("{}:{}", dir.path.join("error.test").display(), line);
+		let error = |commands: &str| dir.parse_error(&format!("{}{}", CAMERA, commands));
+
+		assert_eq!(error("sphere 0 0 0 big"), format!("{}: bad number big", location(3)));
+		assert_eq!(error("\nsphere 0 0 0"), format!("{}: missing number", location(4)));
+		assert_eq!(error("quaternion 0 0 0 0"), format!("{}: the quaternion is zero", location(3)));
+		assert_eq!(error("lookat 1 1 1 1 1 1 0 1 0"), format!("{}: the lookat transform has no direction", location(3)));
+		assert_eq!(error("scale 0 1 1\nsphere 0 0 0 1"), format!("{}: the transform is singular", location(4)));
+		assert_eq!(error("matrix 1 0 0 0 0 1 0 0 0 0 1 0 0 0 1 1"), format!("{}: the bottom row of the matrix is not 0 0 0 1", location(3)));
+		assert_eq!(error("brdf blinn"), format!("{}: unknown BRDF blinn", location(3)));
+		assert_eq!(error("texture diffuse bricks"), format!("{}: unknown texture type bricks", location(3)));
+		assert_eq!(error("texture glow none"), format!("{}: unknown texture channel glow", location(3)));
+		assert_eq!(error("texture diffuse image tex.bmp mirror"), format!("{}: unknown texture option mirror", location(3)));
+		assert_eq!(error("sphere 0 0 0 0"), format!("{}: the radius of the sphere is not positive", location(3)));
+		assert_eq!(error("cylinder 1 2 0"), format!("{}: the top of the cylinder is not above its bottom", location(3)));
+		assert_eq!(error("cone 1 0"), format!("{}: the radius or height of the cone is not positive", location(3)));
+		assert_eq!(error("cone 1 1 hollow"), format!("{}: unknown option hollow", location(3)));
+		assert_eq!(error("torus 1 -0.5"), format!("{}: the radii of the torus are not positive", location(3)));
+		assert_eq!(error("box 0 0 0 1 0 1"), format!("{}: the box has no volume", location(3)));
+		assert_eq!(error("quad 0 0 0 1 0 0 2 0 0"), format!("{}: degenerate quad", location(3)));
+		assert_eq!(error("csg xor"), format!("{}: unknown CSG operation xor", location(3)));
+		assert_eq!(error("sdf 1 cone 1"), format!("{}: unknown SDF node cone", location(3)));
+		assert_eq!(error("sdf 1 smoothunion 0 sphere 1 sphere 1"), format!("{}: the blending distance of smoothunion must be positive", location(3)));
+		assert_eq!(error("envmap"), format!("{}: missing file name", location(3)));
+		assert_eq!(error("shutter 0 1 many"), format!("{}: bad number many", location(3)));
+		assert_eq!(error("sphere $r 0 0 1"), format!("{}: undefined variable $r", location(3)));
+		assert_eq!(error("material m\nsphere 0 0 0 1"), format!("{}: sphere cannot be used in a material block", location(4)));
+
+		dir.write("bad.test", "sphere 0 0 0 big\n");
+		assert_eq!(error("include bad.test"), format!("{}:1: bad number big\n\tincluded from {}",
+			dir.path.join("bad.test").display(), location(3)));
+	}
+
+	#[test]
+	fn reports_scene_errors_with_the_file() {
+		let dir = SceneDir::new("scene_errors");
+		let path = dir.path.join("error.test");
+		assert_eq!(dir.parse_error("sphere 0 0 0 1\n"), format!("{}: the scene has no camera", path.display()));
+		assert_eq!(dir.parse_error(&format!("{}shutter 1 0\n", CAMERA)), format!("{}: the shutter closes before it opens", path.display()));
+		assert!(dir.parse_error("include error.test\n").contains(": include cycle\n\tincluded from "));
+	}
 }
@@ -0,0 +1,377 @@
+use std::io;
+use std::io::prelude::*;
+use std::fs::File;
+use std::collections::HashMap;
+
+use float::*;
+use structs::*;
+use vec3::*;
+use mat4::*;
+use scene::*;
+
+/// Writes the scene to a scene file, which parses back into the same scene.
+pub fn write_scene_file(scene: &Scene, filepath: String) -> Result<(), io::Error> {
+	let contents = format_scene(scene).map_err(|msg| io::Error::new(io::ErrorKind::InvalidData, msg))?;
+	let mut f = File::create(&filepath)?;
+	f.write_all(contents.as_bytes())
+}
+
+/// The contents of a scene file describing the scene, or the reason it cannot be written (e.g. a graphic made from an
+/// image, whose file is not kept, or an animation).
+pub fn format_scene(scene: &Scene) -> Result<String, String> {
+	if scene.animation.is_animated() {
+		return Err(String::from("animated scenes cannot be written to scene files"));
+	}
+	let mut writer = SceneWriter::new(&scene.materials);
+	writer.write_line(format!("size {} {}", scene.image_width, scene.image_height));
+	writer.write_line(format!("maxdepth {}", scene.max_raytrace_depth));
+	writer.write_line(format!("output {}", scene.image_filename));
+	if let Some((first, last)) = scene.animation.frames {
+		writer.write_line(format!("frames {} {}", first, last));
+	}
+
+	let camera = &scene.camera;
+	let up = to_exact_direction(camera.up);
+	writer.write_line(format!("camera {} {} {} {} {} {} {} {} {} {}",
+		camera.pos.x, camera.pos.y, camera.pos.z,
+		camera.look_at.x, camera.look_at.y, camera.look_at.z,
+		up.x, up.y, up.z,
+		to_exact_degrees(camera.fov_y)));
+	if camera.shutter != (0., 0.) {
+		writer.write_line(format!("shutter {} {} {}", camera.shutter.0, camera.shutter.1, camera.shutter_samples));
+	}
+
+	writer.write_line(format!("ambient {}", format_color(scene.ambient_color)));
+	if let Some(ref background) = scene.background {
+		writer.write_line(background.get_scene_command()?);
+	}
+	for light in &scene.lights {
+		light.write_scene_commands(&mut writer)?;
+	}
+	for graphic in &scene.graphics {
+		graphic.write_scene_commands(&mut writer)?;
+	}
+	writer.set_transform(&Mat4::id(1.));
+	Ok(writer.contents)
+}
+
+pub fn format_color(color: Color) -> String {
+	format!("{} {} {}", color.r, color.g, color.b)
+}
+
+// the camera keeps its field of view in radians, which may not convert back exactly to the degrees it was created with
+fn to_exact_degrees(radians: Float) -> Float {
+	let degrees = radians.to_degrees();
+	let (mut below, mut above) = (degrees, degrees);
+	for _ in 0..8 {
+		for candidate in [below, above] {
+			if candidate.to_radians() == radians {
+				return candidate;
+			}
+		}
+		below = below.next_down();
+		above = above.next_up();
+	}
+	degrees
+}
+
+// unit vectors are normalized again when read, which may change them slightly, so a vector that normalizes to exactly
+// the same one is written instead, when one is found: for each length tried, the components divide back to those of the
+// direction, but the length they give is not always the one tried
+pub fn to_exact_direction(direction: Vector3) -> Vector3 {
+	let components = |c: Float, length: Float| -> Vec<Float> {
+		let scaled = c * length;
+		[scaled, scaled.next_down(), scaled.next_up()].iter().cloned().filter(|&x| x / length == c).collect()
+	};
+	for step in 0..1024 {
+		let length = 1. + step as Float / 1024.;
+		for &x in &components(direction.x, length) {
+			for &y in &components(direction.y, length) {
+				for &z in &components(direction.z, length) {
+					let candidate = Vector3::new(x, y, z);
+					let normalized = candidate.normalize();
+					if normalized.x == direction.x && normalized.y == direction.y && normalized.z == direction.z {
+						return candidate;
+					}
+				}
+			}
+		}
+	}
+	direction
+}
+
+// ---------- SceneWriter ----------
+
+// Scene file being written, keeping the material, transform and point light attenuation of the commands written so far,
+// so that the commands changing them are only written when an object needs it. Each material of the scene's material
+// table is written as a named material the first time it is used, so objects that share it still do once read back.
+// Transforms are written as raw matrices, and all numbers with as many digits as they need to be read back exactly.
+pub struct SceneWriter<'a> {
+	contents: String,
+	materials: &'a [Material],
+	material_names: HashMap<usize, String>,	// of the materials written so far, by their index in the table
+	material_index: Option<usize>,	// of the material in use
+	transform: Mat4,	// pushed on the stack, unless it is the identity
+	attenuation: Attenuation,
+	vertices: HashMap<String, usize>,	// index of each vertex command written
+	tex_vertices: HashMap<String, usize>,
+}
+
+impl<'a> SceneWriter<'a> {
+	fn new(materials: &'a [Material]) -> SceneWriter<'a> {
+		SceneWriter {
+			contents: String::new(),
+			materials: materials,
+			material_names: HashMap::new(),
+			material_index: None,
+			transform: Mat4::id(1.),
+			attenuation: Default::default(),
+			vertices: HashMap::new(),
+			tex_vertices: HashMap::new(),
+		}
+	}
+
+	pub fn write_line(&mut self, line: String) {
+		self.contents.push_str(&line);
+		self.contents.push('\n');
+	}
+
+	// writes the command creating an object, after the material and transform it needs
+	pub fn write_object(&mut self, material_index: usize, transform: &Mat4, command: String) -> Result<(), String> {
+		self.set_material(material_index)?;
+		self.set_transform(transform);
+		self.write_line(command);
+		Ok(())
+	}
+	// index of the vertex, written the first time it is used
+	pub fn write_vertex(&mut self, point: Point3) -> usize {
+		let line = format!("vertex {} {} {}", point.x, point.y, point.z);
+		let index = self.vertices.len();
+		if let Some(&index) = self.vertices.get(&line) {
+			return index;
+		}
+		self.vertices.insert(line.clone(), index);
+		self.write_line(line);
+		index
+	}
+
+	pub fn write_tex_vertex(&mut self, point: Point3, uv: UV) -> usize {
+		let line = format!("vertextex {} {} {} {} {}", point.x, point.y, point.z, uv.u, uv.v);
+		let index = self.tex_vertices.len();
+		if let Some(&index) = self.tex_vertices.get(&line) {
+			return index;
+		}
+		self.tex_vertices.insert(line.clone(), index);
+		self.write_line(line);
+		index
+	}
+
+	pub fn set_transform(&mut self, transform: &Mat4) {
+		if transform.data == self.transform.data {
+			return;
+		}
+		let identity = Mat4::id(1.);
+		if self.transform.data != identity.data {
+			self.write_line(String::from("popTransform"));
+		}
+		if transform.data != identity.data {
+			self.write_line(String::from("pushTransform"));
+			let values: Vec<String> = transform.data.iter().flat_map(|row| row.iter()).map(|value| value.to_string()).collect();
+			self.write_line(format!("matrix {}", values.join(" ")));
+		}
+		self.transform = *transform;
+	}
+
+	pub fn set_attenuation(&mut self, attenuation: &Attenuation) {
+		let current = &self.attenuation;
+		if attenuation.constant != current.constant || attenuation.linear != current.linear || attenuation.quadratic != current.quadratic {
+			self.write_line(format!("attenuation {} {} {}", attenuation.constant, attenuation.linear, attenuation.quadratic));
+			self.attenuation = attenuation.clone();
+		}
+	}
+
+	pub fn set_material(&mut self, material_index: usize) -> Result<(), String> {
+		if self.material_index == Some(material_index) {
+			return Ok(());
+		}
+		if !self.material_names.contains_key(&material_index) {
+			let name = format!("material{}", self.material_names.len() + 1);
+			self.write_line(format!("material {}", name));
+			for line in get_material_commands(&self.materials[material_index])? {
+				self.write_line(line);
+			}
+			self.write_line(String::from("endmaterial"));
+			self.material_names.insert(material_index, name);
+		}
+		let line = format!("usematerial {}", self.material_names[&material_index]);
+		self.write_line(line);
+		self.material_index = Some(material_index);
+		Ok(())
+	}
+}
+
+// commands setting the fields of a material block that differ from the default material
+fn get_material_commands(material: &Material) -> Result<Vec<String>, String> {
+	let mut lines: Vec<String> = Vec::new();
+	let default: Material = Default::default();
+	let same_color = |a: Color, b: Color| a.r == b.r && a.g == b.g && a.b == b.b;
+	if !same_color(material.kd, default.kd) {
+		lines.push(format!("diffuse {}", format_color(material.kd)));
+	}
+	if !same_color(material.ks, default.ks) {
+		lines.push(format!("specular {}", format_color(material.ks)));
+	}
+	if !same_color(material.ke, default.ke) {
+		lines.push(format!("emission {}", format_color(material.ke)));
+	}
+	if material.shininess != default.shininess {
+		lines.push(format!("shininess {}", material.shininess));
+	}
+	if material.brdf != default.brdf {
+		lines.push(String::from(match material.brdf { Brdf::Phong => "brdf phong", Brdf::CookTorrance => "brdf cooktorrance" }));
+	}
+	if material.roughness != default.roughness {
+		lines.push(format!("roughness {}", material.roughness));
+	}
+	if material.metallic != default.metallic {
+		lines.push(format!("metallic {}", material.metallic));
+	}
+	if material.bump_strength != default.bump_strength {
+		lines.push(format!("bumpstrength {}", material.bump_strength));
+	}
+
+	let channels = [
+		("diffuse", &material.kd_texture),
+		("specular", &material.ks_texture),
+		("emission", &material.ke_texture),
+		("bump", &material.bump_texture),
+		("normal", &material.normal_texture),
+	];
+	for (channel, texture) in channels {
+		if let Some(texture) = texture {
+			lines.push(format!("texture {} {}", channel, texture.get_scene_description()?));
+		}
+	}
+	Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::env;
+	use std::fs;
+	use std::process;
+	use scene_parser::*;
+
+	static SCENE: &str = "
+size 64 48
+maxdepth 4
+output out.bmp
+camera 1 2 5 0 0.5 0 0.1 1 0 40
+shutter 0 0.5 4
+ambient 0.1 0.1 0.1
+skygradient 1 1 1 0.2 0.4 0.9 0 1 0
+directional 1 -1 -1 0.5 0.5 0.5
+attenuation 1 0.1 0.01
+point 1 4 3 1 1 1
+
+material red
+diffuse 0.8 0.1 0.1
+specular 0.2 0.2 0.2
+shininess 30
+texture diffuse checker 4 1 0 0 0 0 1
+endmaterial
+material metal
+brdf cooktorrance
+roughness 0.3
+metallic 1
+endmaterial
+
+usematerial red
+sphere 0 0 0 1
+pushTransform
+translate 1 0 0
+rotate 0 1 0 30
+scale 1 2 1
+box -1 -1 -1 1 1 1
+cylinder 0.5 0 1 open
+popTransform
+usematerial metal
+cone 0.5 1
+torus 1 0.25
+disk 0 0 0 0 1 0 2
+quad 0 0 0 1 0 0 0 0 1
+plane 0 -1 0 0 1 0
+vertex 0 0 0
+vertex 1 0 0
+vertex 0 1 0
+tri 0 1 2
+vertextex 0 0 1 0.5 0
+vertextex 1 0 1 1 0.5
+vertextex 0 1 1 0 1
+tritex 0 1 2
+usematerial red
+sdf 2 smoothunion 0.2 sphere 1 translate 1 0 0 box 0.5 0.5 0.5
+csg difference
+box -1 -1 -1 1 1 1
+sphere 0 0 0 1.2
+endcsg
+";
+
+	fn parse_scene(name: &str, contents: &str) -> Scene {
+		let path = env::temp_dir().join(format!("raytracer-test-{}-{}.test", process::id(), name)).to_string_lossy().into_owned();
+		fs::write(&path, contents).unwrap();
+		let scene = parse_scene_file(path.clone());
+		fs::remove_file(&path).unwrap();
+		scene.unwrap()
+	}
+
+	#[test]
+	fn writes_scenes_that_parse_back_the_same() {
+		let scene = parse_scene("written", SCENE);
+		let contents = format_scene(&scene).unwrap();
+		let read = parse_scene("read", &contents);
+		// written again, the scene gives the same file
+		assert_eq!(format_scene(&read).unwrap(), contents);
+
+		assert_eq!((read.image_width, read.image_height, read.max_raytrace_depth), (64, 48, 4));
+		assert_eq!(read.camera.shutter, (0., 0.5));
+		assert_eq!(read.camera.shutter_samples, 4);
+		assert_eq!(read.lights.len(), 2);
+		let type_names = |scene: &Scene| scene.graphics.iter().map(|graphic| graphic.get_type_name()).collect::<Vec<&str>>();
+		assert_eq!(type_names(&read), type_names(&scene));
+		for (graphic, read_graphic) in scene.graphics.iter().zip(&read.graphics) {
+			assert_eq!(graphic.get_transform().data, read_graphic.get_transform().data);
+		}
+		// shared materials stay shared
+		assert_eq!(read.materials.len(), scene.materials.len());
+	}
+
+	#[test]
+	fn writes_vertices_and_materials_once() {
+		let scene = parse_scene("shared", SCENE);
+		let contents = format_scene(&scene).unwrap();
+		let count = |prefix: &str| contents.lines().filter(|line| line.starts_with(prefix)).count();
+		assert_eq!(count("material "), 2);
+		assert_eq!(count("vertex "), 3);
+		assert_eq!(count("vertextex "), 3);
+	}
+
+	#[test]
+	fn refuses_animated_scenes() {
+		let scene = parse_scene("animated", "size 8 8\ncamera 0 0 5 0 0 0 0 1 0 45\nframes 1 2\ncamerakey 2 position 0 0 6\n");
+		assert!(format_scene(&scene).is_err());
+	}
+
+	#[test]
+	fn finds_exact_degrees_and_directions() {
+		for &degrees in &[30., 45., 57.3, 90.] {
+			let radians = (degrees as Float).to_radians();
+			assert_eq!(to_exact_degrees(radians).to_radians(), radians);
+		}
+		let direction = Vector3::new(0.1, 1., 0.3).normalize();
+		let written = to_exact_direction(direction);
+		let normalized = written.normalize();
+		assert_eq!((normalized.x, normalized.y, normalized.z), (direction.x, direction.y, direction.z));
+	}
+}
@@ -0,0 +1,313 @@
+use float::*;
+use float::consts::PI;
+use std::str::SplitWhitespace;
+use std::fmt;
+use structs::*;
+use vec3::*;
+use mat4::*;
+use graphics::*;
+use bvh::*;
+use scene_writer::*;
+
+static MAX_MARCH_STEPS: u32 = 512;
+static SURFACE_EPS: Float = 1e-5;
+
+// ---------- SdfNode ----------
+
+// Signed distance function, built from primitives and operators.
+#[derive(Debug)]
+pub enum SdfNode {
+	Sphere(Float),	// radius
+	Box(Vector3),	// half sizes
+	Torus(Float, Float),	// major and minor radius, around the y axis
+	Cylinder(Float, Float),	// radius and half height, around the y axis
+	Union(Box<SdfNode>, Box<SdfNode>),
+	Intersection(Box<SdfNode>, Box<SdfNode>),
+	Difference(Box<SdfNode>, Box<SdfNode>),
+	SmoothUnion(Float, Box<SdfNode>, Box<SdfNode>),	// blending distance
+	Round(Float, Box<SdfNode>),	// grows the shape, rounding its edges
+	Translate(Vector3, Box<SdfNode>),
+	Scale(Float, Box<SdfNode>),	// uniform
+	Repeat(Vector3, Box<SdfNode>),	// period along each axis, 0 meaning no repetition
+	Twist(Float, Box<SdfNode>),	// radians per unit along the y axis
+}
+
+impl SdfNode {
+	pub fn calc_distance(&self, p: Point3) -> Float {
+		match *self {
+			SdfNode::Sphere(radius) => p.to_vector().length() - radius,
+			SdfNode::Box(half_size) => {
+				let q = Vector3::new(p.x.abs() - half_size.x, p.y.abs() - half_size.y, p.z.abs() - half_size.z);
+				let outside = Vector3::new(q.x.max(0.), q.y.max(0.), q.z.max(0.)).length();
+				let inside = q.x.max(q.y).max(q.z).min(0.);
+				outside + inside
+			}
+			SdfNode::Torus(major_radius, minor_radius) => {
+				let to_tube = (p.x * p.x + p.z * p.z).sqrt() - major_radius;
+				(to_tube * to_tube + p.y * p.y).sqrt() - minor_radius
+			}
+			SdfNode::Cylinder(radius, half_height) => {
+				let dx = (p.x * p.x + p.z * p.z).sqrt() - radius;
+				let dy = p.y.abs() - half_height;
+				dx.max(dy).min(0.) + (dx.max(0.) * dx.max(0.) + dy.max(0.) * dy.max(0.)).sqrt()
+			}
+			SdfNode::Union(ref a, ref b) => a.calc_distance(p).min(b.calc_distance(p)),
+			SdfNode::Intersection(ref a, ref b) => a.calc_distance(p).max(b.calc_distance(p)),
+			SdfNode::Difference(ref a, ref b) => a.calc_distance(p).max(-b.calc_distance(p)),
+			SdfNode::SmoothUnion(k, ref a, ref b) => {
+				let (da, db) = (a.calc_distance(p), b.calc_distance(p));
+				let h = (0.5 + 0.5 * (db - da) / k).clamp(0., 1.);
+				(1. - h) * db + h * da - k * h * (1. - h)
+			}
+			SdfNode::Round(radius, ref a) => a.calc_distance(p) - radius,
+			SdfNode::Translate(offset, ref a) => a.calc_distance(p - offset),
+			SdfNode::Scale(factor, ref a) => factor * a.calc_distance(p.scale(1. / factor)),
+			SdfNode::Repeat(period, ref a) => {
+				let repeat = |x: Float, period: Float| if period > 0. { x - period * (x / period).round() } else { x };
+				a.calc_distance(Point3::new(repeat(p.x, period.x), repeat(p.y, period.y), repeat(p.z, period.z)))
+			}
+			SdfNode::Twist(rate, ref a) => {
+				let (sin, cos) = (rate * p.y).sin_cos();
+				a.calc_distance(Point3::new(cos * p.x - sin * p.z, p.y, sin * p.x + cos * p.z))
+			}
+		}
+	}
+
+	// upper bound of how much faster than the distance to the surface the function can change within the radius,
+	// so that marching steps can be shortened accordingly
+	fn calc_lipschitz_bound(&self, radius: Float) -> Float {
+		match *self {
+			SdfNode::Sphere(_) | SdfNode::Box(_) | SdfNode::Torus(_, _) | SdfNode::Cylinder(_, _) => 1.,
+			SdfNode::Union(ref a, ref b) | SdfNode::Intersection(ref a, ref b) | SdfNode::Difference(ref a, ref b) | SdfNode::SmoothUnion(_, ref a, ref b) => {
+				a.calc_lipschitz_bound(radius).max(b.calc_lipschitz_bound(radius))
+			}
+			SdfNode::Round(_, ref a) | SdfNode::Translate(_, ref a) | SdfNode::Scale(_, ref a) | SdfNode::Repeat(_, ref a) => a.calc_lipschitz_bound(radius),
+			SdfNode::Twist(rate, ref a) => (1. + (rate * radius) * (rate * radius)).sqrt() * a.calc_lipschitz_bound(radius),
+		}
+	}
+
+	/// Parses a node written in prefix notation, e.g. "smoothunion 0.2 sphere 1 translate 1 0 0 box 0.5 0.5 0.5".
+	pub fn parse(tokens: &mut SplitWhitespace) -> Result<SdfNode, String> {
+		let name = tokens.next().ok_or("missing SDF node")?;
+		let mut number = || -> Result<Float, String> {
+			let token = tokens.next().ok_or(format!("missing parameter of SDF node {}", name))?;
+			token.parse::<Float>().map_err(|_| format!("bad parameter {} of SDF node {}", token, name))
+		};
+
+		let node = match name {
+			"sphere" => SdfNode::Sphere(number()?),
+			"box" => SdfNode::Box(Vector3::new(number()?, number()?, number()?)),
+			"torus" => SdfNode::Torus(number()?, number()?),
+			"cylinder" => SdfNode::Cylinder(number()?, number()?),
+			"union" => SdfNode::Union(Box::new(SdfNode::parse(tokens)?), Box::new(SdfNode::parse(tokens)?)),
+			"intersection" => SdfNode::Intersection(Box::new(SdfNode::parse(tokens)?), Box::new(SdfNode::parse(tokens)?)),
+			"difference" => SdfNode::Difference(Box::new(SdfNode::parse(tokens)?), Box::new(SdfNode::parse(tokens)?)),
+			"smoothunion" => {
+				let k = number()?;
+				if k <= 0. {
+					return Err(String::from("the blending distance of smoothunion must be positive"));
+				}
+				SdfNode::SmoothUnion(k, Box::new(SdfNode::parse(tokens)?), Box::new(SdfNode::parse(tokens)?))
+			}
+			"round" => {
+				let radius = number()?;
+				SdfNode::Round(radius, Box::new(SdfNode::parse(tokens)?))
+			}
+			"translate" => {
+				let offset = Vector3::new(number()?, number()?, number()?);
+				SdfNode::Translate(offset, Box::new(SdfNode::parse(tokens)?))
+			}
+			"scale" => {
+				let factor = number()?;
+				if factor <= 0. {
+					return Err(String::from("the factor of scale must be positive"));
+				}
+				SdfNode::Scale(factor, Box::new(SdfNode::parse(tokens)?))
+			}
+			"repeat" => {
+				let period = Vector3::new(number()?, number()?, number()?);
+				SdfNode::Repeat(period, Box::new(SdfNode::parse(tokens)?))
+			}
+			"twist" => {
+				let rate = number()?;
+				SdfNode::Twist(rate, Box::new(SdfNode::parse(tokens)?))
+			}
+			_ => { return Err(format!("unknown SDF node {}", name)); }
+		};
+		Ok(node)
+	}
+}
+
+// prefix notation, as read by SdfNode::parse
+impl fmt::Display for SdfNode {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			SdfNode::Sphere(radius) => write!(f, "sphere {}", radius),
+			SdfNode::Box(half_size) => write!(f, "box {} {} {}", half_size.x, half_size.y, half_size.z),
+			SdfNode::Torus(major_radius, minor_radius) => write!(f, "torus {} {}", major_radius, minor_radius),
+			SdfNode::Cylinder(radius, half_height) => write!(f, "cylinder {} {}", radius, half_height),
+			SdfNode::Union(ref a, ref b) => write!(f, "union {} {}", a, b),
+			SdfNode::Intersection(ref a, ref b) => write!(f, "intersection {} {}", a, b),
+			SdfNode::Difference(ref a, ref b) => write!(f, "difference {} {}", a, b),
+			SdfNode::SmoothUnion(k, ref a, ref b) => write!(f, "smoothunion {} {} {}", k, a, b),
+			SdfNode::Round(radius, ref a) => write!(f, "round {} {}", radius, a),
+			SdfNode::Translate(offset, ref a) => write!(f, "translate {} {} {} {}", offset.x, offset.y, offset.z, a),
+			SdfNode::Scale(factor, ref a) => write!(f, "scale {} {}", factor, a),
+			SdfNode::Repeat(period, ref a) => write!(f, "repeat {} {} {} {}", period.x, period.y, period.z, a),
+			SdfNode::Twist(rate, ref a) => write!(f, "twist {} {}", rate, a),
+		}
+	}
+}
+
+// ---------- Sdf ----------
+
+// Implicit surface traced by sphere tracing its signed distance function, limited to a bounding sphere at the origin.
+#[derive(Debug)]
+pub struct Sdf {
+	pub root: SdfNode,
+	pub bounding_radius: Float,
+	pub step_scale: Float,	// marching steps are shortened for distorting operators such as twist
+	pub material_index: usize,	// in the material table of the scene
+	pub transform: Mat4,
+	pub inverse_transform: Mat4,
+}
+
+impl Sdf {
+	pub fn new(root: SdfNode, bounding_radius: Float, material_index: usize, transform: Mat4, inverse_transform: Mat4) -> Sdf {
+		Sdf {
+			step_scale: 1. / root.calc_lipschitz_bound(bounding_radius),
+			root: root,
+			bounding_radius: bounding_radius,
+			material_index: material_index,
+			transform: transform,
+			inverse_transform: inverse_transform,
+		}
+	}
+
+	// range of the ray inside the bounding sphere
+	fn calc_bounding_range(&self, ray: &Ray) -> Option<(Float, Float)> {
+		let o = ray.origin.to_vector();
+		let b = -o.dot(ray.direction);
+		let det = b * b - o.length_sq() + self.bounding_radius * self.bounding_radius;
+		if det < 0. {
+			return None;
+		}
+		let squared_det = det.sqrt();
+		Some((b - squared_det, b + squared_det))
+	}
+
+	fn calc_tolerance(t: Float) -> Float {
+		SURFACE_EPS * t.abs().max(1.)
+	}
+
+	// marches from t_start towards t_end, returning where the surface is crossed
+	fn march(&self, ray: &Ray, t_start: Float, t_end: Float) -> Option<Float> {
+		let mut t = t_start;
+		for _ in 0..MAX_MARCH_STEPS {
+			if t > t_end {
+				return None;
+			}
+			let distance = self.root.calc_distance(ray.origin + t * ray.direction).abs();
+			if distance < Sdf::calc_tolerance(t) {
+				return Some(t);
+			}
+			t += self.step_scale * distance;
+		}
+		None
+	}
+
+	// moves past the surface, if t is on it, so that marching again finds the next crossing; hits are only accurate
+	// to the tolerance, so this also applies to rays leaving the surface
+	fn skip_surface(&self, ray: &Ray, t: Float) -> Float {
+		let mut t = t;
+		for _ in 0..MAX_MARCH_STEPS {
+			if self.root.calc_distance(ray.origin + t * ray.direction).abs() >= Sdf::calc_tolerance(t) {
+				break;
+			}
+			t += 2. * Sdf::calc_tolerance(t);
+		}
+		t
+	}
+
+	// all the surface crossings inside the bounding sphere, sorted
+	fn calc_crossings(&self, ray: &Ray) -> Vec<Float> {
+		let mut crossings = Vec::new();
+		if let Some((t_enter, t_exit)) = self.calc_bounding_range(ray) {
+			let mut t = t_enter;
+			while let Some(t_hit) = self.march(ray, t, t_exit) {
+				crossings.push(t_hit);
+				t = self.skip_surface(ray, t_hit);
+			}
+		}
+		crossings
+	}
+}
+
+impl Graphic for Sdf {
+	fn get_material_index(&self) -> usize {
+		self.material_index
+	}
+	fn get_type_name(&self) -> &'static str {
+		"sdf"
+	}
+	fn get_transform(&self) -> &Mat4 {
+		&self.transform
+	}
+	fn get_inverse_transform(&self) -> &Mat4 {
+		&self.inverse_transform
+	}
+	fn set_transform(&mut self, transform: Mat4, inverse_transform: Mat4) {
+		self.transform = transform;
+		self.inverse_transform = inverse_transform;
+	}
+
+	fn test_intersection(&self, ray: &Ray) -> Option<Point3> {
+		let (t_enter, t_exit) = self.calc_bounding_range(ray)?;
+		let t_start = self.skip_surface(ray, t_enter.max(ray.t_min));
+		self.march(ray, t_start, t_exit.min(ray.t_max))
+			.filter(|&t| ray.contains(t))
+			.map(|t| ray.origin + t * ray.direction)
+	}
+
+	fn test_closest_hit(&self, ray: &Ray) -> Option<Hit<'_>> {
+		self.test_intersection(ray).map(|point| Hit::from_point(ray, point, self))
+	}
+
+	fn test_intersection_intervals(&self, ray: &Ray) -> Vec<Interval<'_>> {
+		pair_crossings(ray, &self.calc_crossings(ray), self)
+	}
+
+	fn calc_normal_at(&self, point: Point3) -> Normal3 {
+		// gradient of the distance, sampled at the vertices of a tetrahedron around the point
+		let h = 1e-4 * self.bounding_radius.max(1.);
+		let mut normal = Vector3::new(0., 0., 0.);
+		for &(x, y, z) in &[(1., -1., -1.), (-1., -1., 1.), (-1., 1., -1.), (1., 1., 1.)] {
+			let k = Vector3::new(x, y, z);
+			normal = normal + self.root.calc_distance(point + h * k) * k;
+		}
+		normal.normalize().to_normal()
+	}
+
+	fn calc_uv_at(&self, point: Point3) -> UV {
+		// spherical mapping around the origin
+		let d = point.to_vector().normalize();
+		UV {
+			u: 0.5 + Float::atan2(d.x, d.z) / (2. * PI),
+			v: 0.5 + Float::asin(d.y.clamp(-1., 1.)) / PI,
+		}
+	}
+
+	fn calc_tangents_at(&self, point: Point3) -> (Vector3, Vector3) {
+		// an arbitrary frame around the normal; solid textures are a better fit than UVs for these surfaces
+		calc_plane_axes(self.calc_normal_at(point))
+	}
+
+	fn calc_bounds(&self) -> Option<Bounds> {
+		let radius = Vector3::new(self.bounding_radius, self.bounding_radius, self.bounding_radius);
+		Some(Bounds::new(Point3::origin() - radius, Point3::origin() + radius))
+	}
+
+	fn write_scene_commands(&self, writer: &mut SceneWriter) -> Result<(), String> {
+		writer.write_object(self.material_index, &self.transform, format!("sdf {} {}", self.bounding_radius, self.root))
+	}
+}
@@ -0,0 +1,168 @@
+use std::f64;
+use std::f64::consts::PI;
+
+// Polynomial root finders used by the intersection routines. All of them return the real roots in increasing order.
+// They always work in f64, even with the "f32" feature, as the quartic's roots lose too much precision otherwise.
+
+static ZERO_EPS: f64 = 1e-12;
+
+/// Roots of a*x^2 + b*x + c, using the numerically stable form of the quadratic formula.
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+	if a.abs() < ZERO_EPS {
+		if b.abs() < ZERO_EPS {
+			return Vec::new();
+		}
+		return vec![-c / b];
+	}
+
+	let discriminant = b * b - 4. * a * c;
+	if discriminant < 0. {
+		return Vec::new();
+	}
+
+	let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+	let (r0, r1) = if q == 0. { (0., 0.) } else { (q / a, c / q) };
+	if r0 < r1 { vec![r0, r1] } else { vec![r1, r0] }
+}
+
+/// Roots of a*x^3 + b*x^2 + c*x + d (Cardano's method, with the trigonometric form for three real roots).
+pub fn solve_cubic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+	if a.abs() < ZERO_EPS {
+		return solve_quadratic(b, c, d);
+	}
+
+	// normal form x^3 + A*x^2 + B*x + C, then substituting x = y - A/3 gives y^3 + 3*p*y + 2*q
+	let (a, b, c) = (b / a, c / a, d / a);
+	let sq_a = a * a;
+	let p = (1. / 3.) * (-(1. / 3.) * sq_a + b);
+	let q = 0.5 * ((2. / 27.) * a * sq_a - (1. / 3.) * a * b + c);
+	let cb_p = p * p * p;
+	let discriminant = q * q + cb_p;
+	let shift = a / 3.;
+
+	let mut roots = if discriminant.abs() < ZERO_EPS {
+		if q.abs() < ZERO_EPS {
+			vec![0.]
+		} else {
+			let u = (-q).cbrt();
+			vec![2. * u, -u]
+		}
+	} else if discriminant < 0. {
+		let phi = (1. / 3.) * (-q / (-cb_p).sqrt()).clamp(-1., 1.).acos();
+		let t = 2. * (-p).sqrt();
+		vec![t * phi.cos(), -t * (phi + PI / 3.).cos(), -t * (phi - PI / 3.).cos()]
+	} else {
+		let sqrt_d = discriminant.sqrt();
+		vec![(sqrt_d - q).cbrt() - (sqrt_d + q).cbrt()]
+	};
+
+	for root in &mut roots {
+		*root -= shift;
+	}
+	roots.sort_by(|a, b| a.total_cmp(b));
+	roots
+}
+
+/// Roots of a*x^4 + b*x^3 + c*x^2 + d*x + e (Ferrari's method through the resolvent cubic),
+/// each refined with a few Newton iterations since the closed form loses precision.
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64, e: f64) -> Vec<f64> {
+	if a.abs() < ZERO_EPS {
+		return solve_cubic(b, c, d, e);
+	}
+
+	// normal form x^4 + A*x^3 + B*x^2 + C*x + D, then substituting x = y - A/4 gives y^4 + p*y^2 + q*y + r
+	let (a, b, c, d) = (b / a, c / a, d / a, e / a);
+	let sq_a = a * a;
+	let p = -(3. / 8.) * sq_a + b;
+	let q = (1. / 8.) * sq_a * a - 0.5 * a * b + c;
+	let r = -(3. / 256.) * sq_a * sq_a + (1. / 16.) * sq_a * b - 0.25 * a * c + d;
+
+	let mut roots = if r.abs() < ZERO_EPS {
+		// y * (y^3 + p*y + q) = 0
+		let mut roots = solve_cubic(1., 0., p, q);
+		roots.push(0.);
+		roots
+	} else {
+		// one real root of the resolvent cubic splits the quartic into two quadratics
+		let z = *solve_cubic(1., -0.5 * p, -r, 0.5 * r * p - 0.125 * q * q).last().unwrap();
+
+		let u = z * z - r;
+		let v = 2. * z - p;
+		let u = if u.abs() < ZERO_EPS { 0. } else if u > 0. { u.sqrt() } else { return Vec::new(); };
+		let v = if v.abs() < ZERO_EPS { 0. } else if v > 0. { v.sqrt() } else { return Vec::new(); };
+
+		let mut roots = solve_quadratic(1., if q < 0. { -v } else { v }, z - u);
+		roots.extend(solve_quadratic(1., if q < 0. { v } else { -v }, z + u));
+		roots
+	};
+
+	let shift = 0.25 * a;
+	for root in &mut roots {
+		let mut x = *root - shift;
+		for _ in 0..3 {
+			let f = (((x + a) * x + b) * x + c) * x + d;
+			let df = ((4. * x + 3. * a) * x + 2. * b) * x + c;
+			if df.abs() < ZERO_EPS {
+				break;
+			}
+			x -= f / df;
+		}
+		*root = x;
+	}
+	roots.sort_by(|a, b| a.total_cmp(b));
+	roots
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn assert_roots(roots: Vec<f64>, expected: &[f64]) {
+		assert_eq!(roots.len(), expected.len(), "roots {:?}, expected {:?}", roots, expected);
+		for (root, expected) in roots.iter().zip(expected) {
+			assert!((root - expected).abs() < 1e-9, "roots {:?}, expected {:?}", roots, expected);
+		}
+	}
+
+	#[test]
+	fn solves_quadratics() {
+		assert_roots(solve_quadratic(1., -3., 2.), &[1., 2.]);
+		assert_roots(solve_quadratic(2., 0., -8.), &[-2., 2.]);
+		assert_roots(solve_quadratic(1., 0., 1.), &[]);
+		// linear
+		assert_roots(solve_quadratic(0., 2., -1.), &[0.5]);
+		// the stable form keeps the small root of a large b
+		assert_roots(solve_quadratic(1., -1e8, 1.), &[1e-8, 1e8]);
+	}
+
+	#[test]
+	fn solves_cubics() {
+		// (x + 1)(x - 2)(x - 3)
+		assert_roots(solve_cubic(1., -4., 1., 6.), &[-1., 2., 3.]);
+		// (x - 1)(x^2 + 1)
+		assert_roots(solve_cubic(2., -2., 2., -2.), &[1.]);
+		// (x - 1)^2 (x + 2)
+		assert_roots(solve_cubic(1., 0., -3., 2.), &[-2., 1.]);
+	}
+
+	#[test]
+	fn solves_quartics() {
+		// (x - 1)(x - 2)(x - 3)(x - 4)
+		assert_roots(solve_quartic(1., -10., 35., -50., 24.), &[1., 2., 3., 4.]);
+		// (x^2 - 4)(x^2 + 1)
+		assert_roots(solve_quartic(3., 0., -9., 0., -12.), &[-2., 2.]);
+		// x^4 + 1
+		assert_roots(solve_quartic(1., 0., 0., 0., 1.), &[]);
+		// x (x - 1)(x + 2)(x - 5), with a zero constant term
+		assert_roots(solve_quartic(1., -4., -7., 10., 0.), &[-2., 0., 1., 5.]);
+	}
+
+	#[test]
+	fn solves_torus_like_quartics() {
+		// a ray along x through a torus of radii 2 and 0.5 around y: (x^2 + 4 - 0.25)^2 = 16 x^2
+		let (major, minor): (f64, f64) = (2., 0.5);
+		let k = major * major - minor * minor;
+		let roots = solve_quartic(1., 0., 2. * k - 4. * major * major, 0., k * k);
+		assert_roots(roots, &[-2.5, -1.5, 1.5, 2.5]);
+	}
+}
@@ -1,35 +1,176 @@
 use std::ops::{Add,Sub,Mul};
-use vec4::*;
+use std::rc::Rc;
+use float::*;
+use vec3::*;
 use mat4::*;
+use textures::*;
 
 // ---------- Material ----------
 
-#[derive(Debug)]
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum Brdf {
+	Phong,	// Lambert plus Blinn-Phong highlight, as in the CS184 course
+	CookTorrance,	// GGX microfacets with Smith masking-shadowing and Schlick's Fresnel
+}
+
+#[derive(Debug,Clone)]
 pub struct Material {
 	pub ke: Color,
-	pub kd: Color,
+	pub kd: Color,	// also the base color of Cook-Torrance materials
 	pub ks: Color,
-	pub shininess: f64,
+	pub shininess: Float,
+	pub brdf: Brdf,
+	pub roughness: Float,	// Cook-Torrance only
+	pub metallic: Float,	// Cook-Torrance only
+	pub ke_texture: Option<Rc<dyn Texture>>,	// when set, replaces the constant color of the channel
+	pub kd_texture: Option<Rc<dyn Texture>>,
+	pub ks_texture: Option<Rc<dyn Texture>>,
+	pub bump_texture: Option<Rc<dyn Texture>>,	// height map, using the luminance of the texture
+	pub bump_strength: Float,
+	pub normal_texture: Option<Rc<dyn Texture>>,	// tangent-space normal map
+}
+
+impl Default for Material {
+	fn default() -> Material {
+		Material {
+			ke: Color::black(),
+			kd: Color::black(),
+			ks: Color::black(),
+			shininess: 0.,
+			brdf: Brdf::Phong,
+			roughness: 0.5,
+			metallic: 0.,
+			ke_texture: None,
+			kd_texture: None,
+			ks_texture: None,
+			bump_texture: None,
+			bump_strength: 1.,
+			normal_texture: None,
+		}
+	}
+}
+
+impl Material {
+	// point is the object-space position of the hit
+	pub fn get_emission_at(&self, uv: UV, point: Point3) -> Color {
+		Material::sample_channel(self.ke, &self.ke_texture, uv, point)
+	}
+
+	pub fn get_diffuse_at(&self, uv: UV, point: Point3) -> Color {
+		Material::sample_channel(self.kd, &self.kd_texture, uv, point)
+	}
+
+	pub fn get_specular_at(&self, uv: UV, point: Point3) -> Color {
+		Material::sample_channel(self.ks, &self.ks_texture, uv, point)
+	}
+
+	fn sample_channel(color: Color, texture: &Option<Rc<dyn Texture>>, uv: UV, point: Point3) -> Color {
+		match *texture {
+			Some(ref texture) => texture.get_color_at(uv, point),
+			None => color,
+		}
+	}
+}
+
+// ---------- UV ----------
+
+#[derive(Debug,Copy,Clone,Default)]
+pub struct UV {
+	pub u: Float,
+	pub v: Float,
 }
 
 // ---------- Ray ----------
 
-#[derive(Debug)]
+// relative size of the offset that moves the origin of secondary rays off the surface they leave, well above the
+// rounding errors of the hit points for each scalar type
+#[cfg(not(feature = "f32"))]
+static SELF_INTERSECTION_EPS: Float = 1e-7;
+#[cfg(feature = "f32")]
+static SELF_INTERSECTION_EPS: Float = 1e-5;
+
+// Only hits with t_min < t < t_max count, t being measured along the direction of the ray.
+#[derive(Debug,Copy,Clone)]
 pub struct Ray {
-	pub origin: Vec4,
-	pub direction: Vec4,
+	pub origin: Point3,
+	pub direction: Vector3,
+	pub t_min: Float,
+	pub t_max: Float,
+	pub time: Float,	// frame at which the ray sees the scene, when objects move while the shutter is open
 }
 
 impl Ray {
+	pub fn new(origin: Point3, direction: Vector3) -> Ray {
+		Ray {
+			origin: origin,
+			direction: direction,
+			t_min: 0.,
+			t_max: Float::INFINITY,
+			time: 0.,
+		}
+	}
+
+	// ray leaving a surface point, with its origin pushed off the surface along the geometric normal, to the side the
+	// ray goes to; the offset grows with the magnitude of the coordinates, as does their rounding error
+	pub fn from_surface(point: Point3, direction: Vector3, normal: Normal3) -> Ray {
+		let magnitude = point.x.abs().max(point.y.abs()).max(point.z.abs());
+		let offset = SELF_INTERSECTION_EPS * (1. + magnitude);
+		let origin = point + (if normal.dot(direction) >= 0. { offset } else { -offset }) * normal.to_vector();
+		Ray::new(origin, direction)
+	}
+
+	pub fn contains(&self, t: Float) -> bool {
+		t > self.t_min && t < self.t_max
+	}
+
+	// the direction stays normalized, so the range is scaled to keep referring to the same points
 	pub fn transformed(&self, transform: &Mat4) -> Ray {
-		let mut ray = Ray {
-			origin: *transform * self.origin,
-			direction: (*transform * self.direction).normalize(),
-		};
-		// TODO: make sure
-		ray.origin.w = 1.;
-		ray.direction.w = 0.;
-		ray
+		let direction = transform.transform_vector(self.direction);
+		let scale = direction.length();
+		Ray {
+			origin: transform.transform_point(self.origin),
+			direction: direction.normalize(),
+			t_min: self.t_min * scale,
+			t_max: self.t_max * scale,
+			time: self.time,
+		}
+	}
+}
+
+// ---------- RayPacket ----------
+
+// Rays with a common origin, such as the primary rays of neighbouring pixels, which are traced together.
+#[derive(Debug,Clone)]
+pub struct RayPacket {
+	pub origin: Point3,
+	pub rays: Vec<Ray>,
+}
+
+impl RayPacket {
+	pub fn new(origin: Point3, directions: &[Vector3]) -> RayPacket {
+		RayPacket {
+			origin: origin,
+			rays: directions.iter().map(|&direction| Ray::new(origin, direction)).collect(),
+		}
+	}
+
+	// like Ray::transformed for each ray, transforming the shared origin only once
+	pub fn transformed(&self, transform: &Mat4) -> RayPacket {
+		let origin = transform.transform_point(self.origin);
+		RayPacket {
+			origin: origin,
+			rays: self.rays.iter().map(|ray| {
+				let direction = transform.transform_vector(ray.direction);
+				let scale = direction.length();
+				Ray {
+					origin: origin,
+					direction: direction.normalize(),
+					t_min: ray.t_min * scale,
+					t_max: ray.t_max * scale,
+					time: ray.time,
+				}
+			}).collect(),
+		}
 	}
 }
 
@@ -37,15 +178,23 @@ impl Ray {
 
 #[derive(Debug,Copy,Clone,Default)]
 pub struct Color {
-	pub r: f64,
-	pub g: f64,
-	pub b: f64,
+	pub r: Float,
+	pub g: Float,
+	pub b: Float,
 }
 
 impl Color {
 	pub fn black() -> Color {
 		Color { r:0., g:0., b:0. }
 	}
+
+	pub fn white() -> Color {
+		Color { r:1., g:1., b:1. }
+	}
+
+	pub fn luminance(&self) -> Float {
+		0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+	}
 }
 
 impl Add for Color {
@@ -69,14 +218,14 @@ impl Mul for Color {
 	}
 }
 
-impl Mul<f64> for Color {
+impl Mul<Float> for Color {
 	type Output = Color;
-	fn mul(self, o: f64) -> Color {
+	fn mul(self, o: Float) -> Color {
 		Color { r: self.r * o, g: self.g * o, b: self.b * o }
 	}
 }
 
-impl Mul<Color> for f64 {
+impl Mul<Color> for Float {
 	type Output = Color;
 	fn mul(self, o: Color) -> Color {
 		Color { r: self * o.r, g: self * o.g, b: self * o.b }
@@ -87,9 +236,9 @@ impl Mul<Color> for f64 {
 
 #[derive(Debug,Clone)]
 pub struct Attenuation {
-	pub constant: f64,
-	pub linear: f64,
-	pub quadratic: f64,
+	pub constant: Float,
+	pub linear: Float,
+	pub quadratic: Float,
 }
 
 impl Default for Attenuation {
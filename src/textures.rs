@@ -0,0 +1,229 @@
+use std::fmt;
+use std::mem;
+use float::*;
+use structs::*;
+use vec3::*;
+use bmp::*;
+use noise::*;
+use scene_writer::*;
+
+// ---------- Texture ----------
+
+// Textures are evaluated with the UV coordinates and the object-space position of a hit.
+// Image textures use the former, while procedural (solid) textures use the latter, so they move with the object.
+pub trait Texture: fmt::Debug {
+	fn get_color_at(&self, uv: UV, point: Point3) -> Color;
+	// the arguments of the texture command that creates it, following the channel
+	fn get_scene_description(&self) -> Result<String, String> {
+		Err(format!("{:?} cannot be written to a scene file", self))
+	}
+	// bytes taken by the texture, including what it allocates
+	fn estimate_memory(&self) -> usize {
+		mem::size_of_val(self)
+	}
+}
+
+fn mix(color1: Color, color2: Color, t: Float) -> Color {
+	(1. - t) * color1 + t * color2
+}
+
+// ---------- ImageTexture ----------
+
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum WrapMode {
+	Wrap,	// repeats the image outside of [0,1]
+	Clamp,	// extends the border texels outside of [0,1]
+}
+
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum FilterMode {
+	Nearest,
+	Bilinear,
+}
+
+pub struct ImageTexture {
+	pub width: u32,
+	pub height: u32,
+	pub texels: Vec<Color>,	// row-major, first row is the top of the image
+	pub wrap: WrapMode,
+	pub filter: FilterMode,
+}
+
+impl fmt::Debug for ImageTexture {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "ImageTexture {{ width: {}, height: {}, wrap: {:?}, filter: {:?} }}", self.width, self.height, self.wrap, self.filter)
+	}
+}
+
+impl ImageTexture {
+	pub fn from_image(image: &BmpImage, wrap: WrapMode, filter: FilterMode) -> ImageTexture {
+		let mut texels = Vec::with_capacity((image.width * image.height) as usize);
+		for row in 0..image.height {
+			for col in 0..image.width {
+				let (r, g, b) = image.get_pixel(row, col);
+				texels.push(Color { r: r, g: g, b: b });
+			}
+		}
+
+		ImageTexture {
+			width: image.width,
+			height: image.height,
+			texels: texels,
+			wrap: wrap,
+			filter: filter,
+		}
+	}
+
+	fn get_texel(&self, x: i64, y: i64) -> Color {
+		let (x, y) = match self.wrap {
+			WrapMode::Wrap => (x.rem_euclid(self.width as i64), y.rem_euclid(self.height as i64)),
+			WrapMode::Clamp => (x.clamp(0, self.width as i64 - 1), y.clamp(0, self.height as i64 - 1)),
+		};
+		self.texels[(y * self.width as i64 + x) as usize]
+	}
+}
+
+impl Texture for ImageTexture {
+	fn get_color_at(&self, uv: UV, _point: Point3) -> Color {
+		// v goes up, image rows go down
+		let x = uv.u * self.width as Float;
+		let y = (1. - uv.v) * self.height as Float;
+
+		match self.filter {
+			FilterMode::Nearest => {
+				self.get_texel(x.floor() as i64, y.floor() as i64)
+			}
+			FilterMode::Bilinear => {
+				// texel centers are at half-integer coordinates
+				let x = x - 0.5;
+				let y = y - 0.5;
+				let x0 = x.floor();
+				let y0 = y.floor();
+				let fx = x - x0;
+				let fy = y - y0;
+				let (x0, y0) = (x0 as i64, y0 as i64);
+
+				let top    = (1. - fx) * self.get_texel(x0, y0)   + fx * self.get_texel(x0+1, y0);
+				let bottom = (1. - fx) * self.get_texel(x0, y0+1) + fx * self.get_texel(x0+1, y0+1);
+				(1. - fy) * top + fy * bottom
+			}
+		}
+	}
+
+	fn get_scene_description(&self) -> Result<String, String> {
+		Err(String::from("image textures cannot be written to scene files, as their file is not kept"))
+	}
+
+	fn estimate_memory(&self) -> usize {
+		mem::size_of_val(self) + self.texels.capacity() * mem::size_of::<Color>()
+	}
+}
+
+// ---------- CheckerTexture ----------
+
+#[derive(Debug)]
+pub struct CheckerTexture {
+	pub scale: Float,	// number of cells per unit
+	pub color1: Color,
+	pub color2: Color,
+}
+
+impl Texture for CheckerTexture {
+	fn get_color_at(&self, _uv: UV, point: Point3) -> Color {
+		// the small bias keeps surfaces lying exactly on a cell boundary (e.g. the plane z = 0) from flickering between cells
+		let cell = |x: Float| (x * self.scale + 1e-6).floor() as i64;
+		if (cell(point.x) + cell(point.y) + cell(point.z)).rem_euclid(2) == 0 {
+			self.color1
+		} else {
+			self.color2
+		}
+	}
+
+	fn get_scene_description(&self) -> Result<String, String> {
+		Ok(format!("checker {} {} {}", self.scale, format_color(self.color1), format_color(self.color2)))
+	}
+}
+
+// ---------- GradientTexture ----------
+
+#[derive(Debug)]
+pub struct GradientTexture {
+	pub axis: Vector3,	// color1 where point.axis <= 0, color2 where point.axis >= 1
+	pub color1: Color,
+	pub color2: Color,
+}
+
+impl Texture for GradientTexture {
+	fn get_color_at(&self, _uv: UV, point: Point3) -> Color {
+		mix(self.color1, self.color2, point.to_vector().dot(self.axis).clamp(0., 1.))
+	}
+
+	fn get_scene_description(&self) -> Result<String, String> {
+		Ok(format!("gradient {} {} {} {} {}", format_color(self.color1), format_color(self.color2), self.axis.x, self.axis.y, self.axis.z))
+	}
+}
+
+// ---------- NoiseTexture ----------
+
+#[derive(Debug)]
+pub struct NoiseTexture {
+	pub scale: Float,
+	pub octaves: u32,
+	pub color1: Color,
+	pub color2: Color,
+}
+
+impl Texture for NoiseTexture {
+	fn get_color_at(&self, _uv: UV, point: Point3) -> Color {
+		let value = 0.5 * (1. + fbm(point.scale(self.scale), self.octaves));
+		mix(self.color1, self.color2, value.clamp(0., 1.))
+	}
+
+	fn get_scene_description(&self) -> Result<String, String> {
+		Ok(format!("noise {} {} {} {}", self.scale, self.octaves, format_color(self.color1), format_color(self.color2)))
+	}
+}
+
+// ---------- MarbleTexture ----------
+
+#[derive(Debug)]
+pub struct MarbleTexture {
+	pub scale: Float,	// frequency of the veins along x
+	pub turbulence: Float,	// how much the veins are distorted
+	pub octaves: u32,
+	pub color1: Color,
+	pub color2: Color,
+}
+
+impl Texture for MarbleTexture {
+	fn get_color_at(&self, _uv: UV, point: Point3) -> Color {
+		let phase = self.scale * point.x + self.turbulence * turbulence(point.scale(self.scale), self.octaves);
+		mix(self.color1, self.color2, 0.5 * (1. + phase.sin()))
+	}
+
+	fn get_scene_description(&self) -> Result<String, String> {
+		Ok(format!("marble {} {} {} {} {}", self.scale, self.turbulence, self.octaves, format_color(self.color1), format_color(self.color2)))
+	}
+}
+
+// ---------- WoodTexture ----------
+
+#[derive(Debug)]
+pub struct WoodTexture {
+	pub rings: Float,	// number of rings per unit of distance from the y axis
+	pub turbulence: Float,	// how much the rings are distorted
+	pub color1: Color,
+	pub color2: Color,
+}
+
+impl Texture for WoodTexture {
+	fn get_color_at(&self, _uv: UV, point: Point3) -> Color {
+		let distance = (point.x * point.x + point.z * point.z).sqrt();
+		let ring = self.rings * distance + self.turbulence * perlin_noise(point);
+		mix(self.color1, self.color2, ring - ring.floor())
+	}
+
+	fn get_scene_description(&self) -> Result<String, String> {
+		Ok(format!("wood {} {} {} {}", self.rings, self.turbulence, format_color(self.color1), format_color(self.color2)))
+	}
+}
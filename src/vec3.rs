@@ -0,0 +1,159 @@
+use std::ops::{Add,Sub,Neg,Mul};
+use float::*;
+
+// Points, vectors and normals are separate types, so that only the operations that make sense between them compile:
+// points differ by vectors, vectors can be added and scaled, and normals (which transform differently) only take part
+// in dot products until they are explicitly turned into vectors.
+
+// ---------- Point3 ----------
+
+#[derive(Debug,Copy,Clone,Default,PartialEq)]
+pub struct Point3 {
+	pub x: Float,
+	pub y: Float,
+	pub z: Float,
+}
+// ------------------------------
+impl Add<Vector3> for Point3 {
+	type Output = Point3;
+	fn add(self, o: Vector3) -> Point3 {
+		Point3 { x: self.x + o.x, y: self.y + o.y, z: self.z + o.z }
+	}
+}
+// ------------------------------
+impl Sub<Vector3> for Point3 {
+	type Output = Point3;
+	fn sub(self, o: Vector3) -> Point3 {
+		Point3 { x: self.x - o.x, y: self.y - o.y, z: self.z - o.z }
+	}
+}
+// ------------------------------
+impl Sub for Point3 {
+	type Output = Vector3;
+	fn sub(self, o: Point3) -> Vector3 {
+		Vector3 { x: self.x - o.x, y: self.y - o.y, z: self.z - o.z }
+	}
+}
+// ------------------------------
+impl Point3 {
+	pub fn new(x: Float, y: Float, z: Float) -> Point3 {
+		Point3 { x: x, y: y, z: z }
+	}
+	pub fn origin() -> Point3 {
+		Point3 { x: 0., y: 0., z: 0. }
+	}
+	// as done by a uniform scale transform around the origin
+	pub fn scale(&self, factor: Float) -> Point3 {
+		Point3 { x: self.x * factor, y: self.y * factor, z: self.z * factor }
+	}
+	// the vector from the origin to the point
+	pub fn to_vector(self) -> Vector3 {
+		Vector3 { x: self.x, y: self.y, z: self.z }
+	}
+}
+
+// ---------- Vector3 ----------
+
+#[derive(Debug,Copy,Clone,Default,PartialEq)]
+pub struct Vector3 {
+	pub x: Float,
+	pub y: Float,
+	pub z: Float,
+}
+// ------------------------------
+impl Add for Vector3 {
+	type Output = Vector3;
+	fn add(self, o: Vector3) -> Vector3 {
+		Vector3 { x: self.x + o.x, y: self.y + o.y, z: self.z + o.z }
+	}
+}
+// ------------------------------
+impl Sub for Vector3 {
+	type Output = Vector3;
+	fn sub(self, o: Vector3) -> Vector3 {
+		Vector3 { x: self.x - o.x, y: self.y - o.y, z: self.z - o.z }
+	}
+}
+// ------------------------------
+impl Neg for Vector3 {
+	type Output = Vector3;
+	fn neg(self) -> Vector3 {
+		Vector3 { x: -self.x, y: -self.y, z: -self.z }
+	}
+}
+// ------------------------------
+impl Mul<Float> for Vector3 {
+	type Output = Vector3;
+	fn mul(self, o: Float) -> Vector3 {
+		Vector3 { x: self.x * o, y: self.y * o, z: self.z * o }
+	}
+}
+impl Mul<Vector3> for Float {
+	type Output = Vector3;
+	fn mul(self, o: Vector3) -> Vector3 {
+		Vector3 { x: self * o.x, y: self * o.y, z: self * o.z }
+	}
+}
+// ------------------------------
+impl Vector3 {
+	pub fn new(x: Float, y: Float, z: Float) -> Vector3 {
+		Vector3 { x: x, y: y, z: z }
+	}
+	pub fn zero() -> Vector3 {
+		Vector3 { x: 0., y: 0., z: 0. }
+	}
+	pub fn dot(&self, other: Vector3) -> Float {
+		self.x*other.x + self.y*other.y + self.z*other.z
+	}
+	pub fn cross(&self, other: Vector3) -> Vector3 {
+		Vector3 { x: self.y * other.z - self.z * other.y,
+				  y: self.z * other.x - self.x * other.z,
+				  z: self.x * other.y - self.y * other.x }
+	}
+	pub fn length(&self) -> Float {
+		self.length_sq().sqrt()
+	}
+	pub fn length_sq(&self) -> Float {
+		self.x*self.x + self.y*self.y + self.z*self.z
+	}
+	pub fn normalize(&self) -> Vector3 {
+		let len = self.length();
+		Vector3 { x: self.x / len, y: self.y / len, z: self.z / len }
+	}
+	// the normal of a surface whose tangent plane is perpendicular to this vector
+	pub fn to_normal(self) -> Normal3 {
+		Normal3 { x: self.x, y: self.y, z: self.z }
+	}
+}
+
+// ---------- Normal3 ----------
+
+#[derive(Debug,Copy,Clone,Default,PartialEq)]
+pub struct Normal3 {
+	pub x: Float,
+	pub y: Float,
+	pub z: Float,
+}
+// ------------------------------
+impl Neg for Normal3 {
+	type Output = Normal3;
+	fn neg(self) -> Normal3 {
+		Normal3 { x: -self.x, y: -self.y, z: -self.z }
+	}
+}
+// ------------------------------
+impl Normal3 {
+	pub fn new(x: Float, y: Float, z: Float) -> Normal3 {
+		Normal3 { x: x, y: y, z: z }
+	}
+	pub fn dot(&self, other: Vector3) -> Float {
+		self.x*other.x + self.y*other.y + self.z*other.z
+	}
+	pub fn normalize(&self) -> Normal3 {
+		self.to_vector().normalize().to_normal()
+	}
+	// the normal as a direction, e.g. to offset points along it or to build a frame around it
+	pub fn to_vector(self) -> Vector3 {
+		Vector3 { x: self.x, y: self.y, z: self.z }
+	}
+}
@@ -0,0 +1,33 @@
+#!/bin/sh
+# Compares the default (f64) build with the single precision one (--features f32) on a scene: renders it with both,
+# printing the statistics of each render (parse, build and render times, memory and intersection tests), then how the
+# two images differ. Run from the root of the repository:
+#
+#	tools/compare-f32.sh [scene file] [image written by the scene]
+#
+# The scene is scenes/scene6.test by default, and its image the one named by the output command of the scene file
+# (JSON scenes and animations need it given). The images are kept as <name>.f64.bmp and <name>.f32.bmp, along with
+# <name>.diff.bmp showing their differences.
+set -e
+
+scene=${1:-scenes/scene6.test}
+image=${2:-$(sed -n 's/^output[[:space:]]\{1,\}//p' "$scene" | tail -n 1)}
+if [ -z "$image" ]; then
+	echo "No output image found in $scene, give it after the scene file."
+	exit 2
+fi
+name=${image%.*}
+
+# separate target directories, so that each build is kept
+cargo build --release
+cargo build --release --features f32 --target-dir target/f32
+cargo build --release --example imagediff
+
+echo "---------- f64 ----------"
+target/release/raytracer --stats "$scene"
+mv "$image" "$name.f64.bmp"
+echo "---------- f32 ----------"
+target/f32/release/raytracer --stats "$scene"
+mv "$image" "$name.f32.bmp"
+echo "---------- differences ----------"
+target/release/examples/imagediff "$name.f64.bmp" "$name.f32.bmp" "$name.diff.bmp"